@@ -1,19 +1,35 @@
-use anchor_lang::prelude::{AccountInfo, Key, Pubkey};
+use anchor_lang::prelude::*;
 use light_sdk::{address::NewAddressParamsPacked, merkle_context::AddressMerkleContext};
+use crate::{ErrorCode, ADDRESS_MERKLE_TREE_ID};
 
+/// Derives the compressed address of a device from its marketplace and device ID
+/// in the pinned address tree. The tree rejects an address that already exists,
+/// so a device ID can only be registered once per marketplace while an owner may
+/// hold any number of devices.
 pub fn create_address(
-    signer: Pubkey,
+    marketplace: Pubkey,
+    device_id: &[u8; 32],
     remaining_accounts: &[AccountInfo],
     address_merkle_tree_account_index: u8,
     address_queue_account_index: u8,
     address_merkle_tree_root_index: u16,
-) -> (NewAddressParamsPacked, [u8; 32]) {
+) -> Result<(NewAddressParamsPacked, [u8; 32])> {
+    let account_key = |index: u8| {
+        remaining_accounts
+            .get(index as usize)
+            .map(|account| account.key())
+            .ok_or(ErrorCode::InvalidAccountIndex)
+    };
     let address_merkle_context = AddressMerkleContext {
-        address_merkle_tree_pubkey: remaining_accounts[address_merkle_tree_account_index as usize]
-            .key(),
-        address_queue_pubkey: remaining_accounts[address_queue_account_index as usize].key(),
+        address_merkle_tree_pubkey: account_key(address_merkle_tree_account_index)?,
+        address_queue_pubkey: account_key(address_queue_account_index)?,
     };
-    let seeds = [b"device", signer.as_ref()];
+    require_keys_eq!(
+        address_merkle_context.address_merkle_tree_pubkey,
+        ADDRESS_MERKLE_TREE_ID,
+        ErrorCode::InvalidAddressTree
+    );
+    let seeds = [b"device".as_slice(), marketplace.as_ref(), device_id.as_slice()];
 
     let address_seed = light_sdk::address::derive_address_seed(
         seeds.as_slice(),
@@ -27,5 +43,5 @@ pub fn create_address(
         address_merkle_tree_root_index,
         seed: address_seed,
     };
-    (address_params, address)
+    Ok((address_params, address))
 }
\ No newline at end of file
@@ -0,0 +1,93 @@
+use anchor_lang::prelude::*;
+use anchor_lang::solana_program::hash::hash;
+use anchor_lang::solana_program::instruction::{AccountMeta, Instruction};
+use anchor_lang::solana_program::program::invoke;
+use mpl_bubblegum::types::LeafSchema;
+use mpl_bubblegum::utils::get_asset_id;
+
+use crate::state::CompressedDeviceRegistry;
+use crate::{ErrorCode, SPL_ACCOUNT_COMPRESSION_ID};
+
+/// Bubblegum leaf of a device certificate. The tree's proof nodes follow the
+/// light accounts in `remaining_accounts`, starting at `proof_accounts_offset`.
+#[derive(Clone, Debug, AnchorSerialize, AnchorDeserialize)]
+pub struct CertificateProof {
+    pub root: [u8; 32],
+    pub nonce: u64,
+    pub delegate: Pubkey,
+    pub data_hash: [u8; 32],
+    pub creator_hash: [u8; 32],
+    pub proof_accounts_offset: u8,
+}
+
+/// Proves that `holder` owns the certificate NFT of `device_registry` via
+/// spl-account-compression `verify_leaf`.
+pub fn verify_certificate_holder<'info>(
+    certificate_tree: &AccountInfo<'info>,
+    remaining_accounts: &[AccountInfo<'info>],
+    device_registry: &CompressedDeviceRegistry,
+    holder: Pubkey,
+    proof: &CertificateProof,
+) -> Result<()> {
+    let certificate = get_asset_id(certificate_tree.key, proof.nonce);
+    require!(certificate == device_registry.certificate, ErrorCode::CertificateMismatch);
+
+    let leaf = LeafSchema::V1 {
+        id: certificate,
+        owner: holder,
+        delegate: proof.delegate,
+        nonce: proof.nonce,
+        data_hash: proof.data_hash,
+        creator_hash: proof.creator_hash,
+    }
+    .hash();
+    let proof_accounts = remaining_accounts
+        .get(proof.proof_accounts_offset as usize..)
+        .ok_or(ErrorCode::InvalidAccountIndex)?;
+    let mut data = hash(b"global:verify_leaf").to_bytes()[..8].to_vec();
+    data.extend_from_slice(&proof.root);
+    data.extend_from_slice(&leaf);
+    data.extend_from_slice(&(proof.nonce as u32).to_le_bytes());
+    let mut accounts = vec![AccountMeta::new_readonly(certificate_tree.key(), false)];
+    accounts.extend(
+        proof_accounts
+            .iter()
+            .map(|node| AccountMeta::new_readonly(node.key(), false)),
+    );
+    let mut account_infos = vec![certificate_tree.clone()];
+    account_infos.extend(proof_accounts.iter().cloned());
+    invoke(
+        &Instruction {
+            program_id: SPL_ACCOUNT_COMPRESSION_ID,
+            accounts,
+            data,
+        },
+        &account_infos,
+    )?;
+    Ok(())
+}
+
+/// A certified device follows its certificate NFT, so actions on it, and
+/// purchases of its listings, must prove that the recorded owner still holds
+/// the certificate. Once the certificate has been sold they fail until the new
+/// holder runs `claim_device`. Uncertified devices pass without a proof.
+pub fn verify_certificate_held<'info>(
+    certificate_tree: Option<&AccountInfo<'info>>,
+    remaining_accounts: &[AccountInfo<'info>],
+    device_registry: &CompressedDeviceRegistry,
+    proof: Option<&CertificateProof>,
+) -> Result<()> {
+    if device_registry.certificate == Pubkey::default() {
+        return Ok(());
+    }
+    match (certificate_tree, proof) {
+        (Some(certificate_tree), Some(proof)) => verify_certificate_holder(
+            certificate_tree,
+            remaining_accounts,
+            device_registry,
+            device_registry.owner,
+            proof,
+        ),
+        _ => err!(ErrorCode::CertificateProofRequired),
+    }
+}
@@ -3,8 +3,17 @@ use light_account_checks::discriminator::Discriminator;
 use light_hasher::{DataHasher, Poseidon};
 use light_sdk::compressed_account::{
     CompressedAccount, CompressedAccountData, OutputCompressedAccountWithPackedContext,
+    PackedCompressedAccountWithMerkleContext,
 };
+use light_sdk::merkle_context::PackedMerkleContext;
+use light_sdk::proof::CompressedProof;
+use light_sdk::traits::{
+    InvokeAccounts, InvokeCpiAccounts, InvokeCpiContextAccount, LightSystemAccount,
+    SignerAccounts,
+};
+use light_sdk::verify::{verify, InstructionDataInvokeCpi};
 use crate::state::CompressedDeviceRegistry;
+use crate::CPI_AUTHORITY_PDA_SEED;
 
 use anchor_lang::prelude::error_code;
 
@@ -19,8 +28,18 @@ pub enum ListingError {
     HashingError,
 }
 
+/// Current version of a compressed device together with the validity proof and
+/// Merkle context needed to consume it.
+#[derive(Clone, Debug, AnchorSerialize, AnchorDeserialize)]
+pub struct DeviceInput {
+    pub device_registry: CompressedDeviceRegistry,
+    pub proof: CompressedProof,
+    pub merkle_context: PackedMerkleContext,
+    pub merkle_tree_root_index: u16,
+    pub bump: u8,
+}
+
 pub fn create_output_account(
-    signer: Pubkey,
     merkle_tree_index: u8,
     device_registry: CompressedDeviceRegistry,
     address: [u8; 32],
@@ -42,3 +61,108 @@ pub fn create_output_account(
         merkle_tree_index,
     })
 }
+
+pub fn create_input_account(
+    device_registry: &CompressedDeviceRegistry,
+    address: [u8; 32],
+    merkle_context: PackedMerkleContext,
+    merkle_tree_root_index: u16,
+) -> Result<PackedCompressedAccountWithMerkleContext> {
+    let account_data = CompressedAccountData {
+        discriminator: CompressedDeviceRegistry::discriminator(),
+        data: device_registry.try_to_vec()?,
+        data_hash: device_registry
+            .hash::<Poseidon>()
+            .map_err(|_| ErrorCode::HashingError)?,
+    };
+    Ok(PackedCompressedAccountWithMerkleContext {
+        compressed_account: CompressedAccount {
+            owner: crate::ID,
+            lamports: 0,
+            address: Some(address),
+            data: Some(account_data),
+        },
+        merkle_context,
+        root_index: merkle_tree_root_index,
+        read_only: false,
+    })
+}
+
+/// Nullifies the proven device and writes `updated` under the same address.
+pub fn rewrite_device<'info, T>(
+    ctx: &Context<'_, '_, '_, 'info, T>,
+    device_address: [u8; 32],
+    device: DeviceInput,
+    updated: CompressedDeviceRegistry,
+) -> Result<()>
+where
+    T: InvokeAccounts<'info>
+        + LightSystemAccount<'info>
+        + InvokeCpiAccounts<'info>
+        + SignerAccounts<'info>
+        + InvokeCpiContextAccount<'info>
+        + Bumps,
+{
+    let merkle_tree_index = device.merkle_context.merkle_tree_pubkey_index;
+    let input = create_input_account(
+        &device.device_registry,
+        device_address,
+        device.merkle_context,
+        device.merkle_tree_root_index,
+    )?;
+    let output = create_output_account(merkle_tree_index, updated, device_address)?;
+    invoke_light_system(ctx, vec![input], vec![output], device.proof, device.bump)
+}
+
+/// Nullifies the proven device without writing it back. Its address stays in
+/// the address tree, so the device ID cannot be registered again.
+pub fn burn_device<'info, T>(
+    ctx: &Context<'_, '_, '_, 'info, T>,
+    device_address: [u8; 32],
+    device: DeviceInput,
+) -> Result<()>
+where
+    T: InvokeAccounts<'info>
+        + LightSystemAccount<'info>
+        + InvokeCpiAccounts<'info>
+        + SignerAccounts<'info>
+        + InvokeCpiContextAccount<'info>
+        + Bumps,
+{
+    let input = create_input_account(
+        &device.device_registry,
+        device_address,
+        device.merkle_context,
+        device.merkle_tree_root_index,
+    )?;
+    invoke_light_system(ctx, vec![input], Vec::new(), device.proof, device.bump)
+}
+
+fn invoke_light_system<'info, T>(
+    ctx: &Context<'_, '_, '_, 'info, T>,
+    inputs: Vec<PackedCompressedAccountWithMerkleContext>,
+    outputs: Vec<OutputCompressedAccountWithPackedContext>,
+    proof: CompressedProof,
+    bump: u8,
+) -> Result<()>
+where
+    T: InvokeAccounts<'info>
+        + LightSystemAccount<'info>
+        + InvokeCpiAccounts<'info>
+        + SignerAccounts<'info>
+        + InvokeCpiContextAccount<'info>
+        + Bumps,
+{
+    let inputs = InstructionDataInvokeCpi {
+        cpi_context: None,
+        is_compress: false,
+        compress_or_decompress_lamports: None,
+        new_address_params: Vec::new(),
+        relay_fee: None,
+        input_compressed_accounts_with_merkle_context: inputs,
+        output_compressed_accounts: outputs,
+        proof: Some(proof),
+    };
+    let signer_seeds = [CPI_AUTHORITY_PDA_SEED, &[bump]];
+    verify(ctx, &inputs, &[signer_seeds.as_slice()])
+}
@@ -0,0 +1,265 @@
+use anchor_lang::prelude::*;
+use anchor_lang::solana_program::{
+    ed25519_program,
+    instruction::Instruction,
+    sysvar::instructions::{load_current_index_checked, load_instruction_at_checked},
+};
+
+use crate::ErrorCode;
+
+// Layout of a single-signature Ed25519 precompile instruction
+const SIGNATURE_OFFSETS_START: usize = 2;
+const SIGNATURE_OFFSETS_LEN: usize = 14;
+const PUBKEY_LEN: usize = 32;
+const SIGNATURE_LEN: usize = 64;
+
+/// Checks that the instruction right before the current one is an Ed25519
+/// precompile call verifying `message` under `pubkey`. The precompile itself
+/// fails the transaction on a bad signature, so only its inputs are checked here.
+pub fn verify_ed25519_signature(
+    instructions_sysvar: &AccountInfo,
+    pubkey: &[u8; 32],
+    message: &[u8],
+) -> Result<()> {
+    verify_ed25519_signature_at(instructions_sysvar, 1, pubkey, message)
+}
+
+/// Same as `verify_ed25519_signature` for the instruction `offset` positions
+/// before the current one.
+pub fn verify_ed25519_signature_at(
+    instructions_sysvar: &AccountInfo,
+    offset: usize,
+    pubkey: &[u8; 32],
+    message: &[u8],
+) -> Result<()> {
+    let current_index = load_current_index_checked(instructions_sysvar)? as usize;
+    require!(offset > 0 && current_index >= offset, ErrorCode::MissingDeviceSignature);
+    let ix = load_instruction_at_checked(current_index - offset, instructions_sysvar)?;
+    check_ed25519_instruction(&ix, pubkey, message)
+}
+
+/// Checks that `ix` is a single-signature Ed25519 precompile call over
+/// `message` under `pubkey`, with all of its data inline.
+fn check_ed25519_instruction(ix: &Instruction, pubkey: &[u8; 32], message: &[u8]) -> Result<()> {
+    require!(ix.program_id == ed25519_program::ID, ErrorCode::MissingDeviceSignature);
+    require!(ix.accounts.is_empty(), ErrorCode::InvalidDeviceSignature);
+
+    let data = &ix.data;
+    require!(
+        data.len() >= SIGNATURE_OFFSETS_START + SIGNATURE_OFFSETS_LEN && data[0] == 1,
+        ErrorCode::InvalidDeviceSignature
+    );
+    let read_u16 = |at: usize| u16::from_le_bytes([data[at], data[at + 1]]);
+    let offsets = SIGNATURE_OFFSETS_START;
+    let signature_offset = read_u16(offsets) as usize;
+    let signature_instruction_index = read_u16(offsets + 2);
+    let public_key_offset = read_u16(offsets + 4) as usize;
+    let public_key_instruction_index = read_u16(offsets + 6);
+    let message_data_offset = read_u16(offsets + 8) as usize;
+    let message_data_size = read_u16(offsets + 10) as usize;
+    let message_instruction_index = read_u16(offsets + 12);
+
+    // All signature data must live inside the precompile instruction itself
+    require!(
+        signature_instruction_index == u16::MAX
+            && public_key_instruction_index == u16::MAX
+            && message_instruction_index == u16::MAX,
+        ErrorCode::InvalidDeviceSignature
+    );
+    require!(
+        data.len() >= signature_offset + SIGNATURE_LEN
+            && data.len() >= public_key_offset + PUBKEY_LEN
+            && data.len() >= message_data_offset + message_data_size,
+        ErrorCode::InvalidDeviceSignature
+    );
+    require!(
+        data[public_key_offset..public_key_offset + PUBKEY_LEN] == pubkey[..],
+        ErrorCode::InvalidDeviceSignature
+    );
+    require!(
+        data[message_data_offset..message_data_offset + message_data_size] == *message,
+        ErrorCode::InvalidDeviceSignature
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anchor_lang::solana_program::instruction::AccountMeta;
+    use anchor_lang::solana_program::sysvar::instructions::{
+        self as sysvar_instructions, construct_instructions_data, store_current_index,
+        BorrowedInstruction,
+    };
+
+    const PUBKEY: [u8; 32] = [7u8; 32];
+    const MESSAGE: &[u8] = b"device attestation";
+
+    // Precompile data as built by the Ed25519 program client: header, then
+    // public key, signature and message
+    fn precompile_data(pubkey: &[u8; 32], message: &[u8]) -> Vec<u8> {
+        let public_key_offset = SIGNATURE_OFFSETS_START + SIGNATURE_OFFSETS_LEN;
+        let signature_offset = public_key_offset + PUBKEY_LEN;
+        let message_data_offset = signature_offset + SIGNATURE_LEN;
+        let mut data = vec![1u8, 0];
+        for field in [
+            signature_offset as u16,
+            u16::MAX,
+            public_key_offset as u16,
+            u16::MAX,
+            message_data_offset as u16,
+            message.len() as u16,
+            u16::MAX,
+        ] {
+            data.extend_from_slice(&field.to_le_bytes());
+        }
+        data.extend_from_slice(pubkey);
+        data.extend_from_slice(&[0u8; SIGNATURE_LEN]);
+        data.extend_from_slice(message);
+        data
+    }
+
+    fn precompile_ix(data: Vec<u8>) -> Instruction {
+        Instruction {
+            program_id: ed25519_program::ID,
+            accounts: vec![],
+            data,
+        }
+    }
+
+    fn set_u16(data: &mut [u8], field: usize, value: u16) {
+        let at = SIGNATURE_OFFSETS_START + field * 2;
+        data[at..at + 2].copy_from_slice(&value.to_le_bytes());
+    }
+
+    #[test]
+    fn accepts_matching_key_and_message() {
+        let ix = precompile_ix(precompile_data(&PUBKEY, MESSAGE));
+        assert!(check_ed25519_instruction(&ix, &PUBKEY, MESSAGE).is_ok());
+    }
+
+    #[test]
+    fn rejects_other_program() {
+        let mut ix = precompile_ix(precompile_data(&PUBKEY, MESSAGE));
+        ix.program_id = Pubkey::new_unique();
+        assert_eq!(
+            check_ed25519_instruction(&ix, &PUBKEY, MESSAGE).unwrap_err(),
+            ErrorCode::MissingDeviceSignature.into()
+        );
+    }
+
+    #[test]
+    fn rejects_instruction_with_accounts() {
+        let mut ix = precompile_ix(precompile_data(&PUBKEY, MESSAGE));
+        ix.accounts.push(AccountMeta::new_readonly(Pubkey::new_unique(), false));
+        assert_eq!(
+            check_ed25519_instruction(&ix, &PUBKEY, MESSAGE).unwrap_err(),
+            ErrorCode::InvalidDeviceSignature.into()
+        );
+    }
+
+    #[test]
+    fn rejects_wrong_key_or_message() {
+        let ix = precompile_ix(precompile_data(&PUBKEY, MESSAGE));
+        assert_eq!(
+            check_ed25519_instruction(&ix, &[8u8; 32], MESSAGE).unwrap_err(),
+            ErrorCode::InvalidDeviceSignature.into()
+        );
+        assert_eq!(
+            check_ed25519_instruction(&ix, &PUBKEY, b"other message").unwrap_err(),
+            ErrorCode::InvalidDeviceSignature.into()
+        );
+    }
+
+    #[test]
+    fn rejects_multiple_signatures() {
+        let mut data = precompile_data(&PUBKEY, MESSAGE);
+        data[0] = 2;
+        assert_eq!(
+            check_ed25519_instruction(&precompile_ix(data), &PUBKEY, MESSAGE).unwrap_err(),
+            ErrorCode::InvalidDeviceSignature.into()
+        );
+    }
+
+    #[test]
+    fn rejects_truncated_header() {
+        let data = vec![1u8, 0, 0, 0];
+        assert_eq!(
+            check_ed25519_instruction(&precompile_ix(data), &PUBKEY, MESSAGE).unwrap_err(),
+            ErrorCode::InvalidDeviceSignature.into()
+        );
+    }
+
+    #[test]
+    fn rejects_data_in_other_instructions() {
+        // signature, public key and message instruction indexes
+        for field in [1, 3, 6] {
+            let mut data = precompile_data(&PUBKEY, MESSAGE);
+            set_u16(&mut data, field, 0);
+            assert_eq!(
+                check_ed25519_instruction(&precompile_ix(data), &PUBKEY, MESSAGE).unwrap_err(),
+                ErrorCode::InvalidDeviceSignature.into()
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_offsets_past_the_data() {
+        // signature offset, public key offset and message size
+        for field in [0, 2, 5] {
+            let mut data = precompile_data(&PUBKEY, MESSAGE);
+            let len = data.len() as u16;
+            set_u16(&mut data, field, len);
+            assert_eq!(
+                check_ed25519_instruction(&precompile_ix(data), &PUBKEY, MESSAGE).unwrap_err(),
+                ErrorCode::InvalidDeviceSignature.into()
+            );
+        }
+    }
+
+    fn borrowed<'a>(program_id: &'a Pubkey, data: &'a [u8]) -> BorrowedInstruction<'a> {
+        BorrowedInstruction {
+            program_id,
+            accounts: vec![],
+            data,
+        }
+    }
+
+    // Transaction of [Ed25519 precompile, other instruction, current instruction]
+    fn check_at(offset: usize) -> Result<()> {
+        let precompile = precompile_data(&PUBKEY, MESSAGE);
+        let program_id = crate::ID;
+        let mut data = construct_instructions_data(&[
+            borrowed(&ed25519_program::ID, precompile.as_slice()),
+            borrowed(&program_id, &[]),
+            borrowed(&program_id, &[]),
+        ]);
+        store_current_index(&mut data, 2);
+        let mut lamports = 0;
+        let sysvar_id = sysvar_instructions::ID;
+        let owner = Pubkey::default();
+        let sysvar = AccountInfo::new(
+            &sysvar_id,
+            false,
+            false,
+            &mut lamports,
+            &mut data,
+            &owner,
+            false,
+            0,
+        );
+        verify_ed25519_signature_at(&sysvar, offset, &PUBKEY, MESSAGE)
+    }
+
+    #[test]
+    fn finds_precompile_at_offset() {
+        assert!(check_at(2).is_ok());
+        assert_eq!(check_at(1).unwrap_err(), ErrorCode::MissingDeviceSignature.into());
+    }
+
+    #[test]
+    fn rejects_offsets_outside_the_transaction() {
+        assert_eq!(check_at(0).unwrap_err(), ErrorCode::MissingDeviceSignature.into());
+        assert_eq!(check_at(3).unwrap_err(), ErrorCode::MissingDeviceSignature.into());
+    }
+}
@@ -0,0 +1,28 @@
+use anchor_lang::prelude::*;
+use crate::state::Marketplace;
+use crate::ErrorCode;
+
+#[derive(Accounts)]
+pub struct AcceptAdmin<'info> {
+    pub new_admin: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"marketplace",
+            marketplace.creator.as_ref(),
+            &marketplace.marketplace_id.to_le_bytes(),
+        ],
+        bump = marketplace.bump,
+        constraint = marketplace.pending_admin == Some(new_admin.key()) @ ErrorCode::NotPendingAdmin,
+    )]
+    pub marketplace: Account<'info, Marketplace>,
+}
+
+pub fn handler(ctx: Context<AcceptAdmin>) -> Result<()> {
+    let marketplace = &mut ctx.accounts.marketplace;
+    marketplace.admin = ctx.accounts.new_admin.key();
+    marketplace.pending_admin = None;
+    msg!("Marketplace admin is now {}", marketplace.admin);
+    Ok(())
+}
@@ -0,0 +1,127 @@
+use anchor_lang::prelude::*;
+use anchor_lang::solana_program::clock::Clock;
+use anchor_spl::token_2022::spl_token_2022::{
+    self,
+    extension::{BaseStateWithExtensions, ExtensionType, StateWithExtensions},
+    state::Mint as MintState,
+};
+use anchor_spl::token_interface::{Mint, TokenAccount, TokenInterface};
+use crate::state::{AcceptedMint, Marketplace};
+use crate::ErrorCode;
+
+#[derive(Accounts)]
+pub struct AddAcceptedMint<'info> {
+    #[account(mut)]
+    pub admin: Signer<'info>,
+
+    #[account(
+        seeds = [
+            b"marketplace",
+            marketplace.creator.as_ref(),
+            &marketplace.marketplace_id.to_le_bytes(),
+        ],
+        bump = marketplace.bump,
+        has_one = admin @ ErrorCode::AdminMismatch,
+    )]
+    pub marketplace: Account<'info, Marketplace>,
+
+    /// SPL Token or Token-2022 mint.
+    #[account(mint::token_program = token_program)]
+    pub mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        init,
+        payer = admin,
+        seeds = [b"accepted_mint", marketplace.key().as_ref(), mint.key().as_ref()],
+        bump,
+        space = 8 + AcceptedMint::INIT_SPACE,
+    )]
+    pub accepted_mint: Account<'info, AcceptedMint>,
+
+    /// Per-mint fee treasury; kept when the mint is removed so its balance stays withdrawable.
+    #[account(
+        init_if_needed,
+        payer = admin,
+        seeds = [b"treasury", marketplace.key().as_ref(), mint.key().as_ref()],
+        bump,
+        token::mint = mint,
+        token::authority = treasury,
+        token::token_program = token_program,
+    )]
+    pub treasury: InterfaceAccount<'info, TokenAccount>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+/// Token-2022 extensions a payment mint may carry. Others, such as transfer hooks,
+/// confidential transfers or a permanent delegate, would change how settlement
+/// transfers behave and are rejected.
+const SUPPORTED_MINT_EXTENSIONS: [ExtensionType; 5] = [
+    ExtensionType::TransferFeeConfig,
+    ExtensionType::MintCloseAuthority,
+    ExtensionType::InterestBearingConfig,
+    ExtensionType::MetadataPointer,
+    ExtensionType::TokenMetadata,
+];
+
+/// Rejects Token-2022 mints carrying an extension settlement can't handle.
+/// SPL Token mints have no extensions and always pass.
+pub fn validate_payment_mint(mint: &AccountInfo) -> Result<()> {
+    if *mint.owner != spl_token_2022::ID {
+        return Ok(());
+    }
+    let data = mint.try_borrow_data()?;
+    let mint = StateWithExtensions::<MintState>::unpack(&data)?;
+    check_mint_extensions(&mint.get_extension_types()?)
+}
+
+fn check_mint_extensions(extensions: &[ExtensionType]) -> Result<()> {
+    require!(
+        extensions.iter().all(|extension| SUPPORTED_MINT_EXTENSIONS.contains(extension)),
+        ErrorCode::UnsupportedMintExtension
+    );
+    Ok(())
+}
+
+pub fn handler(ctx: Context<AddAcceptedMint>) -> Result<()> {
+    validate_payment_mint(&ctx.accounts.mint.to_account_info())?;
+    ctx.accounts.accepted_mint.set_inner(AcceptedMint {
+        marketplace: ctx.accounts.marketplace.key(),
+        mint: ctx.accounts.mint.key(),
+        treasury: ctx.accounts.treasury.key(),
+        treasury_bump: ctx.bumps.treasury,
+        added_at: Clock::get()?.unix_timestamp,
+        bump: ctx.bumps.accepted_mint,
+    });
+    msg!("Accepted mint: {}", ctx.accounts.mint.key());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_supported_extensions() {
+        assert!(check_mint_extensions(&[]).is_ok());
+        assert!(check_mint_extensions(&SUPPORTED_MINT_EXTENSIONS).is_ok());
+    }
+
+    #[test]
+    fn rejects_unsupported_extensions() {
+        for extension in [
+            ExtensionType::TransferHook,
+            ExtensionType::ConfidentialTransferMint,
+            ExtensionType::NonTransferable,
+            ExtensionType::PermanentDelegate,
+            ExtensionType::DefaultAccountState,
+        ] {
+            assert_eq!(
+                check_mint_extensions(&[ExtensionType::TransferFeeConfig, extension]).unwrap_err(),
+                ErrorCode::UnsupportedMintExtension.into()
+            );
+        }
+    }
+}
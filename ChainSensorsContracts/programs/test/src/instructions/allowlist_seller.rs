@@ -0,0 +1,44 @@
+use anchor_lang::prelude::*;
+use anchor_lang::solana_program::clock::Clock;
+use crate::state::{AllowlistEntry, Marketplace};
+use crate::ErrorCode;
+
+#[derive(Accounts)]
+#[instruction(seller: Pubkey)]
+pub struct AllowlistSeller<'info> {
+    #[account(mut)]
+    pub admin: Signer<'info>,
+
+    #[account(
+        seeds = [
+            b"marketplace",
+            marketplace.creator.as_ref(),
+            &marketplace.marketplace_id.to_le_bytes(),
+        ],
+        bump = marketplace.bump,
+        has_one = admin @ ErrorCode::AdminMismatch,
+    )]
+    pub marketplace: Account<'info, Marketplace>,
+
+    #[account(
+        init,
+        payer = admin,
+        seeds = [b"allowlist", marketplace.key().as_ref(), seller.as_ref()],
+        bump,
+        space = 8 + AllowlistEntry::INIT_SPACE,
+    )]
+    pub allowlist_entry: Account<'info, AllowlistEntry>,
+
+    pub system_program: Program<'info, System>,
+}
+
+pub fn handler(ctx: Context<AllowlistSeller>, seller: Pubkey) -> Result<()> {
+    ctx.accounts.allowlist_entry.set_inner(AllowlistEntry {
+        marketplace: ctx.accounts.marketplace.key(),
+        seller,
+        added_at: Clock::get()?.unix_timestamp,
+        bump: ctx.bumps.allowlist_entry,
+    });
+    msg!("Allowlisted seller: {}", seller);
+    Ok(())
+}
@@ -0,0 +1,46 @@
+use anchor_lang::prelude::*;
+use anchor_lang::solana_program::clock::Clock;
+use crate::state::{DeviceBan, Marketplace};
+use crate::ErrorCode;
+
+#[derive(Accounts)]
+#[instruction(device_address: [u8; 32])]
+pub struct BanDevice<'info> {
+    #[account(mut)]
+    pub admin: Signer<'info>,
+
+    #[account(
+        seeds = [
+            b"marketplace",
+            marketplace.creator.as_ref(),
+            &marketplace.marketplace_id.to_le_bytes(),
+        ],
+        bump = marketplace.bump,
+        has_one = admin @ ErrorCode::AdminMismatch,
+    )]
+    pub marketplace: Account<'info, Marketplace>,
+
+    #[account(
+        init,
+        payer = admin,
+        seeds = [b"ban", marketplace.key().as_ref(), device_address.as_ref()],
+        bump,
+        space = 8 + DeviceBan::INIT_SPACE,
+    )]
+    pub device_ban: Account<'info, DeviceBan>,
+
+    pub system_program: Program<'info, System>,
+}
+
+pub fn handler(ctx: Context<BanDevice>, device_address: [u8; 32], reason: u8) -> Result<()> {
+    let device = Pubkey::new_from_array(device_address);
+    ctx.accounts.device_ban.set_inner(DeviceBan {
+        marketplace: ctx.accounts.marketplace.key(),
+        device,
+        reason,
+        banned_at: Clock::get()?.unix_timestamp,
+        bump: ctx.bumps.device_ban,
+    });
+    msg!("Banned device {} (reason {})", device, reason);
+    Ok(())
+}
@@ -1,41 +1,74 @@
 use anchor_lang::prelude::*;
-use crate::state::ListingState;
-use crate::state::device_registry::DeviceRegistry;
+use light_sdk::light_system_accounts;
+use light_sdk_macros::LightTraits;
+use crate::certificate::{verify_certificate_held, CertificateProof};
+use crate::compressed_account_helpers::{rewrite_device, DeviceInput};
+use crate::state::{CompressedDeviceRegistry, ListingState};
+use crate::{ErrorCode, SPL_ACCOUNT_COMPRESSION_ID};
 use anchor_lang::solana_program::clock::Clock;
 
-#[derive(Accounts)]
-#[instruction(listing_id: [u8; 32])]
+#[light_system_accounts]
+#[derive(Accounts, LightTraits)]
+#[instruction(listing_id: String)]
 pub struct CancelListing<'info> {
     #[account(mut)]
+    #[fee_payer]
     pub seller: Signer<'info>,
+    /// CHECK: checked by cpi.
+    #[authority]
+    pub cpi_signer: AccountInfo<'info>,
+    #[self_program]
+    pub self_program: Program<'info, crate::program::Chainsensor>,
 
     #[account(
         mut,
-        seeds = [b"listing", device_registry.key().as_ref(), listing_id.as_ref()],
+        seeds = [b"listing", listing_state.device.as_ref(), listing_id.as_bytes()],
         bump = listing_state.bump,
-        constraint = listing_state.seller == seller.key() @ ErrorCode::Unauthorized,
-        constraint = listing_state.status == 0 @ ErrorCode::InvalidStatus, // Only active listings
+        constraint = listing_state.status == 0 @ ErrorCode::ListingNotActive, // Only active listings
     )]
     pub listing_state: Account<'info, ListingState>,
+    /// CHECK: certificate tree, required for certified devices; checked by verify_leaf.
+    pub certificate_tree: Option<UncheckedAccount<'info>>,
+    /// CHECK: address is checked.
+    #[account(address = SPL_ACCOUNT_COMPRESSION_ID)]
+    pub compression_program: Option<UncheckedAccount<'info>>,
+}
 
-    // Required since it's part of the PDA seeds
-    pub device_registry: Account<'info, DeviceRegistry>,
+pub fn handler<'info>(
+    ctx: Context<'_, '_, '_, 'info, CancelListing<'info>>,
+    listing_id: String,
+    device: DeviceInput,
+    certificate_proof: Option<CertificateProof>,
+) -> Result<()> {
+    // The current device owner or its operator may cancel, including listings left
+    // by a previous owner before `claim_device`; the record is proven below
+    require!(
+        device.device_registry.is_owner_or_operator(&ctx.accounts.seller.key()),
+        ErrorCode::CancelUnauthorized
+    );
+    verify_certificate_held(
+        ctx.accounts.certificate_tree.as_deref(),
+        ctx.remaining_accounts,
+        &device.device_registry,
+        certificate_proof.as_ref(),
+    )?;
 
-    pub system_program: Program<'info, System>,
-}
+    // Release the listing slot on the device
+    let device_address = ctx.accounts.listing_state.device.to_bytes();
+    let active_listings = device
+        .device_registry
+        .active_listings
+        .checked_sub(1)
+        .ok_or(ErrorCode::MathOverflow)?;
+    let updated_registry = CompressedDeviceRegistry {
+        active_listings,
+        ..device.device_registry.clone()
+    };
+    rewrite_device(&ctx, device_address, device, updated_registry)?;
 
-pub fn handler(ctx: Context<CancelListing>, listing_id: [u8; 32]) -> Result<()> {
     let listing = &mut ctx.accounts.listing_state;
     listing.status = 2; // Cancelled
     listing.updated_at = Clock::get()?.unix_timestamp;
-    msg!("Cancelled listing: {}", String::from_utf8_lossy(&listing_id));
+    msg!("Cancelled listing: {}", listing_id);
     Ok(())
 }
-
-#[error_code]
-pub enum ErrorCode {
-    #[msg("Only the seller can cancel the listing")]
-    Unauthorized,
-    #[msg("Listing is not active")]
-    InvalidStatus,
-}
\ No newline at end of file
@@ -0,0 +1,79 @@
+use anchor_lang::prelude::*;
+use anchor_lang::solana_program::clock::Clock;
+use light_sdk::light_system_accounts;
+use light_sdk_macros::LightTraits;
+use crate::certificate::{verify_certificate_holder, CertificateProof};
+use crate::compressed_account_helpers::{rewrite_device, DeviceInput};
+use crate::state::{CompressedDeviceRegistry, DeviceHeartbeat, DeviceOperator};
+use crate::{DeviceTransferred, ErrorCode, SPL_ACCOUNT_COMPRESSION_ID};
+
+#[light_system_accounts]
+#[derive(Accounts, LightTraits)]
+#[instruction(device_address: [u8; 32])]
+pub struct ClaimDevice<'info> {
+    #[account(mut)]
+    #[fee_payer]
+    pub holder: Signer<'info>,
+    /// CHECK: checked by cpi.
+    #[authority]
+    pub cpi_signer: AccountInfo<'info>,
+    #[self_program]
+    pub self_program: Program<'info, crate::program::Chainsensor>,
+    /// CHECK: bound to the certificate through the asset ID and checked by verify_leaf.
+    pub certificate_tree: AccountInfo<'info>,
+    /// CHECK: address is checked.
+    #[account(address = SPL_ACCOUNT_COMPRESSION_ID)]
+    pub compression_program: AccountInfo<'info>,
+    /// CHECK: heartbeat PDA; its cached key and operator are refreshed if it exists.
+    #[account(mut, seeds = [b"heartbeat", device_address.as_ref()], bump)]
+    pub heartbeat: UncheckedAccount<'info>,
+}
+
+/// Moves a certified device to the current holder of its certificate NFT.
+pub fn handler<'info>(
+    ctx: Context<'_, '_, '_, 'info, ClaimDevice<'info>>,
+    device_address: [u8; 32],
+    device: DeviceInput,
+    certificate_proof: CertificateProof,
+) -> Result<()> {
+    let holder = ctx.accounts.holder.key();
+    let device_registry = &device.device_registry;
+    let previous_owner = device_registry.owner;
+    require!(
+        device_registry.certificate != Pubkey::default(),
+        ErrorCode::CertificateNotMinted
+    );
+    require!(holder != previous_owner, ErrorCode::InvalidNewOwner);
+    // Unlike `transfer_device`, open listings don't block the claim: the previous owner
+    // could hold them open forever. They stop being purchasable and the new owner can
+    // cancel them.
+    verify_certificate_holder(
+        &ctx.accounts.certificate_tree,
+        ctx.remaining_accounts,
+        device_registry,
+        holder,
+        &certificate_proof,
+    )?;
+
+    let device_id = device_registry.device_id;
+    let marketplace = device_registry.marketplace;
+    let ek_pubkey_hash = device_registry.ek_pubkey_hash;
+    let claimed_registry = CompressedDeviceRegistry {
+        owner: holder,
+        operator: DeviceOperator::default(),
+        ..device_registry.clone()
+    };
+    rewrite_device(&ctx, device_address, device, claimed_registry)?;
+    // The new owner starts without an operator
+    DeviceHeartbeat::refresh(&ctx.accounts.heartbeat, ek_pubkey_hash, Pubkey::default())?;
+
+    emit!(DeviceTransferred {
+        device_address,
+        device_id,
+        marketplace,
+        previous_owner,
+        new_owner: holder,
+        timestamp: Clock::get()?.unix_timestamp,
+    });
+    Ok(())
+}
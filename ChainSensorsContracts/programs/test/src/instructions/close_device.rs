@@ -0,0 +1,54 @@
+use anchor_lang::prelude::*;
+use light_sdk::light_system_accounts;
+use light_sdk_macros::LightTraits;
+use crate::certificate::{verify_certificate_held, CertificateProof};
+use crate::compressed_account_helpers::{burn_device, DeviceInput};
+use crate::state::DeviceHeartbeat;
+use crate::{ErrorCode, SPL_ACCOUNT_COMPRESSION_ID};
+
+#[light_system_accounts]
+#[derive(Accounts, LightTraits)]
+#[instruction(device_address: [u8; 32])]
+pub struct CloseDevice<'info> {
+    #[account(mut)]
+    #[fee_payer]
+    pub owner: Signer<'info>,
+    /// CHECK: checked by cpi.
+    #[authority]
+    pub cpi_signer: AccountInfo<'info>,
+    #[self_program]
+    pub self_program: Program<'info, crate::program::Chainsensor>,
+    /// CHECK: heartbeat PDA; its cached key and operator are refreshed if it exists.
+    #[account(mut, seeds = [b"heartbeat", device_address.as_ref()], bump)]
+    pub heartbeat: UncheckedAccount<'info>,
+    /// CHECK: certificate tree, required for certified devices; checked by verify_leaf.
+    pub certificate_tree: Option<UncheckedAccount<'info>>,
+    /// CHECK: address is checked.
+    #[account(address = SPL_ACCOUNT_COMPRESSION_ID)]
+    pub compression_program: Option<UncheckedAccount<'info>>,
+}
+
+pub fn handler<'info>(
+    ctx: Context<'_, '_, '_, 'info, CloseDevice<'info>>,
+    device_address: [u8; 32],
+    device: DeviceInput,
+    certificate_proof: Option<CertificateProof>,
+) -> Result<()> {
+    let device_registry = &device.device_registry;
+    require!(device_registry.owner == ctx.accounts.owner.key(), ErrorCode::DeviceOwnerMismatch);
+    require!(device_registry.active_listings == 0, ErrorCode::DeviceHasActiveListings);
+    verify_certificate_held(
+        ctx.accounts.certificate_tree.as_deref(),
+        ctx.remaining_accounts,
+        device_registry,
+        certificate_proof.as_ref(),
+    )?;
+
+    // Burn the record; its address stays taken
+    burn_device(&ctx, device_address, device)?;
+    // Revoke the cached key and operator so the closed device can't post heartbeats
+    DeviceHeartbeat::refresh(&ctx.accounts.heartbeat, [0u8; 32], Pubkey::default())?;
+
+    msg!("Closed device: {}", Pubkey::new_from_array(device_address));
+    Ok(())
+}
@@ -0,0 +1,66 @@
+use anchor_lang::prelude::*;
+use anchor_lang::solana_program::clock::Clock;
+use crate::state::{DataBatch, DeviceHeartbeat};
+use crate::ErrorCode;
+
+#[derive(Accounts)]
+#[instruction(merkle_root: [u8; 32])]
+pub struct CommitDataBatch<'info> {
+    /// The device key itself or the device operator.
+    #[account(mut)]
+    pub signer: Signer<'info>,
+
+    // Holds the device key hash and operator cached from the compressed record
+    #[account(
+        seeds = [b"heartbeat", heartbeat.device.as_ref()],
+        bump = heartbeat.bump,
+    )]
+    pub heartbeat: Account<'info, DeviceHeartbeat>,
+
+    #[account(
+        init,
+        payer = signer,
+        seeds = [b"data_batch", heartbeat.device.as_ref(), merkle_root.as_ref()],
+        bump,
+        space = 8 + DataBatch::INIT_SPACE,
+    )]
+    pub data_batch: Account<'info, DataBatch>,
+
+    pub system_program: Program<'info, System>,
+}
+
+pub fn handler(
+    ctx: Context<CommitDataBatch>,
+    merkle_root: [u8; 32],
+    blob_id: String,
+    window_start: i64,
+    window_end: i64,
+    reading_count: u64,
+) -> Result<()> {
+    let clock = Clock::get()?;
+    let signer = ctx.accounts.signer.key();
+    let heartbeat = &ctx.accounts.heartbeat;
+    require!(heartbeat.is_device_or_operator(&signer), ErrorCode::HeartbeatUnauthorized);
+
+    require!(!merkle_root.iter().all(|&x| x == 0), ErrorCode::DataBatchRootEmpty);
+    require!(!blob_id.is_empty() && blob_id.len() <= 64, ErrorCode::InvalidBlobId);
+    require!(
+        window_start <= window_end && window_end <= clock.unix_timestamp,
+        ErrorCode::InvalidDataWindow
+    );
+    require!(reading_count > 0, ErrorCode::InvalidReadingCount);
+
+    ctx.accounts.data_batch.set_inner(DataBatch {
+        device: heartbeat.device,
+        merkle_root,
+        blob_id,
+        window_start,
+        window_end,
+        reading_count,
+        submitted_by: signer,
+        created_at: clock.unix_timestamp,
+        bump: ctx.bumps.data_batch,
+    });
+    msg!("Committed data batch of {} readings", reading_count);
+    Ok(())
+}
@@ -1,35 +1,54 @@
 use anchor_lang::prelude::*;
-use crate::state::{Marketplace, DeviceRegistry};
-use crate::state::listing::ListingState;
 use anchor_lang::solana_program::clock::Clock;
+use light_sdk::light_system_accounts;
+use light_sdk_macros::LightTraits;
+use crate::certificate::{verify_certificate_held, CertificateProof};
+use crate::compressed_account_helpers::{rewrite_device, DeviceInput};
+use crate::state::{
+    AcceptedMint, CompressedDeviceRegistry, DataBatch, ListingState, Marketplace,
+    PAUSE_LISTING, SETTLEMENT_SOL, SETTLEMENT_TOKEN,
+};
+use crate::{ErrorCode, SPL_ACCOUNT_COMPRESSION_ID};
 
-#[derive(Accounts)]
-#[instruction(listing_id: String)]
+#[light_system_accounts]
+#[derive(Accounts, LightTraits)]
+#[instruction(listing_id: String, device_address: [u8; 32])]
 pub struct CreateListing<'info> {
     #[account(mut)]
+    #[fee_payer]
     pub seller: Signer<'info>,
+    /// CHECK: checked by cpi.
+    #[authority]
+    pub cpi_signer: AccountInfo<'info>,
+    #[self_program]
+    pub self_program: Program<'info, crate::program::Chainsensor>,
 
     #[account(
-        seeds = [b"marketplace", marketplace.admin.as_ref()],
+        seeds = [
+            b"marketplace",
+            marketplace.creator.as_ref(),
+            &marketplace.marketplace_id.to_le_bytes(),
+        ],
         bump = marketplace.bump,
         constraint = marketplace.is_active @ ErrorCode::MarketplaceInactive,
+        constraint = !marketplace.is_paused(PAUSE_LISTING) @ ErrorCode::ListingPaused,
     )]
     pub marketplace: Account<'info, Marketplace>,
 
+    /// Settlement mint for token listings; must be accepted by the marketplace.
+    /// Omitted for listings priced and settled in lamports.
     #[account(
-        seeds = [b"device", marketplace.key().as_ref(), device_registry.device_id.as_bytes()],
-        bump = device_registry.bump,
-        constraint = device_registry.owner == seller.key() @ ErrorCode::Unauthorized,
-        constraint = device_registry.is_active @ ErrorCode::DeviceInactive,
+        seeds = [b"accepted_mint", marketplace.key().as_ref(), accepted_mint.mint.as_ref()],
+        bump = accepted_mint.bump,
     )]
-    pub device_registry: Account<'info, DeviceRegistry>,
+    pub accepted_mint: Option<Account<'info, AcceptedMint>>,
 
     #[account(
         init,
         payer = seller,
         seeds = [
           b"listing",
-          device_registry.key().as_ref(),
+          device_address.as_ref(),
           listing_id.as_bytes()
         ],
         bump,
@@ -37,33 +56,109 @@ pub struct CreateListing<'info> {
     )]
     pub listing_state: Account<'info, ListingState>,
 
-    pub system_program: Program<'info, System>,
+    /// Optional committed batch backing this listing's data.
+    #[account(
+        seeds = [b"data_batch", device_address.as_ref(), data_batch.merkle_root.as_ref()],
+        bump = data_batch.bump,
+    )]
+    pub data_batch: Option<Account<'info, DataBatch>>,
+
+    /// CHECK: ban PDA for this device; must not exist.
+    #[account(
+        seeds = [b"ban", marketplace.key().as_ref(), device_address.as_ref()],
+        bump,
+        constraint = device_ban.data_is_empty() @ ErrorCode::DeviceBanned,
+    )]
+    pub device_ban: UncheckedAccount<'info>,
+
     pub rent:            Sysvar<'info, Rent>,
+    /// CHECK: certificate tree, required for certified devices; checked by verify_leaf.
+    pub certificate_tree: Option<UncheckedAccount<'info>>,
+    /// CHECK: address is checked.
+    #[account(address = SPL_ACCOUNT_COMPRESSION_ID)]
+    pub compression_program: Option<UncheckedAccount<'info>>,
 }
 
-pub fn handler(
-    ctx: Context<CreateListing>,
+pub fn handler<'info>(
+    ctx: Context<'_, '_, '_, 'info, CreateListing<'info>>,
     listing_id: String,
+    device_address: [u8; 32],
     data_cid:   String,
     price_per_unit: u64,
     device_id:  String,
     total_data_units: u64,
     expires_at: Option<i64>,
+    settlement_mode: u8,
+    device: DeviceInput,
+    certificate_proof: Option<CertificateProof>,
 ) -> Result<()> {
-    let l = &mut ctx.accounts.listing_state;
     let clock = Clock::get()?;
 
     // Input validation
     require!(!listing_id.is_empty(), ErrorCode::ListingIdEmpty);
-    require!(!device_id.is_empty(),  ErrorCode::DeviceIdEmpty);
+    require!(!device_id.is_empty(),  ErrorCode::DeviceIdEmptyListing);
     require!(!data_cid.is_empty(),   ErrorCode::DataCidEmpty);
     require!(price_per_unit > 0,     ErrorCode::InvalidPrice);
     require!(total_data_units > 0,   ErrorCode::InvalidDataUnits);
 
-    // Initialize
-    l.seller           = ctx.accounts.seller.key();
+    // The accepted mint is passed exactly when the listing settles in tokens
+    let token_mint = match (settlement_mode, &ctx.accounts.accepted_mint) {
+        (SETTLEMENT_TOKEN, Some(accepted_mint)) => accepted_mint.mint,
+        (SETTLEMENT_TOKEN, None) => return err!(ErrorCode::MissingTokenAccounts),
+        (SETTLEMENT_SOL, None) => Pubkey::default(),
+        (SETTLEMENT_SOL, Some(_)) => return err!(ErrorCode::UnexpectedAcceptedMint),
+        _ => return err!(ErrorCode::InvalidSettlementMode),
+    };
+
+    // The compressed device must belong to the seller (or be run by its
+    // operator, within the owner's price floor) on this marketplace
+    let device_registry = &device.device_registry;
+    let signer = ctx.accounts.seller.key();
+    require!(device_registry.is_owner_or_operator(&signer), ErrorCode::NotOwnerOrOperator);
+    if signer != device_registry.owner {
+        require!(
+            price_per_unit >= device_registry.operator.price_floor,
+            ErrorCode::PriceBelowOperatorFloor
+        );
+    }
+    require!(
+        device_registry.marketplace == ctx.accounts.marketplace.key(),
+        ErrorCode::DeviceMarketplaceMismatch
+    );
+    require!(
+        device_id_matches(&device_id, &device_registry.device_id),
+        ErrorCode::DeviceIdMismatch
+    );
+    require!(device_registry.is_active, ErrorCode::DeviceInactive);
+    if let Some(batch) = &ctx.accounts.data_batch {
+        require!(batch.blob_id == data_cid, ErrorCode::DataBatchCidMismatch);
+    }
+    verify_certificate_held(
+        ctx.accounts.certificate_tree.as_deref(),
+        ctx.remaining_accounts,
+        device_registry,
+        certificate_proof.as_ref(),
+    )?;
+
+    // Prove the device exists by consuming it and writing it back with the listing counted
+    let device_owner = device_registry.owner;
+    let data_type = device_registry.metadata.data_type;
+    let data_batch = ctx.accounts.data_batch.as_ref().map(|batch| batch.key());
+    let active_listings = device_registry
+        .active_listings
+        .checked_add(1)
+        .ok_or(ErrorCode::MathOverflow)?;
+    let updated_registry = CompressedDeviceRegistry {
+        active_listings,
+        ..device_registry.clone()
+    };
+    rewrite_device(&ctx, device_address, device, updated_registry)?;
+
+    // Initialize; proceeds always go to the device owner
+    let l = &mut ctx.accounts.listing_state;
+    l.seller           = device_owner;
     l.marketplace      = ctx.accounts.marketplace.key();
-    l.device           = ctx.accounts.device_registry.key();
+    l.device           = Pubkey::new_from_array(device_address);
     l.device_id        = device_id.clone();
     l.listing_id       = listing_id.clone();
     l.data_cid         = data_cid;
@@ -71,7 +166,7 @@ pub fn handler(
     l.status           = 0;
     l.total_data_units = total_data_units;
     l.remaining_units  = total_data_units;
-    l.token_mint       = ctx.accounts.marketplace.token_mint;
+    l.token_mint       = token_mint;
     l.created_at       = clock.unix_timestamp;
     l.updated_at       = clock.unix_timestamp;
     l.expires_at       = expires_at;
@@ -79,6 +174,9 @@ pub fn handler(
     l.buyer            = None;
     l.purchase_count   = 0;
     l.sold_at          = None;
+    l.data_batch       = data_batch;
+    l.settlement_mode  = settlement_mode;
+    l.data_type        = data_type;
 
     msg!(
         "Listing created: {} for device: {}",
@@ -88,22 +186,35 @@ pub fn handler(
     Ok(())
 }
 
-#[error_code]
-pub enum ErrorCode {
-    #[msg("Marketplace is not active")]
-    MarketplaceInactive,
-    #[msg("Unauthorized: Seller does not own the device")]
-    Unauthorized,
-    #[msg("Device is not active")]
-    DeviceInactive,
-    #[msg("Listing ID cannot be empty")]
-    ListingIdEmpty,
-    #[msg("Device ID cannot be empty")]
-    DeviceIdEmpty,
-    #[msg("Data CID cannot be empty")]
-    DataCidEmpty,
-    #[msg("Price per unit must be greater than zero")]
-    InvalidPrice,
-    #[msg("Total data units must be greater than zero")]
-    InvalidDataUnits,
-}
\ No newline at end of file
+/// Compares a listing's device ID with the zero-padded ID stored on the device.
+fn device_id_matches(device_id: &str, stored: &[u8; 32]) -> bool {
+    let bytes = device_id.as_bytes();
+    bytes.len() <= stored.len()
+        && stored[..bytes.len()] == *bytes
+        && stored[bytes.len()..].iter().all(|&b| b == 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stored(id: &[u8]) -> [u8; 32] {
+        let mut stored = [0u8; 32];
+        stored[..id.len()].copy_from_slice(id);
+        stored
+    }
+
+    #[test]
+    fn matches_zero_padded_id() {
+        assert!(device_id_matches("sensor-1", &stored(b"sensor-1")));
+        assert!(device_id_matches(&"x".repeat(32), &[b'x'; 32]));
+    }
+
+    #[test]
+    fn rejects_prefix_and_longer_ids() {
+        assert!(!device_id_matches("sensor", &stored(b"sensor-1")));
+        assert!(!device_id_matches("sensor-10", &stored(b"sensor-1")));
+        assert!(!device_id_matches("sensor-2", &stored(b"sensor-1")));
+        assert!(!device_id_matches(&"x".repeat(33), &[b'x'; 32]));
+    }
+}
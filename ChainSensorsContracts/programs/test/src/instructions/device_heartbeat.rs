@@ -0,0 +1,29 @@
+use anchor_lang::prelude::*;
+use anchor_lang::solana_program::clock::Clock;
+use crate::state::DeviceHeartbeat;
+use crate::ErrorCode;
+
+#[derive(Accounts)]
+pub struct RecordHeartbeat<'info> {
+    /// The device key itself or the device operator.
+    pub signer: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"heartbeat", heartbeat.device.as_ref()],
+        bump = heartbeat.bump,
+    )]
+    pub heartbeat: Account<'info, DeviceHeartbeat>,
+}
+
+pub fn handler(ctx: Context<RecordHeartbeat>) -> Result<()> {
+    let signer = ctx.accounts.signer.key();
+    let heartbeat = &mut ctx.accounts.heartbeat;
+    require!(heartbeat.is_device_or_operator(&signer), ErrorCode::HeartbeatUnauthorized);
+
+    let clock = Clock::get()?;
+    heartbeat.last_seen_slot = clock.slot;
+    heartbeat.last_seen_at = clock.unix_timestamp;
+    heartbeat.heartbeat_count = heartbeat.heartbeat_count.saturating_add(1);
+    Ok(())
+}
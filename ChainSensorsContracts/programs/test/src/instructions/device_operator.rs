@@ -0,0 +1,67 @@
+use anchor_lang::prelude::*;
+use light_sdk::light_system_accounts;
+use light_sdk_macros::LightTraits;
+use crate::certificate::{verify_certificate_held, CertificateProof};
+use crate::compressed_account_helpers::{rewrite_device, DeviceInput};
+use crate::state::{CompressedDeviceRegistry, DeviceHeartbeat, DeviceOperator};
+use crate::{ErrorCode, SPL_ACCOUNT_COMPRESSION_ID};
+
+/// Shared by `set_device_operator` and `clear_device_operator`.
+#[light_system_accounts]
+#[derive(Accounts, LightTraits)]
+#[instruction(device_address: [u8; 32])]
+pub struct SetDeviceOperator<'info> {
+    #[account(mut)]
+    #[fee_payer]
+    pub owner: Signer<'info>,
+    /// CHECK: checked by cpi.
+    #[authority]
+    pub cpi_signer: AccountInfo<'info>,
+    #[self_program]
+    pub self_program: Program<'info, crate::program::Chainsensor>,
+    /// CHECK: heartbeat PDA; its cached key and operator are refreshed if it exists.
+    #[account(mut, seeds = [b"heartbeat", device_address.as_ref()], bump)]
+    pub heartbeat: UncheckedAccount<'info>,
+    /// CHECK: certificate tree, required for certified devices; checked by verify_leaf.
+    pub certificate_tree: Option<UncheckedAccount<'info>>,
+    /// CHECK: address is checked.
+    #[account(address = SPL_ACCOUNT_COMPRESSION_ID)]
+    pub compression_program: Option<UncheckedAccount<'info>>,
+}
+
+/// Sets the device operator, or clears it when `operator` is `None`.
+pub fn handler<'info>(
+    ctx: Context<'_, '_, '_, 'info, SetDeviceOperator<'info>>,
+    device_address: [u8; 32],
+    device: DeviceInput,
+    operator: Option<DeviceOperator>,
+    certificate_proof: Option<CertificateProof>,
+) -> Result<()> {
+    let device_registry = &device.device_registry;
+    require!(device_registry.owner == ctx.accounts.owner.key(), ErrorCode::DeviceOwnerMismatch);
+    if let Some(operator) = &operator {
+        require!(
+            operator.key != Pubkey::default() && operator.key != device_registry.owner,
+            ErrorCode::InvalidOperator
+        );
+    }
+    verify_certificate_held(
+        ctx.accounts.certificate_tree.as_deref(),
+        ctx.remaining_accounts,
+        device_registry,
+        certificate_proof.as_ref(),
+    )?;
+
+    let ek_pubkey_hash = device_registry.ek_pubkey_hash;
+    let operator = operator.unwrap_or_default();
+    let operator_key = operator.key;
+    let updated_registry = CompressedDeviceRegistry {
+        operator,
+        ..device_registry.clone()
+    };
+    rewrite_device(&ctx, device_address, device, updated_registry)?;
+    DeviceHeartbeat::refresh(&ctx.accounts.heartbeat, ek_pubkey_hash, operator_key)?;
+
+    msg!("Device operator set to {}", operator_key);
+    Ok(())
+}
@@ -0,0 +1,85 @@
+use anchor_lang::prelude::*;
+use anchor_lang::solana_program::clock::Clock;
+use light_sdk::light_system_accounts;
+use light_sdk_macros::LightTraits;
+use crate::certificate::{verify_certificate_held, CertificateProof};
+use crate::compressed_account_helpers::{rewrite_device, DeviceInput};
+use crate::state::{CompressedDeviceRegistry, Marketplace};
+use crate::{ErrorCode, SPL_ACCOUNT_COMPRESSION_ID};
+
+/// Shared by `deactivate_device` and `reactivate_device`.
+#[light_system_accounts]
+#[derive(Accounts, LightTraits)]
+pub struct SetDeviceStatus<'info> {
+    #[account(mut)]
+    #[fee_payer]
+    pub authority: Signer<'info>,
+    /// CHECK: checked by cpi.
+    #[authority]
+    pub cpi_signer: AccountInfo<'info>,
+    #[self_program]
+    pub self_program: Program<'info, crate::program::Chainsensor>,
+    #[account(
+        seeds = [
+            b"marketplace",
+            marketplace.creator.as_ref(),
+            &marketplace.marketplace_id.to_le_bytes(),
+        ],
+        bump = marketplace.bump,
+    )]
+    pub marketplace: Account<'info, Marketplace>,
+    /// CHECK: certificate tree, required for certified devices; checked by verify_leaf.
+    pub certificate_tree: Option<UncheckedAccount<'info>>,
+    /// CHECK: address is checked.
+    #[account(address = SPL_ACCOUNT_COMPRESSION_ID)]
+    pub compression_program: Option<UncheckedAccount<'info>>,
+}
+
+pub fn handler<'info>(
+    ctx: Context<'_, '_, '_, 'info, SetDeviceStatus<'info>>,
+    device_address: [u8; 32],
+    device: DeviceInput,
+    is_active: bool,
+    certificate_proof: Option<CertificateProof>,
+) -> Result<()> {
+    let authority = ctx.accounts.authority.key();
+    let is_admin = authority == ctx.accounts.marketplace.admin;
+    let device_registry = &device.device_registry;
+    require!(
+        device_registry.marketplace == ctx.accounts.marketplace.key(),
+        ErrorCode::DeviceMarketplaceMismatch
+    );
+    require!(
+        authority == device_registry.owner || is_admin,
+        ErrorCode::DeviceStatusUnauthorized
+    );
+    if !is_admin {
+        verify_certificate_held(
+            ctx.accounts.certificate_tree.as_deref(),
+            ctx.remaining_accounts,
+            device_registry,
+            certificate_proof.as_ref(),
+        )?;
+    }
+    if is_active {
+        require!(!device_registry.is_active, ErrorCode::DeviceAlreadyActive);
+        // An admin deactivation can't be undone by the owner
+        require!(
+            is_admin || !device_registry.deactivated_by_admin,
+            ErrorCode::DeactivatedByAdmin
+        );
+    } else {
+        require!(device_registry.is_active, ErrorCode::DeviceInactive);
+    }
+
+    let updated_registry = CompressedDeviceRegistry {
+        is_active,
+        deactivated_at: if is_active { 0 } else { Clock::get()?.unix_timestamp },
+        deactivated_by_admin: !is_active && is_admin,
+        ..device_registry.clone()
+    };
+    rewrite_device(&ctx, device_address, device, updated_registry)?;
+
+    msg!("Device active: {}", is_active);
+    Ok(())
+}
@@ -0,0 +1,109 @@
+use anchor_lang::prelude::*;
+use light_sdk::light_system_accounts;
+use light_sdk_macros::LightTraits;
+use mpl_bubblegum::accounts::TreeConfig;
+use mpl_bubblegum::instructions::MintV1CpiBuilder;
+use mpl_bubblegum::types::{MetadataArgs, TokenProgramVersion, TokenStandard};
+use mpl_bubblegum::utils::get_asset_id;
+use crate::compressed_account_helpers::{rewrite_device, DeviceInput};
+use crate::state::CompressedDeviceRegistry;
+use crate::{ErrorCode, CERTIFICATE_AUTHORITY_SEED, SPL_ACCOUNT_COMPRESSION_ID};
+
+pub const CERTIFICATE_NAME: &str = "ChainSensors Device";
+pub const CERTIFICATE_SYMBOL: &str = "CSDEV";
+// Bubblegum caps metadata URIs at 200 bytes; 44 are reserved for the device address.
+pub const MAX_CERTIFICATE_URI_PREFIX_LEN: usize = 156;
+
+#[light_system_accounts]
+#[derive(Accounts, LightTraits)]
+pub struct MintDeviceCertificate<'info> {
+    #[account(mut)]
+    #[fee_payer]
+    pub owner: Signer<'info>,
+    /// CHECK: checked by cpi.
+    #[authority]
+    pub cpi_signer: AccountInfo<'info>,
+    #[self_program]
+    pub self_program: Program<'info, crate::program::Chainsensor>,
+    /// CHECK: program PDA set as the Bubblegum tree delegate.
+    #[account(seeds = [CERTIFICATE_AUTHORITY_SEED], bump)]
+    pub certificate_authority: AccountInfo<'info>,
+    /// CHECK: validated by Bubblegum.
+    #[account(mut)]
+    pub tree_config: AccountInfo<'info>,
+    /// CHECK: validated by Bubblegum.
+    #[account(mut)]
+    pub certificate_tree: AccountInfo<'info>,
+    /// CHECK: address is checked.
+    #[account(address = mpl_bubblegum::ID)]
+    pub bubblegum_program: AccountInfo<'info>,
+    /// CHECK: address is checked.
+    #[account(address = SPL_ACCOUNT_COMPRESSION_ID)]
+    pub compression_program: AccountInfo<'info>,
+}
+
+/// Mints a compressed NFT to the device owner and records its asset ID on the
+/// device. From then on the NFT holder is the authoritative device owner.
+pub fn handler<'info>(
+    ctx: Context<'_, '_, '_, 'info, MintDeviceCertificate<'info>>,
+    device_address: [u8; 32],
+    device: DeviceInput,
+    uri_prefix: String,
+) -> Result<()> {
+    let device_registry = &device.device_registry;
+    require!(device_registry.owner == ctx.accounts.owner.key(), ErrorCode::DeviceOwnerMismatch);
+    require!(
+        device_registry.certificate == Pubkey::default(),
+        ErrorCode::CertificateAlreadyMinted
+    );
+    require!(
+        uri_prefix.len() <= MAX_CERTIFICATE_URI_PREFIX_LEN,
+        ErrorCode::CertificateUriTooLong
+    );
+
+    // The next leaf's nonce determines the asset ID
+    let num_minted = {
+        let data = ctx.accounts.tree_config.try_borrow_data()?;
+        TreeConfig::from_bytes(&data)
+            .map_err(|_| ErrorCode::InvalidCertificateTree)?
+            .num_minted
+    };
+    let certificate = get_asset_id(ctx.accounts.certificate_tree.key, num_minted);
+
+    let metadata = MetadataArgs {
+        name: CERTIFICATE_NAME.to_string(),
+        symbol: CERTIFICATE_SYMBOL.to_string(),
+        uri: format!("{}{}", uri_prefix, Pubkey::new_from_array(device_address)),
+        seller_fee_basis_points: 0,
+        primary_sale_happened: true,
+        is_mutable: false,
+        edition_nonce: None,
+        token_standard: Some(TokenStandard::NonFungible),
+        collection: None,
+        uses: None,
+        token_program_version: TokenProgramVersion::Original,
+        creators: Vec::new(),
+    };
+    let authority_seeds: &[&[u8]] = &[CERTIFICATE_AUTHORITY_SEED, &[ctx.bumps.certificate_authority]];
+    MintV1CpiBuilder::new(&ctx.accounts.bubblegum_program)
+        .tree_config(&ctx.accounts.tree_config)
+        .leaf_owner(&ctx.accounts.owner)
+        .leaf_delegate(&ctx.accounts.owner)
+        .merkle_tree(&ctx.accounts.certificate_tree)
+        .payer(&ctx.accounts.owner)
+        .tree_creator_or_delegate(&ctx.accounts.certificate_authority)
+        .log_wrapper(&ctx.accounts.noop_program)
+        .compression_program(&ctx.accounts.compression_program)
+        .system_program(&ctx.accounts.system_program)
+        .metadata(metadata)
+        .invoke_signed(&[authority_seeds])?;
+
+    let certified_registry = CompressedDeviceRegistry {
+        certificate,
+        ..device_registry.clone()
+    };
+    rewrite_device(&ctx, device_address, device, certified_registry)?;
+
+    msg!("Minted device certificate: {}", certificate);
+    Ok(())
+}
@@ -1,4 +1,68 @@
-pub mod initialize;
+pub mod accept_admin;
+pub mod add_accepted_mint;
+pub mod allowlist_seller;
+pub mod ban_device;
+pub mod cancel_listing;
+pub mod claim_device;
+pub mod close_device;
+pub mod commit_data_batch;
+pub mod create_listing;
+pub mod device_heartbeat;
+pub mod device_operator;
+pub mod device_status;
+pub mod mint_device_certificate;
+pub mod pause_marketplace;
+pub mod propose_admin;
+pub mod purchase_listing;
 pub mod register_device;
-pub use initialize::*;
-pub use register_device::*;
\ No newline at end of file
+pub mod register_devices;
+pub mod remove_accepted_mint;
+pub mod remove_fee_schedule;
+pub mod revoke_seller;
+pub mod rotate_device_key;
+pub mod set_fee_schedule;
+pub mod set_guardian;
+pub mod set_registration_policy;
+pub mod set_seller_tier;
+pub mod sync_device_heartbeat;
+pub mod transfer_device;
+pub mod unban_device;
+pub mod unpause_marketplace;
+pub mod update_device;
+pub mod update_marketplace;
+pub mod withdraw_sol_treasury;
+pub mod withdraw_treasury;
+pub use accept_admin::*;
+pub use add_accepted_mint::*;
+pub use allowlist_seller::*;
+pub use ban_device::*;
+pub use cancel_listing::*;
+pub use claim_device::*;
+pub use close_device::*;
+pub use commit_data_batch::*;
+pub use create_listing::*;
+pub use device_heartbeat::*;
+pub use device_operator::*;
+pub use device_status::*;
+pub use mint_device_certificate::*;
+pub use pause_marketplace::*;
+pub use propose_admin::*;
+pub use purchase_listing::*;
+pub use register_device::*;
+pub use register_devices::*;
+pub use remove_accepted_mint::*;
+pub use remove_fee_schedule::*;
+pub use revoke_seller::*;
+pub use rotate_device_key::*;
+pub use set_fee_schedule::*;
+pub use set_guardian::*;
+pub use set_registration_policy::*;
+pub use set_seller_tier::*;
+pub use sync_device_heartbeat::*;
+pub use transfer_device::*;
+pub use unban_device::*;
+pub use unpause_marketplace::*;
+pub use update_device::*;
+pub use update_marketplace::*;
+pub use withdraw_sol_treasury::*;
+pub use withdraw_treasury::*;
@@ -0,0 +1,46 @@
+use anchor_lang::prelude::*;
+use anchor_lang::solana_program::clock::Clock;
+use crate::state::{Marketplace, PAUSE_ALL};
+use crate::ErrorCode;
+
+#[derive(Accounts)]
+pub struct PauseMarketplace<'info> {
+    pub authority: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"marketplace",
+            marketplace.creator.as_ref(),
+            &marketplace.marketplace_id.to_le_bytes(),
+        ],
+        bump = marketplace.bump,
+        constraint = authority.key() == marketplace.admin
+            || marketplace.guardian == Some(authority.key()) @ ErrorCode::NotAdminOrGuardian,
+    )]
+    pub marketplace: Account<'info, Marketplace>,
+}
+
+#[event]
+pub struct MarketplacePauseUpdated {
+    pub marketplace: Pubkey,
+    pub pause_flags: u8,
+    pub updated_by: Pubkey,
+    pub timestamp: i64,
+}
+
+/// Sets the given `PAUSE_*` bits. Callable by the admin or the guardian.
+pub fn handler(ctx: Context<PauseMarketplace>, flags: u8) -> Result<()> {
+    require!(flags != 0 && flags & !PAUSE_ALL == 0, ErrorCode::InvalidPauseFlags);
+
+    let marketplace = &mut ctx.accounts.marketplace;
+    marketplace.pause_flags |= flags;
+
+    emit!(MarketplacePauseUpdated {
+        marketplace: marketplace.key(),
+        pause_flags: marketplace.pause_flags,
+        updated_by: ctx.accounts.authority.key(),
+        timestamp: Clock::get()?.unix_timestamp,
+    });
+    Ok(())
+}
@@ -0,0 +1,31 @@
+use anchor_lang::prelude::*;
+use crate::state::Marketplace;
+use crate::ErrorCode;
+
+#[derive(Accounts)]
+pub struct ProposeAdmin<'info> {
+    pub admin: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"marketplace",
+            marketplace.creator.as_ref(),
+            &marketplace.marketplace_id.to_le_bytes(),
+        ],
+        bump = marketplace.bump,
+        has_one = admin @ ErrorCode::AdminMismatch,
+    )]
+    pub marketplace: Account<'info, Marketplace>,
+}
+
+/// First step of an admin transfer; proposing again replaces the pending admin
+/// and `None` withdraws the proposal.
+pub fn handler(ctx: Context<ProposeAdmin>, new_admin: Option<Pubkey>) -> Result<()> {
+    if let Some(new_admin) = new_admin {
+        require!(new_admin != Pubkey::default(), ErrorCode::InvalidNewAdmin);
+    }
+    ctx.accounts.marketplace.pending_admin = new_admin;
+    msg!("Proposed new admin: {:?}", new_admin);
+    Ok(())
+}
@@ -1,58 +1,130 @@
 use anchor_lang::prelude::*;
-use anchor_spl::token::{self, Mint, Token, TokenAccount};
-use crate::state::{ListingState, Marketplace, DeviceRegistry, PurchaseRecord};
+use anchor_lang::system_program;
+use anchor_spl::token_2022::spl_token_2022::{
+    extension::{transfer_fee::TransferFeeConfig, BaseStateWithExtensions, StateWithExtensions},
+    state::Mint as MintState,
+};
+use anchor_spl::token_interface::{self, Mint, TokenAccount, TokenInterface};
+use light_sdk::light_system_accounts;
+use light_sdk_macros::LightTraits;
+use crate::certificate::{verify_certificate_held, CertificateProof};
+use crate::compressed_account_helpers::{rewrite_device, DeviceInput};
+use crate::state::{
+    AcceptedMint, CompressedDeviceRegistry, FeeSchedule, ListingState, Marketplace,
+    PurchaseRecord, SellerTier, SolTreasury, PAUSE_PURCHASES, SETTLEMENT_SOL, SETTLEMENT_TOKEN,
+};
+use crate::{ErrorCode, SPL_ACCOUNT_COMPRESSION_ID};
 
-#[derive(Accounts)]
-#[instruction(listing_id: [u8; 32], units_requested: u64)]
+#[light_system_accounts]
+#[derive(Accounts, LightTraits)]
+#[instruction(listing_id: String, units_requested: u64)]
 pub struct PurchaseListing<'info> {
     #[account(mut)]
+    #[fee_payer]
     pub buyer: Signer<'info>,
+    /// CHECK: checked by cpi.
+    #[authority]
+    pub cpi_signer: AccountInfo<'info>,
+    #[self_program]
+    pub self_program: Program<'info, crate::program::Chainsensor>,
 
+    // Token settlement accounts; omitted for SOL-settled listings
     #[account(
         mut,
-        associated_token::mint = usdc_mint,
+        associated_token::mint = payment_mint,
         associated_token::authority = buyer,
+        associated_token::token_program = token_program,
     )]
-    pub buyer_ata: Account<'info, TokenAccount>,
+    pub buyer_ata: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
 
     #[account(
         mut,
-        associated_token::mint = usdc_mint,
+        associated_token::mint = payment_mint,
         associated_token::authority = listing_state.seller,
+        associated_token::token_program = token_program,
     )]
-    pub seller_ata: Account<'info, TokenAccount>,
+    pub seller_ata: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
 
     #[account(
         mut,
-        seeds = [b"treasury", marketplace.key().as_ref()],
-        bump = marketplace.treasury_bump,
+        seeds = [b"treasury", marketplace.key().as_ref(), listing_state.token_mint.as_ref()],
+        bump,
+        token::mint = payment_mint,
+        token::token_program = token_program,
+    )]
+    pub treasury_ata: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+
+    /// The listing's settlement mint must still be accepted by the marketplace.
+    #[account(
+        seeds = [b"accepted_mint", marketplace.key().as_ref(), listing_state.token_mint.as_ref()],
+        bump = accepted_mint.bump,
+    )]
+    pub accepted_mint: Option<Box<Account<'info, AcceptedMint>>>,
+
+    /// SPL Token or Token-2022 mint.
+    #[account(
+        address = listing_state.token_mint @ ErrorCode::InvalidMint,
+        mint::token_program = token_program,
+    )]
+    pub payment_mint: Option<Box<InterfaceAccount<'info, Mint>>>,
+    pub token_program: Option<Interface<'info, TokenInterface>>,
+
+    // SOL settlement accounts; omitted for token-settled listings
+    #[account(mut, address = listing_state.seller @ ErrorCode::DeviceOwnerMismatch)]
+    pub seller: Option<SystemAccount<'info>>,
+
+    #[account(
+        mut,
+        seeds = [b"sol_treasury", marketplace.key().as_ref()],
+        bump = sol_treasury.bump,
     )]
-    pub treasury_ata: Account<'info, TokenAccount>,
+    pub sol_treasury: Option<Account<'info, SolTreasury>>,
 
     #[account(
         mut,
-        seeds = [b"listing", device_registry.key().as_ref(), listing_id.as_ref()],
+        seeds = [b"listing", listing_state.device.as_ref(), listing_id.as_bytes()],
         bump = listing_state.bump,
         constraint = listing_state.status == 0 @ ErrorCode::ListingNotActive,
         constraint = listing_state.seller != buyer.key() @ ErrorCode::CannotBuyOwnListing,
     )]
-    pub listing_state: Account<'info, ListingState>,
+    pub listing_state: Box<Account<'info, ListingState>>,
 
     #[account(
-        seeds = [b"marketplace", marketplace.admin.as_ref()],
+        seeds = [
+            b"marketplace",
+            marketplace.creator.as_ref(),
+            &marketplace.marketplace_id.to_le_bytes(),
+        ],
         bump = marketplace.bump,
+        constraint = listing_state.marketplace == marketplace.key() @ ErrorCode::ListingMarketplaceMismatch,
+        constraint = marketplace.is_active @ ErrorCode::MarketplaceInactive,
+        constraint = !marketplace.is_paused(PAUSE_PURCHASES) @ ErrorCode::PurchasesPaused,
     )]
     pub marketplace: Account<'info, Marketplace>,
 
+    /// CHECK: ban PDA for the listed device; must not exist.
     #[account(
-        seeds = [b"device", marketplace.key().as_ref(), device_registry.device_id.as_bytes()],
-        bump = device_registry.bump,
-        constraint = device_registry.is_active @ ErrorCode::DeviceInactive,
+        seeds = [b"ban", marketplace.key().as_ref(), listing_state.device.as_ref()],
+        bump,
+        constraint = device_ban.data_is_empty() @ ErrorCode::DeviceBanned,
     )]
-    pub device_registry: Account<'info, DeviceRegistry>,
+    pub device_ban: UncheckedAccount<'info>,
+
+    /// CHECK: fee schedule PDA for the listing's data type; the flat marketplace
+    /// fee applies when it does not exist.
+    #[account(
+        seeds = [b"fee_schedule", marketplace.key().as_ref(), listing_state.data_type.as_ref()],
+        bump,
+    )]
+    pub fee_schedule: UncheckedAccount<'info>,
+
+    /// CHECK: seller tier PDA; the seller is tier 0 when it does not exist.
+    #[account(
+        seeds = [b"seller_tier", marketplace.key().as_ref(), listing_state.seller.as_ref()],
+        bump,
+    )]
+    pub seller_tier: UncheckedAccount<'info>,
 
-    pub usdc_mint: Account<'info, Mint>,
-    pub token_program: Program<'info, Token>,
     pub clock: Sysvar<'info, Clock>,
 
     #[account(
@@ -66,15 +138,19 @@ pub struct PurchaseListing<'info> {
         ],
         bump,
     )]
-    pub purchase_record: Account<'info, PurchaseRecord>,
+    pub purchase_record: Box<Account<'info, PurchaseRecord>>,
 
-    pub system_program: Program<'info, System>,
     pub rent: Sysvar<'info, Rent>,
+    /// CHECK: certificate tree, required for certified devices; checked by verify_leaf.
+    pub certificate_tree: Option<UncheckedAccount<'info>>,
+    /// CHECK: address is checked.
+    #[account(address = SPL_ACCOUNT_COMPRESSION_ID)]
+    pub compression_program: Option<UncheckedAccount<'info>>,
 }
 
 #[event]
 pub struct ListingPurchased {
-    pub listing_id: [u8; 32],
+    pub listing_id: String,
     pub buyer: Pubkey,
     pub seller: Pubkey,
     pub units_purchased: u64,
@@ -84,13 +160,15 @@ pub struct ListingPurchased {
     pub timestamp: i64,
 }
 
-pub fn handler(
-    ctx: Context<PurchaseListing>,
-    listing_id: [u8; 32],
+pub fn handler<'info>(
+    ctx: Context<'_, '_, '_, 'info, PurchaseListing<'info>>,
+    listing_id: String,
     units_requested: u64,
+    device: DeviceInput,
+    certificate_proof: Option<CertificateProof>,
 ) -> Result<()> {
-    let listing = &mut ctx.accounts.listing_state;
     let clock = Clock::get()?;
+    let listing = &ctx.accounts.listing_state;
 
     // Basic validation
     require!(units_requested > 0, ErrorCode::InvalidUnitsRequested);
@@ -105,15 +183,50 @@ pub fn handler(
     // Inventory check
     require!(units_requested <= listing.remaining_units, ErrorCode::InsufficientUnits);
 
-    // Compute payment amounts
+    // The listed device must still be active
+    let device_address = listing.device.to_bytes();
+    require!(
+        device.device_registry.marketplace == ctx.accounts.marketplace.key(),
+        ErrorCode::DeviceMarketplaceMismatch
+    );
+    require!(device.device_registry.is_active, ErrorCode::DeviceInactive);
+    // Listings left over from a previous owner (after `claim_device`) can only be cancelled
+    require!(device.device_registry.owner == listing.seller, ErrorCode::ListingSellerNotOwner);
+    // A seller who sold the certificate NFT no longer controls the device
+    verify_certificate_held(
+        ctx.accounts.certificate_tree.as_deref(),
+        ctx.remaining_accounts,
+        &device.device_registry,
+        certificate_proof.as_ref(),
+    )?;
+
+    // Prove the device state by consuming it and writing it back,
+    // releasing the listing slot when this purchase sells it out
+    let sells_out = units_requested == listing.remaining_units;
+    let active_listings = if sells_out {
+        device
+            .device_registry
+            .active_listings
+            .checked_sub(1)
+            .ok_or(ErrorCode::MathOverflow)?
+    } else {
+        device.device_registry.active_listings
+    };
+    let updated_registry = CompressedDeviceRegistry {
+        active_listings,
+        ..device.device_registry.clone()
+    };
+    rewrite_device(&ctx, device_address, device, updated_registry)?;
+
+    let fee_bps = ctx.accounts.applicable_fee_bps()?;
+    let listing = &mut ctx.accounts.listing_state;
+
+    // Compute payment amounts, in token base units or lamports per the listing
     let price_for_units = listing.price_per_unit
         .checked_mul(units_requested)
         .ok_or(ErrorCode::MathOverflow)?;
-    // Ensure buyer has sufficient funds
-    require!(ctx.accounts.buyer_ata.amount >= price_for_units, ErrorCode::InsufficientFunds);
-
     let fee = (price_for_units as u128)
-        .checked_mul(ctx.accounts.marketplace.seller_fee as u128)
+        .checked_mul(fee_bps as u128)
         .ok_or(ErrorCode::MathOverflow)?
         .checked_div(10_000)
         .ok_or(ErrorCode::MathOverflow)?
@@ -121,31 +234,122 @@ pub fn handler(
         .map_err(|_| ErrorCode::MathOverflow)?;
     let amount_to_seller = price_for_units.checked_sub(fee).ok_or(ErrorCode::MathOverflow)?;
 
-    // 1) Transfer fee → treasury first
-    token::transfer(
-        CpiContext::new(
-            ctx.accounts.token_program.to_account_info(),
-            token::Transfer {
-                from:      ctx.accounts.buyer_ata.to_account_info(),
-                to:        ctx.accounts.treasury_ata.to_account_info(),
-                authority: ctx.accounts.buyer.to_account_info(),
-            },
-        ),
-        fee,
-    )?;
+    let treasury_received;
+    let seller_received;
+    match listing.settlement_mode {
+        SETTLEMENT_TOKEN => {
+            let (
+                Some(buyer_ata),
+                Some(seller_ata),
+                Some(treasury_ata),
+                Some(payment_mint),
+                Some(token_program),
+            ) = (
+                &ctx.accounts.buyer_ata,
+                &ctx.accounts.seller_ata,
+                &ctx.accounts.treasury_ata,
+                &ctx.accounts.payment_mint,
+                &ctx.accounts.token_program,
+            ) else {
+                return err!(ErrorCode::MissingTokenAccounts);
+            };
+            // The listing's mint must still be accepted, and the treasury must be its own
+            let Some(accepted_mint) = &ctx.accounts.accepted_mint else {
+                return err!(ErrorCode::MissingTokenAccounts);
+            };
+            require_keys_eq!(treasury_ata.key(), accepted_mint.treasury, ErrorCode::InvalidTreasury);
+            // Ensure buyer has sufficient funds
+            require!(buyer_ata.amount >= price_for_units, ErrorCode::InsufficientFunds);
 
-    // 2) Transfer remainder → seller
-    token::transfer(
-        CpiContext::new(
-            ctx.accounts.token_program.to_account_info(),
-            token::Transfer {
-                from:      ctx.accounts.buyer_ata.to_account_info(),
-                to:        ctx.accounts.seller_ata.to_account_info(),
-                authority: ctx.accounts.buyer.to_account_info(),
-            },
-        ),
-        amount_to_seller,
-    )?;
+            // 1) Transfer fee → treasury first
+            token_interface::transfer_checked(
+                CpiContext::new(
+                    token_program.to_account_info(),
+                    token_interface::TransferChecked {
+                        from:      buyer_ata.to_account_info(),
+                        mint:      payment_mint.to_account_info(),
+                        to:        treasury_ata.to_account_info(),
+                        authority: ctx.accounts.buyer.to_account_info(),
+                    },
+                ),
+                fee,
+                payment_mint.decimals,
+            )?;
+
+            // 2) Transfer remainder → seller
+            token_interface::transfer_checked(
+                CpiContext::new(
+                    token_program.to_account_info(),
+                    token_interface::TransferChecked {
+                        from:      buyer_ata.to_account_info(),
+                        mint:      payment_mint.to_account_info(),
+                        to:        seller_ata.to_account_info(),
+                        authority: ctx.accounts.buyer.to_account_info(),
+                    },
+                ),
+                amount_to_seller,
+                payment_mint.decimals,
+            )?;
+
+            // Token-2022 transfer fees are withheld from what each recipient receives
+            let mint_info = payment_mint.to_account_info();
+            treasury_received = fee
+                .checked_sub(transfer_fee(&mint_info, fee)?)
+                .ok_or(ErrorCode::MathOverflow)?;
+            seller_received = amount_to_seller
+                .checked_sub(transfer_fee(&mint_info, amount_to_seller)?)
+                .ok_or(ErrorCode::MathOverflow)?;
+        }
+        SETTLEMENT_SOL => {
+            let (Some(seller), Some(sol_treasury)) =
+                (&ctx.accounts.seller, &ctx.accounts.sol_treasury)
+            else {
+                return err!(ErrorCode::MissingSolAccounts);
+            };
+            require!(
+                ctx.accounts.buyer.lamports() >= price_for_units,
+                ErrorCode::InsufficientFunds
+            );
+            // A payout too small to make a new seller account rent-exempt would fail
+            // in the runtime; reject it up front with a clear error
+            let seller_balance = seller
+                .lamports()
+                .checked_add(amount_to_seller)
+                .ok_or(ErrorCode::MathOverflow)?;
+            require!(
+                seller_balance >= Rent::get()?.minimum_balance(seller.to_account_info().data_len()),
+                ErrorCode::SellerNotRentExempt
+            );
+
+            // 1) Transfer fee → lamport treasury first
+            system_program::transfer(
+                CpiContext::new(
+                    ctx.accounts.system_program.to_account_info(),
+                    system_program::Transfer {
+                        from: ctx.accounts.buyer.to_account_info(),
+                        to:   sol_treasury.to_account_info(),
+                    },
+                ),
+                fee,
+            )?;
+
+            // 2) Transfer remainder → seller
+            system_program::transfer(
+                CpiContext::new(
+                    ctx.accounts.system_program.to_account_info(),
+                    system_program::Transfer {
+                        from: ctx.accounts.buyer.to_account_info(),
+                        to:   seller.to_account_info(),
+                    },
+                ),
+                amount_to_seller,
+            )?;
+
+            treasury_received = fee;
+            seller_received = amount_to_seller;
+        }
+        _ => return err!(ErrorCode::InvalidSettlementMode),
+    }
 
     // Update listing state
     listing.remaining_units = listing
@@ -162,12 +366,15 @@ pub fn handler(
 
     // Record the purchase
     let record = &mut ctx.accounts.purchase_record;
-    record.listing         = listing.key();
-    record.buyer           = ctx.accounts.buyer.key();
-    record.units_purchased = units_requested;
-    record.price_paid      = price_for_units;
-    record.fee             = fee;
-    record.timestamp       = clock.unix_timestamp;
+    record.listing           = listing.key();
+    record.buyer             = ctx.accounts.buyer.key();
+    record.units_purchased   = units_requested;
+    record.price_paid        = price_for_units;
+    record.fee               = fee;
+    record.fee_bps           = fee_bps;
+    record.seller_received   = seller_received;
+    record.treasury_received = treasury_received;
+    record.timestamp         = clock.unix_timestamp;
 
     // Increment purchase counter
     listing.purchase_count = listing.purchase_count.checked_add(1).unwrap();
@@ -187,14 +394,104 @@ pub fn handler(
     Ok(())
 }
 
-#[error_code]
-pub enum ErrorCode {
-    #[msg("Listing is not active")]        ListingNotActive,
-    #[msg("Cannot buy your own listing")]  CannotBuyOwnListing,
-    #[msg("Listing has expired")]          ListingExpired,
-    #[msg("Invalid number of units requested")] InvalidUnitsRequested,
-    #[msg("Insufficient funds")]            InsufficientFunds,
-    #[msg("Insufficient units available")]   InsufficientUnits,
-    #[msg("Math overflow")]                 MathOverflow,
-    #[msg("Device is inactive")]            DeviceInactive,
-}
\ No newline at end of file
+impl<'info> PurchaseListing<'info> {
+    /// Seller fee in basis points: the data type's schedule at the seller's tier,
+    /// or the marketplace's flat `seller_fee` when no schedule exists.
+    pub fn applicable_fee_bps(&self) -> Result<u16> {
+        if self.fee_schedule.data_is_empty() {
+            return Ok(self.marketplace.seller_fee);
+        }
+        let schedule = Account::<FeeSchedule>::try_from(&self.fee_schedule)?;
+        let tier = if self.seller_tier.data_is_empty() {
+            0
+        } else {
+            Account::<SellerTier>::try_from(&self.seller_tier)?.tier
+        };
+        Ok(schedule.fee_for_tier(tier))
+    }
+}
+
+/// Transfer fee Token-2022 withholds when moving `amount` of this mint; zero for
+/// SPL Token mints and mints without the transfer-fee extension.
+fn transfer_fee(mint: &AccountInfo, amount: u64) -> Result<u64> {
+    if amount == 0 {
+        return Ok(0);
+    }
+    epoch_transfer_fee(&mint.try_borrow_data()?, Clock::get()?.epoch, amount)
+}
+
+fn epoch_transfer_fee(mint_data: &[u8], epoch: u64, amount: u64) -> Result<u64> {
+    let mint = StateWithExtensions::<MintState>::unpack(mint_data)?;
+    match mint.get_extension::<TransferFeeConfig>() {
+        Ok(config) => Ok(config
+            .calculate_epoch_fee(epoch, amount)
+            .ok_or(ErrorCode::MathOverflow)?),
+        Err(_) => Ok(0),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anchor_lang::solana_program::program_pack::Pack;
+    use anchor_spl::token_2022::spl_token_2022::extension::{
+        transfer_fee::TransferFee, ExtensionType, StateWithExtensionsMut,
+    };
+
+    fn base_mint() -> MintState {
+        MintState {
+            decimals: 6,
+            is_initialized: true,
+            ..Default::default()
+        }
+    }
+
+    fn spl_mint() -> Vec<u8> {
+        let mut data = vec![0u8; MintState::LEN];
+        MintState::pack(base_mint(), &mut data).unwrap();
+        data
+    }
+
+    // Token-2022 mint charging 1% up to 5_000 base units
+    fn fee_mint() -> Vec<u8> {
+        let len = ExtensionType::try_calculate_account_len::<MintState>(&[
+            ExtensionType::TransferFeeConfig,
+        ])
+        .unwrap();
+        let mut data = vec![0u8; len];
+        let mut mint = StateWithExtensionsMut::<MintState>::unpack_uninitialized(&mut data).unwrap();
+        let fee = TransferFee {
+            epoch: 0u64.into(),
+            maximum_fee: 5_000u64.into(),
+            transfer_fee_basis_points: 100u16.into(),
+        };
+        let config = mint.init_extension::<TransferFeeConfig>(true).unwrap();
+        config.older_transfer_fee = fee;
+        config.newer_transfer_fee = fee;
+        mint.base = base_mint();
+        mint.pack_base();
+        mint.init_account_type().unwrap();
+        data
+    }
+
+    #[test]
+    fn spl_mint_has_no_transfer_fee() {
+        assert_eq!(epoch_transfer_fee(&spl_mint(), 0, 1_000_000).unwrap(), 0);
+    }
+
+    #[test]
+    fn transfer_fee_extension_is_applied() {
+        assert_eq!(epoch_transfer_fee(&fee_mint(), 0, 10_000).unwrap(), 100);
+        assert_eq!(epoch_transfer_fee(&fee_mint(), 0, 1_000_000).unwrap(), 5_000);
+    }
+
+    #[test]
+    fn zero_amount_has_no_transfer_fee() {
+        let key = Pubkey::new_unique();
+        let owner = anchor_spl::token_2022::ID;
+        let mut lamports = 0;
+        let mut data = fee_mint();
+        let mint = AccountInfo::new(&key, false, false, &mut lamports, &mut data, &owner, false, 0);
+        assert_eq!(transfer_fee(&mint, 0).unwrap(), 0);
+    }
+}
@@ -0,0 +1,197 @@
+use anchor_lang::prelude::*;
+use anchor_lang::solana_program::clock::Clock;
+use anchor_lang::solana_program::hash::hash;
+use anchor_lang::solana_program::sysvar::instructions as sysvar_instructions;
+use light_sdk::address::NewAddressParamsPacked;
+use light_sdk::{light_system_accounts, merkle_context::PackedAddressMerkleContext};
+use light_sdk_macros::LightTraits;
+use light_sdk::{
+    proof::CompressedProof,
+    verify::{verify, InstructionDataInvokeCpi},
+};
+use crate::address::create_address;
+use crate::compressed_account_helpers::create_output_account;
+use crate::ed25519::verify_ed25519_signature_at;
+use crate::state::{
+    AllowlistEntry, CompressedDeviceRegistry, DeviceMetadata, DeviceOperator, Marketplace,
+    PAUSE_REGISTRATION, REGISTRATION_ADMIN_COSIGN, REGISTRATION_ALLOWLIST, REGISTRATION_OPEN,
+};
+use crate::{ErrorCode, CPI_AUTHORITY_PDA_SEED};
+
+/// Shared by `register_device` and `register_devices`.
+#[light_system_accounts]
+#[derive(Accounts, LightTraits)]
+pub struct RegisterDevice<'info> {
+    #[account(mut)]
+    #[fee_payer]
+    pub owner: Signer<'info>,
+    /// CHECK: checked by cpi.
+    #[authority]
+    pub cpi_signer: AccountInfo<'info>,
+    #[self_program]
+    pub self_program: Program<'info, crate::program::Chainsensor>,
+    #[account(
+        seeds = [
+            b"marketplace",
+            marketplace.creator.as_ref(),
+            &marketplace.marketplace_id.to_le_bytes(),
+        ],
+        bump = marketplace.bump,
+        constraint = marketplace.is_active @ ErrorCode::MarketplaceInactive,
+        constraint = !marketplace.is_paused(PAUSE_REGISTRATION) @ ErrorCode::RegistrationPaused,
+    )]
+    pub marketplace: Account<'info, Marketplace>,
+    /// Required when the marketplace uses the allowlist policy.
+    #[account(
+        seeds = [b"allowlist", marketplace.key().as_ref(), owner.key().as_ref()],
+        bump = allowlist_entry.bump,
+    )]
+    pub allowlist_entry: Option<Account<'info, AllowlistEntry>>,
+    /// Required when the marketplace uses the admin co-signature policy.
+    #[account(constraint = admin.key() == marketplace.admin @ ErrorCode::AdminMismatch)]
+    pub admin: Option<Signer<'info>>,
+    /// CHECK: address is checked; used to introspect the Ed25519 instruction.
+    #[account(address = sysvar_instructions::ID)]
+    pub instructions_sysvar: AccountInfo<'info>,
+}
+
+impl<'info> RegisterDevice<'info> {
+    pub fn check_registration_policy(&self) -> Result<()> {
+        match self.marketplace.registration_policy {
+            REGISTRATION_OPEN => {}
+            REGISTRATION_ALLOWLIST => require!(
+                self.allowlist_entry.is_some(),
+                ErrorCode::SellerNotAllowlisted
+            ),
+            REGISTRATION_ADMIN_COSIGN => require!(
+                self.admin.is_some(),
+                ErrorCode::AdminSignatureRequired
+            ),
+            _ => return err!(ErrorCode::InvalidRegistrationPolicy),
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, AnchorSerialize, AnchorDeserialize)]
+pub struct DeviceRegistrationParams {
+    pub device_id: [u8; 32],
+    pub ek_pubkey: [u8; 32],
+    pub metadata: DeviceMetadata,
+    pub address_merkle_tree_root_index: u16,
+}
+
+pub fn handler<'info>(
+    ctx: Context<'_, '_, '_, 'info, RegisterDevice<'info>>,
+    proof: CompressedProof,
+    address_merkle_tree_root_index: u16,
+    address_merkle_context: PackedAddressMerkleContext,
+    merkle_tree_index: u8,
+    bump: u8,
+    device_id: [u8; 32],
+    ek_pubkey: [u8; 32],
+    metadata: DeviceMetadata,
+) -> Result<()> {
+    let device = DeviceRegistrationParams {
+        device_id,
+        ek_pubkey,
+        metadata,
+        address_merkle_tree_root_index,
+    };
+    register(ctx, proof, address_merkle_context, merkle_tree_index, bump, vec![device])
+}
+
+/// Creates one compressed record per device under a single validity proof.
+/// Device `i` of `n` must be signed by the Ed25519 instruction `n - i`
+/// positions before this one, binding the physical device to its owner.
+pub fn register<'info>(
+    ctx: Context<'_, '_, '_, 'info, RegisterDevice<'info>>,
+    proof: CompressedProof,
+    address_merkle_context: PackedAddressMerkleContext,
+    merkle_tree_index: u8,
+    bump: u8,
+    devices: Vec<DeviceRegistrationParams>,
+) -> Result<()> {
+    ctx.accounts.check_registration_policy()?;
+
+    let created_at = Clock::get()?.unix_timestamp;
+    let device_count = devices.len();
+    let mut new_address_params = Vec::with_capacity(device_count);
+    let mut output_compressed_accounts = Vec::with_capacity(device_count);
+    for (i, device) in devices.into_iter().enumerate() {
+        let (address_params, address, device_registry) = build_device(
+            &ctx,
+            &address_merkle_context,
+            device_count - i,
+            created_at,
+            device,
+        )?;
+        new_address_params.push(address_params);
+        output_compressed_accounts.push(create_output_account(
+            merkle_tree_index,
+            device_registry,
+            address,
+        )?);
+    }
+
+    let inputs = InstructionDataInvokeCpi {
+        cpi_context: None,
+        is_compress: false,
+        compress_or_decompress_lamports: None,
+        new_address_params,
+        relay_fee: None,
+        input_compressed_accounts_with_merkle_context: Vec::new(),
+        output_compressed_accounts,
+        proof: Some(proof),
+    };
+    let signer_seeds = [CPI_AUTHORITY_PDA_SEED, &[bump]];
+    verify(&ctx, &inputs, &[signer_seeds.as_slice()])
+}
+
+/// Validates one device and its Ed25519 signature `signature_offset`
+/// instructions back, and derives its address and initial record.
+fn build_device<'info>(
+    ctx: &Context<'_, '_, '_, 'info, RegisterDevice<'info>>,
+    address_merkle_context: &PackedAddressMerkleContext,
+    signature_offset: usize,
+    created_at: i64,
+    device: DeviceRegistrationParams,
+) -> Result<(NewAddressParamsPacked, [u8; 32], CompressedDeviceRegistry)> {
+    require!(!device.device_id.iter().all(|&x| x == 0), ErrorCode::DeviceIdEmpty);
+    device.metadata.validate()?;
+
+    let owner = ctx.accounts.owner.key();
+    let marketplace = ctx.accounts.marketplace.key();
+    let message = [marketplace.to_bytes(), owner.to_bytes(), device.device_id].concat();
+    verify_ed25519_signature_at(
+        &ctx.accounts.instructions_sysvar,
+        signature_offset,
+        &device.ek_pubkey,
+        &message,
+    )?;
+
+    let (address_params, address) = create_address(
+        marketplace,
+        &device.device_id,
+        ctx.remaining_accounts,
+        address_merkle_context.address_merkle_tree_pubkey_index,
+        address_merkle_context.address_queue_pubkey_index,
+        device.address_merkle_tree_root_index,
+    )?;
+    let device_registry = CompressedDeviceRegistry {
+        owner,
+        marketplace,
+        device_id: device.device_id,
+        ek_pubkey_hash: hash(&device.ek_pubkey).to_bytes(),
+        created_at,
+        metadata: device.metadata,
+        is_active: true,
+        deactivated_at: 0,
+        deactivated_by_admin: false,
+        active_listings: 0,
+        key_rotations: 0,
+        certificate: Pubkey::default(),
+        operator: DeviceOperator::default(),
+    };
+    Ok((address_params, address, device_registry))
+}
@@ -0,0 +1,31 @@
+use anchor_lang::prelude::*;
+use light_sdk::merkle_context::PackedAddressMerkleContext;
+use light_sdk::proof::CompressedProof;
+use crate::instructions::register_device::{register, DeviceRegistrationParams, RegisterDevice};
+use crate::ErrorCode;
+
+/// Upper bound on devices per `register_devices` call. The prover's
+/// non-inclusion circuits cover at most two new addresses per validity proof,
+/// and each device also needs its own Ed25519 instruction in the transaction.
+pub const MAX_DEVICES_PER_BATCH: usize = 2;
+
+/// Registers several devices with one validity proof. Device `i` of `n` must be
+/// signed by the Ed25519 instruction `n - i` positions before this one.
+pub fn handler<'info>(
+    ctx: Context<'_, '_, '_, 'info, RegisterDevice<'info>>,
+    proof: CompressedProof,
+    address_merkle_context: PackedAddressMerkleContext,
+    merkle_tree_index: u8,
+    bump: u8,
+    devices: Vec<DeviceRegistrationParams>,
+) -> Result<()> {
+    require!(
+        !devices.is_empty() && devices.len() <= MAX_DEVICES_PER_BATCH,
+        ErrorCode::InvalidBatchSize
+    );
+    let device_count = devices.len();
+    register(ctx, proof, address_merkle_context, merkle_tree_index, bump, devices)?;
+
+    msg!("Registered {} devices", device_count);
+    Ok(())
+}
@@ -0,0 +1,33 @@
+use anchor_lang::prelude::*;
+use crate::state::{AcceptedMint, Marketplace};
+use crate::ErrorCode;
+
+#[derive(Accounts)]
+pub struct RemoveAcceptedMint<'info> {
+    #[account(mut)]
+    pub admin: Signer<'info>,
+
+    #[account(
+        seeds = [
+            b"marketplace",
+            marketplace.creator.as_ref(),
+            &marketplace.marketplace_id.to_le_bytes(),
+        ],
+        bump = marketplace.bump,
+        has_one = admin @ ErrorCode::AdminMismatch,
+    )]
+    pub marketplace: Account<'info, Marketplace>,
+
+    #[account(
+        mut,
+        close = admin,
+        seeds = [b"accepted_mint", marketplace.key().as_ref(), accepted_mint.mint.as_ref()],
+        bump = accepted_mint.bump,
+    )]
+    pub accepted_mint: Account<'info, AcceptedMint>,
+}
+
+pub fn handler(ctx: Context<RemoveAcceptedMint>) -> Result<()> {
+    msg!("Removed accepted mint: {}", ctx.accounts.accepted_mint.mint);
+    Ok(())
+}
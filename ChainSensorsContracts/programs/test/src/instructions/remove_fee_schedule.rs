@@ -0,0 +1,35 @@
+use anchor_lang::prelude::*;
+use crate::state::{FeeSchedule, Marketplace};
+use crate::ErrorCode;
+
+#[derive(Accounts)]
+#[instruction(data_type: [u8; 32])]
+pub struct RemoveFeeSchedule<'info> {
+    #[account(mut)]
+    pub admin: Signer<'info>,
+
+    #[account(
+        seeds = [
+            b"marketplace",
+            marketplace.creator.as_ref(),
+            &marketplace.marketplace_id.to_le_bytes(),
+        ],
+        bump = marketplace.bump,
+        has_one = admin @ ErrorCode::AdminMismatch,
+    )]
+    pub marketplace: Account<'info, Marketplace>,
+
+    #[account(
+        mut,
+        close = admin,
+        seeds = [b"fee_schedule", marketplace.key().as_ref(), data_type.as_ref()],
+        bump = fee_schedule.bump,
+    )]
+    pub fee_schedule: Account<'info, FeeSchedule>,
+}
+
+/// Drops the schedule so the data type falls back to the marketplace seller fee.
+pub fn handler(_ctx: Context<RemoveFeeSchedule>, _data_type: [u8; 32]) -> Result<()> {
+    msg!("Removed fee schedule");
+    Ok(())
+}
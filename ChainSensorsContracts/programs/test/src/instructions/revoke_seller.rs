@@ -0,0 +1,34 @@
+use anchor_lang::prelude::*;
+use crate::state::{AllowlistEntry, Marketplace};
+use crate::ErrorCode;
+
+#[derive(Accounts)]
+#[instruction(seller: Pubkey)]
+pub struct RevokeSeller<'info> {
+    #[account(mut)]
+    pub admin: Signer<'info>,
+
+    #[account(
+        seeds = [
+            b"marketplace",
+            marketplace.creator.as_ref(),
+            &marketplace.marketplace_id.to_le_bytes(),
+        ],
+        bump = marketplace.bump,
+        has_one = admin @ ErrorCode::AdminMismatch,
+    )]
+    pub marketplace: Account<'info, Marketplace>,
+
+    #[account(
+        mut,
+        close = admin,
+        seeds = [b"allowlist", marketplace.key().as_ref(), seller.as_ref()],
+        bump = allowlist_entry.bump,
+    )]
+    pub allowlist_entry: Account<'info, AllowlistEntry>,
+}
+
+pub fn handler(_ctx: Context<RevokeSeller>, seller: Pubkey) -> Result<()> {
+    msg!("Revoked seller: {}", seller);
+    Ok(())
+}
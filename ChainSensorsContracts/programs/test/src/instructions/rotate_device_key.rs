@@ -0,0 +1,105 @@
+use anchor_lang::prelude::*;
+use anchor_lang::solana_program::hash::hash;
+use anchor_lang::solana_program::sysvar::instructions as sysvar_instructions;
+use light_sdk::light_system_accounts;
+use light_sdk_macros::LightTraits;
+use crate::certificate::{verify_certificate_held, CertificateProof};
+use crate::compressed_account_helpers::{rewrite_device, DeviceInput};
+use crate::ed25519::verify_ed25519_signature;
+use crate::state::{CompressedDeviceRegistry, DeviceHeartbeat, Marketplace};
+use crate::{ErrorCode, SPL_ACCOUNT_COMPRESSION_ID};
+
+#[light_system_accounts]
+#[derive(Accounts, LightTraits)]
+#[instruction(device_address: [u8; 32])]
+pub struct RotateDeviceKey<'info> {
+    #[account(mut)]
+    #[fee_payer]
+    pub owner: Signer<'info>,
+    /// CHECK: checked by cpi.
+    #[authority]
+    pub cpi_signer: AccountInfo<'info>,
+    #[self_program]
+    pub self_program: Program<'info, crate::program::Chainsensor>,
+    #[account(
+        seeds = [
+            b"marketplace",
+            marketplace.creator.as_ref(),
+            &marketplace.marketplace_id.to_le_bytes(),
+        ],
+        bump = marketplace.bump,
+    )]
+    pub marketplace: Account<'info, Marketplace>,
+    /// Recovery authority, replaces the old device key signature when present.
+    #[account(constraint = admin.key() == marketplace.admin @ ErrorCode::AdminMismatch)]
+    pub admin: Option<Signer<'info>>,
+    /// CHECK: address is checked; used to introspect the Ed25519 instruction.
+    #[account(address = sysvar_instructions::ID)]
+    pub instructions_sysvar: AccountInfo<'info>,
+    /// CHECK: heartbeat PDA; its cached key and operator are refreshed if it exists.
+    #[account(mut, seeds = [b"heartbeat", device_address.as_ref()], bump)]
+    pub heartbeat: UncheckedAccount<'info>,
+    /// CHECK: certificate tree, required for certified devices; checked by verify_leaf.
+    pub certificate_tree: Option<UncheckedAccount<'info>>,
+    /// CHECK: address is checked.
+    #[account(address = SPL_ACCOUNT_COMPRESSION_ID)]
+    pub compression_program: Option<UncheckedAccount<'info>>,
+}
+
+pub fn handler<'info>(
+    ctx: Context<'_, '_, '_, 'info, RotateDeviceKey<'info>>,
+    device_address: [u8; 32],
+    device: DeviceInput,
+    old_ek_pubkey: Option<[u8; 32]>,
+    new_ek_pubkey: [u8; 32],
+    certificate_proof: Option<CertificateProof>,
+) -> Result<()> {
+    let device_registry = &device.device_registry;
+    require!(device_registry.owner == ctx.accounts.owner.key(), ErrorCode::DeviceOwnerMismatch);
+    require!(
+        device_registry.marketplace == ctx.accounts.marketplace.key(),
+        ErrorCode::DeviceMarketplaceMismatch
+    );
+    verify_certificate_held(
+        ctx.accounts.certificate_tree.as_deref(),
+        ctx.remaining_accounts,
+        device_registry,
+        certificate_proof.as_ref(),
+    )?;
+
+    // Without the admin, the old device key must sign (marketplace, owner, device_id,
+    // new key, rotation count); the count keeps old signatures from being replayed
+    if ctx.accounts.admin.is_none() {
+        let old_ek_pubkey = old_ek_pubkey.ok_or(ErrorCode::MissingDeviceSignature)?;
+        require!(
+            hash(&old_ek_pubkey).to_bytes() == device_registry.ek_pubkey_hash,
+            ErrorCode::DeviceKeyMismatch
+        );
+        let message = [
+            ctx.accounts.marketplace.key().as_ref(),
+            ctx.accounts.owner.key().as_ref(),
+            device_registry.device_id.as_slice(),
+            new_ek_pubkey.as_slice(),
+            &device_registry.key_rotations.to_le_bytes(),
+        ]
+        .concat();
+        verify_ed25519_signature(&ctx.accounts.instructions_sysvar, &old_ek_pubkey, &message)?;
+    }
+
+    let key_rotations = device_registry
+        .key_rotations
+        .checked_add(1)
+        .ok_or(ErrorCode::MathOverflow)?;
+    let ek_pubkey_hash = hash(&new_ek_pubkey).to_bytes();
+    let operator = device_registry.operator.key;
+    let rotated_registry = CompressedDeviceRegistry {
+        ek_pubkey_hash,
+        key_rotations,
+        ..device_registry.clone()
+    };
+    rewrite_device(&ctx, device_address, device, rotated_registry)?;
+    DeviceHeartbeat::refresh(&ctx.accounts.heartbeat, ek_pubkey_hash, operator)?;
+
+    msg!("Device key rotated, rotation #{}", key_rotations);
+    Ok(())
+}
@@ -0,0 +1,76 @@
+use anchor_lang::prelude::*;
+use anchor_lang::solana_program::clock::Clock;
+use crate::state::{FeeSchedule, Marketplace, MAX_SELLER_TIER};
+use crate::{validate_seller_fee, ErrorCode};
+
+#[derive(Accounts)]
+#[instruction(data_type: [u8; 32])]
+pub struct SetFeeSchedule<'info> {
+    #[account(mut)]
+    pub admin: Signer<'info>,
+
+    #[account(
+        seeds = [
+            b"marketplace",
+            marketplace.creator.as_ref(),
+            &marketplace.marketplace_id.to_le_bytes(),
+        ],
+        bump = marketplace.bump,
+        has_one = admin @ ErrorCode::AdminMismatch,
+    )]
+    pub marketplace: Account<'info, Marketplace>,
+
+    #[account(
+        init_if_needed,
+        payer = admin,
+        seeds = [b"fee_schedule", marketplace.key().as_ref(), data_type.as_ref()],
+        bump,
+        space = 8 + FeeSchedule::INIT_SPACE,
+    )]
+    pub fee_schedule: Account<'info, FeeSchedule>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[event]
+pub struct FeeScheduleUpdated {
+    pub marketplace: Pubkey,
+    pub data_type: [u8; 32],
+    pub seller_fee: u16,
+    pub tier_fees: Vec<u16>,
+    pub timestamp: i64,
+}
+
+/// Creates or replaces the fee schedule for `data_type`.
+pub fn handler(
+    ctx: Context<SetFeeSchedule>,
+    data_type: [u8; 32],
+    seller_fee: u16,
+    tier_fees: Vec<u16>,
+) -> Result<()> {
+    require!(!data_type.iter().all(|&x| x == 0), ErrorCode::DataTypeEmpty);
+    require!(tier_fees.len() <= MAX_SELLER_TIER, ErrorCode::InvalidSellerTier);
+    validate_seller_fee(seller_fee)?;
+    for &fee in &tier_fees {
+        validate_seller_fee(fee)?;
+    }
+
+    let timestamp = Clock::get()?.unix_timestamp;
+    ctx.accounts.fee_schedule.set_inner(FeeSchedule {
+        marketplace: ctx.accounts.marketplace.key(),
+        data_type,
+        seller_fee,
+        tier_fees: tier_fees.clone(),
+        updated_at: timestamp,
+        bump: ctx.bumps.fee_schedule,
+    });
+
+    emit!(FeeScheduleUpdated {
+        marketplace: ctx.accounts.marketplace.key(),
+        data_type,
+        seller_fee,
+        tier_fees,
+        timestamp,
+    });
+    Ok(())
+}
@@ -0,0 +1,27 @@
+use anchor_lang::prelude::*;
+use crate::state::Marketplace;
+use crate::ErrorCode;
+
+#[derive(Accounts)]
+pub struct SetGuardian<'info> {
+    pub admin: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"marketplace",
+            marketplace.creator.as_ref(),
+            &marketplace.marketplace_id.to_le_bytes(),
+        ],
+        bump = marketplace.bump,
+        has_one = admin @ ErrorCode::AdminMismatch,
+    )]
+    pub marketplace: Account<'info, Marketplace>,
+}
+
+/// Sets the incident-response guardian; `None` removes it.
+pub fn handler(ctx: Context<SetGuardian>, guardian: Option<Pubkey>) -> Result<()> {
+    ctx.accounts.marketplace.guardian = guardian;
+    msg!("Guardian set: {:?}", guardian);
+    Ok(())
+}
@@ -0,0 +1,30 @@
+use anchor_lang::prelude::*;
+use crate::state::{Marketplace, REGISTRATION_ADMIN_COSIGN};
+use crate::ErrorCode;
+
+#[derive(Accounts)]
+pub struct SetRegistrationPolicy<'info> {
+    pub admin: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"marketplace",
+            marketplace.creator.as_ref(),
+            &marketplace.marketplace_id.to_le_bytes(),
+        ],
+        bump = marketplace.bump,
+        has_one = admin @ ErrorCode::AdminMismatch,
+    )]
+    pub marketplace: Account<'info, Marketplace>,
+}
+
+pub fn handler(ctx: Context<SetRegistrationPolicy>, registration_policy: u8) -> Result<()> {
+    require!(
+        registration_policy <= REGISTRATION_ADMIN_COSIGN,
+        ErrorCode::InvalidRegistrationPolicy
+    );
+    ctx.accounts.marketplace.registration_policy = registration_policy;
+    msg!("Registration policy set to {}", registration_policy);
+    Ok(())
+}
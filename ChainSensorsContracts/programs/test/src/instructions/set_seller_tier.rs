@@ -0,0 +1,44 @@
+use anchor_lang::prelude::*;
+use crate::state::{Marketplace, SellerTier, MAX_SELLER_TIER};
+use crate::ErrorCode;
+
+#[derive(Accounts)]
+#[instruction(seller: Pubkey)]
+pub struct SetSellerTier<'info> {
+    #[account(mut)]
+    pub admin: Signer<'info>,
+
+    #[account(
+        seeds = [
+            b"marketplace",
+            marketplace.creator.as_ref(),
+            &marketplace.marketplace_id.to_le_bytes(),
+        ],
+        bump = marketplace.bump,
+        has_one = admin @ ErrorCode::AdminMismatch,
+    )]
+    pub marketplace: Account<'info, Marketplace>,
+
+    #[account(
+        init_if_needed,
+        payer = admin,
+        seeds = [b"seller_tier", marketplace.key().as_ref(), seller.as_ref()],
+        bump,
+        space = 8 + SellerTier::INIT_SPACE,
+    )]
+    pub seller_tier: Account<'info, SellerTier>,
+
+    pub system_program: Program<'info, System>,
+}
+
+pub fn handler(ctx: Context<SetSellerTier>, seller: Pubkey, tier: u8) -> Result<()> {
+    require!(tier as usize <= MAX_SELLER_TIER, ErrorCode::InvalidSellerTier);
+    ctx.accounts.seller_tier.set_inner(SellerTier {
+        marketplace: ctx.accounts.marketplace.key(),
+        seller,
+        tier,
+        bump: ctx.bumps.seller_tier,
+    });
+    msg!("Seller {} set to tier {}", seller, tier);
+    Ok(())
+}
@@ -0,0 +1,66 @@
+use anchor_lang::prelude::*;
+use light_sdk::light_system_accounts;
+use light_sdk_macros::LightTraits;
+use crate::certificate::{verify_certificate_held, CertificateProof};
+use crate::compressed_account_helpers::{rewrite_device, DeviceInput};
+use crate::state::DeviceHeartbeat;
+use crate::{ErrorCode, SPL_ACCOUNT_COMPRESSION_ID};
+
+#[light_system_accounts]
+#[derive(Accounts, LightTraits)]
+#[instruction(device_address: [u8; 32])]
+pub struct SyncDeviceHeartbeat<'info> {
+    #[account(mut)]
+    #[fee_payer]
+    pub owner: Signer<'info>,
+    /// CHECK: checked by cpi.
+    #[authority]
+    pub cpi_signer: AccountInfo<'info>,
+    #[self_program]
+    pub self_program: Program<'info, crate::program::Chainsensor>,
+    #[account(
+        init_if_needed,
+        payer = owner,
+        seeds = [b"heartbeat", device_address.as_ref()],
+        bump,
+        space = 8 + DeviceHeartbeat::INIT_SPACE,
+    )]
+    pub heartbeat: Account<'info, DeviceHeartbeat>,
+    /// CHECK: certificate tree, required for certified devices; checked by verify_leaf.
+    pub certificate_tree: Option<UncheckedAccount<'info>>,
+    /// CHECK: address is checked.
+    #[account(address = SPL_ACCOUNT_COMPRESSION_ID)]
+    pub compression_program: Option<UncheckedAccount<'info>>,
+}
+
+/// Creates or refreshes the heartbeat PDA with the device key and operator
+/// currently on the compressed record.
+pub fn handler<'info>(
+    ctx: Context<'_, '_, '_, 'info, SyncDeviceHeartbeat<'info>>,
+    device_address: [u8; 32],
+    device: DeviceInput,
+    certificate_proof: Option<CertificateProof>,
+) -> Result<()> {
+    let device_registry = &device.device_registry;
+    require!(device_registry.owner == ctx.accounts.owner.key(), ErrorCode::DeviceOwnerMismatch);
+    verify_certificate_held(
+        ctx.accounts.certificate_tree.as_deref(),
+        ctx.remaining_accounts,
+        device_registry,
+        certificate_proof.as_ref(),
+    )?;
+
+    let ek_pubkey_hash = device_registry.ek_pubkey_hash;
+    let operator = device_registry.operator.key;
+
+    // Prove the cached values by consuming the record and writing it back unchanged
+    let unchanged_registry = device_registry.clone();
+    rewrite_device(&ctx, device_address, device, unchanged_registry)?;
+
+    let heartbeat = &mut ctx.accounts.heartbeat;
+    heartbeat.device = Pubkey::new_from_array(device_address);
+    heartbeat.ek_pubkey_hash = ek_pubkey_hash;
+    heartbeat.operator = operator;
+    heartbeat.bump = ctx.bumps.heartbeat;
+    Ok(())
+}
@@ -0,0 +1,78 @@
+use anchor_lang::prelude::*;
+use anchor_lang::solana_program::clock::Clock;
+use light_sdk::light_system_accounts;
+use light_sdk_macros::LightTraits;
+use crate::compressed_account_helpers::{rewrite_device, DeviceInput};
+use crate::state::{CompressedDeviceRegistry, DeviceHeartbeat, DeviceOperator};
+use crate::ErrorCode;
+
+#[light_system_accounts]
+#[derive(Accounts, LightTraits)]
+#[instruction(device_address: [u8; 32])]
+pub struct TransferDevice<'info> {
+    #[account(mut)]
+    #[fee_payer]
+    pub owner: Signer<'info>,
+    /// CHECK: checked by cpi.
+    #[authority]
+    pub cpi_signer: AccountInfo<'info>,
+    #[self_program]
+    pub self_program: Program<'info, crate::program::Chainsensor>,
+    /// CHECK: heartbeat PDA; its cached key and operator are refreshed if it exists.
+    #[account(mut, seeds = [b"heartbeat", device_address.as_ref()], bump)]
+    pub heartbeat: UncheckedAccount<'info>,
+}
+
+#[event]
+pub struct DeviceTransferred {
+    pub device_address: [u8; 32],
+    pub device_id: [u8; 32],
+    pub marketplace: Pubkey,
+    pub previous_owner: Pubkey,
+    pub new_owner: Pubkey,
+    pub timestamp: i64,
+}
+
+pub fn handler<'info>(
+    ctx: Context<'_, '_, '_, 'info, TransferDevice<'info>>,
+    device_address: [u8; 32],
+    device: DeviceInput,
+    new_owner: Pubkey,
+) -> Result<()> {
+    let previous_owner = ctx.accounts.owner.key();
+    require!(device.device_registry.owner == previous_owner, ErrorCode::DeviceOwnerMismatch);
+    // Certified devices change hands by transferring the certificate NFT instead
+    require!(
+        device.device_registry.certificate == Pubkey::default(),
+        ErrorCode::DeviceCertified
+    );
+    require!(
+        new_owner != previous_owner && new_owner != Pubkey::default(),
+        ErrorCode::InvalidNewOwner
+    );
+    // Open listings pay out to the current owner, so they must be closed first
+    require!(device.device_registry.active_listings == 0, ErrorCode::DeviceHasActiveListings);
+
+    // Nullify the current record and re-create it under the new owner
+    let device_id = device.device_registry.device_id;
+    let marketplace = device.device_registry.marketplace;
+    let ek_pubkey_hash = device.device_registry.ek_pubkey_hash;
+    let transferred_registry = CompressedDeviceRegistry {
+        owner: new_owner,
+        operator: DeviceOperator::default(),
+        ..device.device_registry.clone()
+    };
+    rewrite_device(&ctx, device_address, device, transferred_registry)?;
+    // The new owner starts without an operator
+    DeviceHeartbeat::refresh(&ctx.accounts.heartbeat, ek_pubkey_hash, Pubkey::default())?;
+
+    emit!(DeviceTransferred {
+        device_address,
+        device_id,
+        marketplace,
+        previous_owner,
+        new_owner,
+        timestamp: Clock::get()?.unix_timestamp,
+    });
+    Ok(())
+}
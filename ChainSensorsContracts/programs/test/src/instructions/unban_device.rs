@@ -0,0 +1,34 @@
+use anchor_lang::prelude::*;
+use crate::state::{DeviceBan, Marketplace};
+use crate::ErrorCode;
+
+#[derive(Accounts)]
+#[instruction(device_address: [u8; 32])]
+pub struct UnbanDevice<'info> {
+    #[account(mut)]
+    pub admin: Signer<'info>,
+
+    #[account(
+        seeds = [
+            b"marketplace",
+            marketplace.creator.as_ref(),
+            &marketplace.marketplace_id.to_le_bytes(),
+        ],
+        bump = marketplace.bump,
+        has_one = admin @ ErrorCode::AdminMismatch,
+    )]
+    pub marketplace: Account<'info, Marketplace>,
+
+    #[account(
+        mut,
+        close = admin,
+        seeds = [b"ban", marketplace.key().as_ref(), device_address.as_ref()],
+        bump = device_ban.bump,
+    )]
+    pub device_ban: Account<'info, DeviceBan>,
+}
+
+pub fn handler(_ctx: Context<UnbanDevice>, device_address: [u8; 32]) -> Result<()> {
+    msg!("Unbanned device {}", Pubkey::new_from_array(device_address));
+    Ok(())
+}
@@ -0,0 +1,37 @@
+use anchor_lang::prelude::*;
+use anchor_lang::solana_program::clock::Clock;
+use crate::state::{Marketplace, PAUSE_ALL};
+use crate::{ErrorCode, MarketplacePauseUpdated};
+
+#[derive(Accounts)]
+pub struct UnpauseMarketplace<'info> {
+    pub admin: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"marketplace",
+            marketplace.creator.as_ref(),
+            &marketplace.marketplace_id.to_le_bytes(),
+        ],
+        bump = marketplace.bump,
+        has_one = admin @ ErrorCode::AdminMismatch,
+    )]
+    pub marketplace: Account<'info, Marketplace>,
+}
+
+/// Clears the given `PAUSE_*` bits. Admin only; the guardian cannot unpause.
+pub fn handler(ctx: Context<UnpauseMarketplace>, flags: u8) -> Result<()> {
+    require!(flags != 0 && flags & !PAUSE_ALL == 0, ErrorCode::InvalidPauseFlags);
+
+    let marketplace = &mut ctx.accounts.marketplace;
+    marketplace.pause_flags &= !flags;
+
+    emit!(MarketplacePauseUpdated {
+        marketplace: marketplace.key(),
+        pause_flags: marketplace.pause_flags,
+        updated_by: ctx.accounts.admin.key(),
+        timestamp: Clock::get()?.unix_timestamp,
+    });
+    Ok(())
+}
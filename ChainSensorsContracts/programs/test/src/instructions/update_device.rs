@@ -0,0 +1,52 @@
+use anchor_lang::prelude::*;
+use light_sdk::light_system_accounts;
+use light_sdk_macros::LightTraits;
+use crate::certificate::{verify_certificate_held, CertificateProof};
+use crate::compressed_account_helpers::{rewrite_device, DeviceInput};
+use crate::state::{CompressedDeviceRegistry, DeviceMetadata};
+use crate::{ErrorCode, SPL_ACCOUNT_COMPRESSION_ID};
+
+#[light_system_accounts]
+#[derive(Accounts, LightTraits)]
+pub struct UpdateDevice<'info> {
+    #[account(mut)]
+    #[fee_payer]
+    pub owner: Signer<'info>,
+    /// CHECK: checked by cpi.
+    #[authority]
+    pub cpi_signer: AccountInfo<'info>,
+    #[self_program]
+    pub self_program: Program<'info, crate::program::Chainsensor>,
+    /// CHECK: certificate tree, required for certified devices; checked by verify_leaf.
+    pub certificate_tree: Option<UncheckedAccount<'info>>,
+    /// CHECK: address is checked.
+    #[account(address = SPL_ACCOUNT_COMPRESSION_ID)]
+    pub compression_program: Option<UncheckedAccount<'info>>,
+}
+
+pub fn handler<'info>(
+    ctx: Context<'_, '_, '_, 'info, UpdateDevice<'info>>,
+    device_address: [u8; 32],
+    device: DeviceInput,
+    metadata: DeviceMetadata,
+    certificate_proof: Option<CertificateProof>,
+) -> Result<()> {
+    metadata.validate()?;
+    require!(
+        device.device_registry.owner == ctx.accounts.owner.key(),
+        ErrorCode::DeviceOwnerMismatch
+    );
+    verify_certificate_held(
+        ctx.accounts.certificate_tree.as_deref(),
+        ctx.remaining_accounts,
+        &device.device_registry,
+        certificate_proof.as_ref(),
+    )?;
+
+    // Nullify the current version and write the updated one under the same address
+    let updated_registry = CompressedDeviceRegistry {
+        metadata,
+        ..device.device_registry.clone()
+    };
+    rewrite_device(&ctx, device_address, device, updated_registry)
+}
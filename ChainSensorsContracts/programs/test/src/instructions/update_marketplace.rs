@@ -0,0 +1,60 @@
+use anchor_lang::prelude::*;
+use anchor_lang::solana_program::clock::Clock;
+use crate::state::Marketplace;
+use crate::{validate_marketplace_name, validate_seller_fee, ErrorCode};
+
+#[derive(Accounts)]
+pub struct UpdateMarketplace<'info> {
+    pub admin: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"marketplace",
+            marketplace.creator.as_ref(),
+            &marketplace.marketplace_id.to_le_bytes(),
+        ],
+        bump = marketplace.bump,
+        has_one = admin @ ErrorCode::AdminMismatch,
+    )]
+    pub marketplace: Account<'info, Marketplace>,
+}
+
+#[event]
+pub struct MarketplaceUpdated {
+    pub marketplace: Pubkey,
+    pub name: String,
+    pub seller_fee: u16,
+    pub is_active: bool,
+    pub timestamp: i64,
+}
+
+/// Updates any of the provided fields; `None` leaves a field unchanged.
+pub fn handler(
+    ctx: Context<UpdateMarketplace>,
+    name: Option<String>,
+    seller_fee: Option<u16>,
+    is_active: Option<bool>,
+) -> Result<()> {
+    let marketplace = &mut ctx.accounts.marketplace;
+    if let Some(name) = name {
+        validate_marketplace_name(&name)?;
+        marketplace.name = name;
+    }
+    if let Some(seller_fee) = seller_fee {
+        validate_seller_fee(seller_fee)?;
+        marketplace.seller_fee = seller_fee;
+    }
+    if let Some(is_active) = is_active {
+        marketplace.is_active = is_active;
+    }
+
+    emit!(MarketplaceUpdated {
+        marketplace: marketplace.key(),
+        name: marketplace.name.clone(),
+        seller_fee: marketplace.seller_fee,
+        is_active: marketplace.is_active,
+        timestamp: Clock::get()?.unix_timestamp,
+    });
+    Ok(())
+}
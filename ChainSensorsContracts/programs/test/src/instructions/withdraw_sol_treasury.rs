@@ -0,0 +1,56 @@
+use anchor_lang::prelude::*;
+use anchor_lang::solana_program::clock::Clock;
+use crate::state::{Marketplace, SolTreasury, PAUSE_WITHDRAWALS};
+use crate::{ErrorCode, TreasuryWithdrawn};
+
+#[derive(Accounts)]
+pub struct WithdrawSolTreasury<'info> {
+    pub admin: Signer<'info>,
+
+    #[account(
+        seeds = [
+            b"marketplace",
+            marketplace.creator.as_ref(),
+            &marketplace.marketplace_id.to_le_bytes(),
+        ],
+        bump = marketplace.bump,
+        has_one = admin @ ErrorCode::AdminMismatch,
+        constraint = !marketplace.is_paused(PAUSE_WITHDRAWALS) @ ErrorCode::WithdrawalsPaused,
+    )]
+    pub marketplace: Account<'info, Marketplace>,
+
+    #[account(
+        mut,
+        seeds = [b"sol_treasury", marketplace.key().as_ref()],
+        bump = sol_treasury.bump,
+    )]
+    pub sol_treasury: Account<'info, SolTreasury>,
+
+    #[account(mut)]
+    pub destination: SystemAccount<'info>,
+}
+
+/// Transfers `amount` lamports from the SOL treasury, or everything above its
+/// rent-exempt minimum when `None`. Reported with the default pubkey as mint.
+pub fn handler(ctx: Context<WithdrawSolTreasury>, amount: Option<u64>) -> Result<()> {
+    let treasury = ctx.accounts.sol_treasury.to_account_info();
+    let rent_exempt = Rent::get()?.minimum_balance(treasury.data_len());
+    let available = treasury.lamports().saturating_sub(rent_exempt);
+    let amount = amount.unwrap_or(available);
+    require!(amount > 0, ErrorCode::InvalidWithdrawAmount);
+    require!(amount <= available, ErrorCode::InsufficientFunds);
+
+    // The treasury is program-owned, so lamports can be moved directly
+    **treasury.try_borrow_mut_lamports()? -= amount;
+    **ctx.accounts.destination.to_account_info().try_borrow_mut_lamports()? += amount;
+
+    emit!(TreasuryWithdrawn {
+        marketplace: ctx.accounts.marketplace.key(),
+        mint: Pubkey::default(),
+        destination: ctx.accounts.destination.key(),
+        amount,
+        remaining: available - amount,
+        timestamp: Clock::get()?.unix_timestamp,
+    });
+    Ok(())
+}
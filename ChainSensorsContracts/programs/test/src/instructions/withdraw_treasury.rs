@@ -0,0 +1,95 @@
+use anchor_lang::prelude::*;
+use anchor_lang::solana_program::clock::Clock;
+use anchor_spl::token_interface::{self, Mint, TokenAccount, TokenInterface};
+use crate::state::{Marketplace, PAUSE_WITHDRAWALS};
+use crate::ErrorCode;
+
+#[derive(Accounts)]
+pub struct WithdrawTreasury<'info> {
+    pub admin: Signer<'info>,
+
+    #[account(
+        seeds = [
+            b"marketplace",
+            marketplace.creator.as_ref(),
+            &marketplace.marketplace_id.to_le_bytes(),
+        ],
+        bump = marketplace.bump,
+        has_one = admin @ ErrorCode::AdminMismatch,
+        constraint = !marketplace.is_paused(PAUSE_WITHDRAWALS) @ ErrorCode::WithdrawalsPaused,
+    )]
+    pub marketplace: Account<'info, Marketplace>,
+
+    #[account(mint::token_program = token_program)]
+    pub mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        mut,
+        seeds = [b"treasury", marketplace.key().as_ref(), mint.key().as_ref()],
+        bump,
+        token::mint = mint,
+        token::token_program = token_program,
+    )]
+    pub treasury: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        token::mint = mint,
+        token::token_program = token_program,
+    )]
+    pub destination: InterfaceAccount<'info, TokenAccount>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[event]
+pub struct TreasuryWithdrawn {
+    pub marketplace: Pubkey,
+    pub mint: Pubkey,
+    pub destination: Pubkey,
+    pub amount: u64,
+    pub remaining: u64,
+    pub timestamp: i64,
+}
+
+/// Transfers `amount` from a mint's treasury, or the whole balance when `None`.
+/// Works for mints that have since been removed from the accepted list.
+pub fn handler(ctx: Context<WithdrawTreasury>, amount: Option<u64>) -> Result<()> {
+    let balance = ctx.accounts.treasury.amount;
+    let amount = amount.unwrap_or(balance);
+    require!(amount > 0, ErrorCode::InvalidWithdrawAmount);
+    require!(amount <= balance, ErrorCode::InsufficientFunds);
+
+    let marketplace_key = ctx.accounts.marketplace.key();
+    let mint_key = ctx.accounts.mint.key();
+    let treasury_seeds: &[&[u8]] = &[
+        b"treasury",
+        marketplace_key.as_ref(),
+        mint_key.as_ref(),
+        &[ctx.bumps.treasury],
+    ];
+    token_interface::transfer_checked(
+        CpiContext::new_with_signer(
+            ctx.accounts.token_program.to_account_info(),
+            token_interface::TransferChecked {
+                from:      ctx.accounts.treasury.to_account_info(),
+                mint:      ctx.accounts.mint.to_account_info(),
+                to:        ctx.accounts.destination.to_account_info(),
+                authority: ctx.accounts.treasury.to_account_info(),
+            },
+            &[treasury_seeds],
+        ),
+        amount,
+        ctx.accounts.mint.decimals,
+    )?;
+
+    emit!(TreasuryWithdrawn {
+        marketplace: marketplace_key,
+        mint: mint_key,
+        destination: ctx.accounts.destination.key(),
+        amount,
+        remaining: balance - amount,
+        timestamp: Clock::get()?.unix_timestamp,
+    });
+    Ok(())
+}
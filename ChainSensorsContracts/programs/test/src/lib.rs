@@ -1,161 +1,491 @@
 use anchor_lang::prelude::*;
 use anchor_lang::solana_program::clock::Clock;
-use anchor_spl::token::{Mint, Token};
-use light_sdk::{light_system_accounts, merkle_context::PackedAddressMerkleContext};
-use light_sdk_macros::LightTraits;
-use light_sdk::{
-    proof::CompressedProof,
-    verify::{verify, InstructionDataInvokeCpi},
-};
-use crate::state::CompressedDeviceRegistry;
-use crate::state::Marketplace;
+use anchor_spl::token_interface::{Mint, TokenAccount, TokenInterface};
+use light_sdk::merkle_context::PackedAddressMerkleContext;
+use light_sdk::proof::CompressedProof;
+use crate::certificate::CertificateProof;
+use crate::compressed_account_helpers::DeviceInput;
+use crate::state::{DeviceMetadata, DeviceOperator};
+use crate::state::{AcceptedMint, Marketplace, SolTreasury, REGISTRATION_OPEN};
 
 declare_id!("Fg6PaFpoGXkYsidMpWTK6W2BeZ7FEfcYkg476zPFsLnS");
 
 pub mod address;
+pub mod certificate;
 pub mod compressed_account_helpers;
+pub mod ed25519;
+pub mod instructions;
 pub mod state;
 
+pub use instructions::*;
+
 pub const CPI_AUTHORITY_PDA_SEED: &[u8] = b"cpi_authority";
+pub const CERTIFICATE_AUTHORITY_SEED: &[u8] = b"certificate_authority";
+pub const SPL_ACCOUNT_COMPRESSION_ID: Pubkey =
+    anchor_lang::solana_program::pubkey!("cmtDvXumGCrqC1Age74AVPhSRVXJMd8PJS91L8KbNCK");
+/// Light Protocol address tree every device address is derived in.
+pub const ADDRESS_MERKLE_TREE_ID: Pubkey =
+    anchor_lang::solana_program::pubkey!("amt1Ayt45jfbdw5YSo7iz6WZxUmnZsQTYXy82hVwyC2");
 
 #[program]
 pub mod chainsensor {
     use super::*;
-    use crate::{
-        address::create_address,
-        compressed_account_helpers::create_output_account,
-        state::{CompressedDeviceRegistry, Marketplace},
-    };
 
     pub fn initialize<'info>(
         ctx: Context<'_, '_, '_, 'info, Initialize<'info>>,
         name: String,
         seller_fee: u16,
+        marketplace_id: u64,
     ) -> Result<()> {
-        require!(name.len() <= 32, ErrorCode::NameTooLong);
-        require!(seller_fee <= 10000, ErrorCode::InvalidFee);
-        require!(
-            name.chars().all(|c| c.is_alphanumeric() || c == ' ' || c == '_'),
-            ErrorCode::InvalidNameChars
-        );
-        require!(!name.is_empty(), ErrorCode::NameEmpty);
-
-        let (treasury_pda, treasury_bump) = Pubkey::find_program_address(
-            &[b"treasury", ctx.accounts.admin.key().as_ref()],
-            &crate::ID,
-        );
-        require!(treasury_pda == *ctx.accounts.treasury.key, ErrorCode::InvalidTreasury);
+        validate_marketplace_name(&name)?;
+        validate_seller_fee(seller_fee)?;
+        validate_payment_mint(&ctx.accounts.usdc_mint.to_account_info())?;
 
+        let created_at = Clock::get()?.unix_timestamp;
         ctx.accounts.marketplace.set_inner(Marketplace {
             admin: ctx.accounts.admin.key(),
             treasury: ctx.accounts.treasury.key(),
-            treasury_bump,
+            treasury_bump: ctx.bumps.treasury,
             seller_fee,
             token_mint: ctx.accounts.usdc_mint.key(),
             is_active: true,
             bump: ctx.bumps.marketplace,
             name,
-            created_at: Clock::get()?.unix_timestamp,
+            created_at,
+            registration_policy: REGISTRATION_OPEN,
+            marketplace_id,
+            creator: ctx.accounts.admin.key(),
+            pending_admin: None,
+            pause_flags: 0,
+            guardian: None,
+        });
+        ctx.accounts.accepted_mint.set_inner(AcceptedMint {
+            marketplace: ctx.accounts.marketplace.key(),
+            mint: ctx.accounts.usdc_mint.key(),
+            treasury: ctx.accounts.treasury.key(),
+            treasury_bump: ctx.bumps.treasury,
+            added_at: created_at,
+            bump: ctx.bumps.accepted_mint,
+        });
+        ctx.accounts.sol_treasury.set_inner(SolTreasury {
+            marketplace: ctx.accounts.marketplace.key(),
+            bump: ctx.bumps.sol_treasury,
         });
         Ok(())
     }
 
     pub fn register_device<'info>(
-        ctx: Context<'_, '_, '_, 'info, GenericAccounts<'info>>,
+        ctx: Context<'_, '_, '_, 'info, RegisterDevice<'info>>,
         proof: CompressedProof,
         address_merkle_tree_root_index: u16,
         address_merkle_context: PackedAddressMerkleContext,
         merkle_tree_index: u8,
         bump: u8,
         device_id: [u8; 32],
-        ek_pubkey_hash: [u8; 32],
-        device_type: [u8; 32],
-        data_type: [u8; 32],
+        ek_pubkey: [u8; 32],
+        metadata: DeviceMetadata,
     ) -> Result<()> {
-        require!(!device_id.iter().all(|&x| x == 0), ErrorCode::DeviceIdEmpty);
-        require!(!device_type.iter().all(|&x| x == 0), ErrorCode::DeviceTypeTooLong);
-        require!(!data_type.iter().all(|&x| x == 0), ErrorCode::DataTypeTooLong);
-
-        let (new_address_params, address) = create_address(
-            ctx.accounts.signer.key(),
-            ctx.remaining_accounts,
-            address_merkle_context.address_merkle_tree_pubkey_index,
-            address_merkle_context.address_queue_pubkey_index,
+        instructions::register_device::handler(
+            ctx,
+            proof,
             address_merkle_tree_root_index,
-        );
-        let device_registry = CompressedDeviceRegistry {
-            owner: ctx.accounts.signer.key(),
-            marketplace: ctx.accounts.marketplace.key(),
-            device_id,
-            ek_pubkey_hash,
+            address_merkle_context,
+            merkle_tree_index,
             bump,
-            device_type,
-            created_at: Clock::get()?.unix_timestamp,
-            data_type,
-        };
+            device_id,
+            ek_pubkey,
+            metadata,
+        )
+    }
 
-        let output_compressed_account = create_output_account(
-            ctx.accounts.signer.key(),
+    pub fn register_devices<'info>(
+        ctx: Context<'_, '_, '_, 'info, RegisterDevice<'info>>,
+        proof: CompressedProof,
+        address_merkle_context: PackedAddressMerkleContext,
+        merkle_tree_index: u8,
+        bump: u8,
+        devices: Vec<DeviceRegistrationParams>,
+    ) -> Result<()> {
+        instructions::register_devices::handler(
+            ctx,
+            proof,
+            address_merkle_context,
             merkle_tree_index,
-            device_registry,
-            address,
-        )?;
-
-        let inputs = InstructionDataInvokeCpi {
-            cpi_context: None,
-            is_compress: false,
-            compress_or_decompress_lamports: None,
-            new_address_params: vec![new_address_params],
-            relay_fee: None,
-            input_compressed_accounts_with_merkle_context: Vec::new(),
-            output_compressed_accounts: vec![output_compressed_account],
-            proof: Some(proof),
-        };
-        let signer_seeds = [CPI_AUTHORITY_PDA_SEED, &[bump]];
-        verify(&ctx, &inputs, &[signer_seeds.as_slice()])?;
-        Ok(())
+            bump,
+            devices,
+        )
+    }
+
+    pub fn create_listing<'info>(
+        ctx: Context<'_, '_, '_, 'info, CreateListing<'info>>,
+        listing_id: String,
+        device_address: [u8; 32],
+        data_cid: String,
+        price_per_unit: u64,
+        device_id: String,
+        total_data_units: u64,
+        expires_at: Option<i64>,
+        settlement_mode: u8,
+        device: DeviceInput,
+        certificate_proof: Option<CertificateProof>,
+    ) -> Result<()> {
+        instructions::create_listing::handler(
+            ctx,
+            listing_id,
+            device_address,
+            data_cid,
+            price_per_unit,
+            device_id,
+            total_data_units,
+            expires_at,
+            settlement_mode,
+            device,
+            certificate_proof,
+        )
+    }
+
+    pub fn cancel_listing<'info>(
+        ctx: Context<'_, '_, '_, 'info, CancelListing<'info>>,
+        listing_id: String,
+        device: DeviceInput,
+        certificate_proof: Option<CertificateProof>,
+    ) -> Result<()> {
+        instructions::cancel_listing::handler(ctx, listing_id, device, certificate_proof)
+    }
+
+    pub fn purchase_listing<'info>(
+        ctx: Context<'_, '_, '_, 'info, PurchaseListing<'info>>,
+        listing_id: String,
+        units_requested: u64,
+        device: DeviceInput,
+        certificate_proof: Option<CertificateProof>,
+    ) -> Result<()> {
+        instructions::purchase_listing::handler(
+            ctx,
+            listing_id,
+            units_requested,
+            device,
+            certificate_proof,
+        )
+    }
+
+    pub fn set_registration_policy(
+        ctx: Context<SetRegistrationPolicy>,
+        registration_policy: u8,
+    ) -> Result<()> {
+        instructions::set_registration_policy::handler(ctx, registration_policy)
+    }
+
+    pub fn allowlist_seller(ctx: Context<AllowlistSeller>, seller: Pubkey) -> Result<()> {
+        instructions::allowlist_seller::handler(ctx, seller)
+    }
+
+    pub fn revoke_seller(ctx: Context<RevokeSeller>, seller: Pubkey) -> Result<()> {
+        instructions::revoke_seller::handler(ctx, seller)
+    }
+
+    pub fn add_accepted_mint(ctx: Context<AddAcceptedMint>) -> Result<()> {
+        instructions::add_accepted_mint::handler(ctx)
+    }
+
+    pub fn remove_accepted_mint(ctx: Context<RemoveAcceptedMint>) -> Result<()> {
+        instructions::remove_accepted_mint::handler(ctx)
+    }
+
+    pub fn set_fee_schedule(
+        ctx: Context<SetFeeSchedule>,
+        data_type: [u8; 32],
+        seller_fee: u16,
+        tier_fees: Vec<u16>,
+    ) -> Result<()> {
+        instructions::set_fee_schedule::handler(ctx, data_type, seller_fee, tier_fees)
+    }
+
+    pub fn remove_fee_schedule(ctx: Context<RemoveFeeSchedule>, data_type: [u8; 32]) -> Result<()> {
+        instructions::remove_fee_schedule::handler(ctx, data_type)
+    }
+
+    pub fn set_seller_tier(ctx: Context<SetSellerTier>, seller: Pubkey, tier: u8) -> Result<()> {
+        instructions::set_seller_tier::handler(ctx, seller, tier)
+    }
+
+    pub fn update_device<'info>(
+        ctx: Context<'_, '_, '_, 'info, UpdateDevice<'info>>,
+        device_address: [u8; 32],
+        device: DeviceInput,
+        metadata: DeviceMetadata,
+        certificate_proof: Option<CertificateProof>,
+    ) -> Result<()> {
+        instructions::update_device::handler(
+            ctx,
+            device_address,
+            device,
+            metadata,
+            certificate_proof,
+        )
+    }
+
+    pub fn deactivate_device<'info>(
+        ctx: Context<'_, '_, '_, 'info, SetDeviceStatus<'info>>,
+        device_address: [u8; 32],
+        device: DeviceInput,
+        certificate_proof: Option<CertificateProof>,
+    ) -> Result<()> {
+        instructions::device_status::handler(
+            ctx,
+            device_address,
+            device,
+            false,
+            certificate_proof,
+        )
+    }
+
+    pub fn reactivate_device<'info>(
+        ctx: Context<'_, '_, '_, 'info, SetDeviceStatus<'info>>,
+        device_address: [u8; 32],
+        device: DeviceInput,
+        certificate_proof: Option<CertificateProof>,
+    ) -> Result<()> {
+        instructions::device_status::handler(
+            ctx,
+            device_address,
+            device,
+            true,
+            certificate_proof,
+        )
+    }
+
+    pub fn transfer_device<'info>(
+        ctx: Context<'_, '_, '_, 'info, TransferDevice<'info>>,
+        device_address: [u8; 32],
+        device: DeviceInput,
+        new_owner: Pubkey,
+    ) -> Result<()> {
+        instructions::transfer_device::handler(ctx, device_address, device, new_owner)
+    }
+
+    pub fn close_device<'info>(
+        ctx: Context<'_, '_, '_, 'info, CloseDevice<'info>>,
+        device_address: [u8; 32],
+        device: DeviceInput,
+        certificate_proof: Option<CertificateProof>,
+    ) -> Result<()> {
+        instructions::close_device::handler(ctx, device_address, device, certificate_proof)
+    }
+
+    pub fn rotate_device_key<'info>(
+        ctx: Context<'_, '_, '_, 'info, RotateDeviceKey<'info>>,
+        device_address: [u8; 32],
+        device: DeviceInput,
+        old_ek_pubkey: Option<[u8; 32]>,
+        new_ek_pubkey: [u8; 32],
+        certificate_proof: Option<CertificateProof>,
+    ) -> Result<()> {
+        instructions::rotate_device_key::handler(
+            ctx,
+            device_address,
+            device,
+            old_ek_pubkey,
+            new_ek_pubkey,
+            certificate_proof,
+        )
+    }
+
+    pub fn mint_device_certificate<'info>(
+        ctx: Context<'_, '_, '_, 'info, MintDeviceCertificate<'info>>,
+        device_address: [u8; 32],
+        device: DeviceInput,
+        uri_prefix: String,
+    ) -> Result<()> {
+        instructions::mint_device_certificate::handler(ctx, device_address, device, uri_prefix)
+    }
+
+    pub fn claim_device<'info>(
+        ctx: Context<'_, '_, '_, 'info, ClaimDevice<'info>>,
+        device_address: [u8; 32],
+        device: DeviceInput,
+        certificate_proof: CertificateProof,
+    ) -> Result<()> {
+        instructions::claim_device::handler(ctx, device_address, device, certificate_proof)
+    }
+
+    pub fn set_device_operator<'info>(
+        ctx: Context<'_, '_, '_, 'info, SetDeviceOperator<'info>>,
+        device_address: [u8; 32],
+        device: DeviceInput,
+        operator: Pubkey,
+        price_floor: u64,
+        certificate_proof: Option<CertificateProof>,
+    ) -> Result<()> {
+        instructions::device_operator::handler(
+            ctx,
+            device_address,
+            device,
+            Some(DeviceOperator {
+                key: operator,
+                price_floor,
+            }),
+            certificate_proof,
+        )
+    }
+
+    pub fn clear_device_operator<'info>(
+        ctx: Context<'_, '_, '_, 'info, SetDeviceOperator<'info>>,
+        device_address: [u8; 32],
+        device: DeviceInput,
+        certificate_proof: Option<CertificateProof>,
+    ) -> Result<()> {
+        instructions::device_operator::handler(
+            ctx,
+            device_address,
+            device,
+            None,
+            certificate_proof,
+        )
+    }
+
+    pub fn sync_device_heartbeat<'info>(
+        ctx: Context<'_, '_, '_, 'info, SyncDeviceHeartbeat<'info>>,
+        device_address: [u8; 32],
+        device: DeviceInput,
+        certificate_proof: Option<CertificateProof>,
+    ) -> Result<()> {
+        instructions::sync_device_heartbeat::handler(ctx, device_address, device, certificate_proof)
+    }
+
+    pub fn device_heartbeat(ctx: Context<RecordHeartbeat>) -> Result<()> {
+        instructions::device_heartbeat::handler(ctx)
+    }
+
+    pub fn commit_data_batch(
+        ctx: Context<CommitDataBatch>,
+        merkle_root: [u8; 32],
+        blob_id: String,
+        window_start: i64,
+        window_end: i64,
+        reading_count: u64,
+    ) -> Result<()> {
+        instructions::commit_data_batch::handler(
+            ctx,
+            merkle_root,
+            blob_id,
+            window_start,
+            window_end,
+            reading_count,
+        )
+    }
+
+    pub fn ban_device(ctx: Context<BanDevice>, device_address: [u8; 32], reason: u8) -> Result<()> {
+        instructions::ban_device::handler(ctx, device_address, reason)
+    }
+
+    pub fn unban_device(ctx: Context<UnbanDevice>, device_address: [u8; 32]) -> Result<()> {
+        instructions::unban_device::handler(ctx, device_address)
+    }
+
+    pub fn update_marketplace(
+        ctx: Context<UpdateMarketplace>,
+        name: Option<String>,
+        seller_fee: Option<u16>,
+        is_active: Option<bool>,
+    ) -> Result<()> {
+        instructions::update_marketplace::handler(ctx, name, seller_fee, is_active)
+    }
+
+    pub fn propose_admin(ctx: Context<ProposeAdmin>, new_admin: Option<Pubkey>) -> Result<()> {
+        instructions::propose_admin::handler(ctx, new_admin)
+    }
+
+    pub fn accept_admin(ctx: Context<AcceptAdmin>) -> Result<()> {
+        instructions::accept_admin::handler(ctx)
+    }
+
+    pub fn withdraw_treasury(ctx: Context<WithdrawTreasury>, amount: Option<u64>) -> Result<()> {
+        instructions::withdraw_treasury::handler(ctx, amount)
+    }
+
+    pub fn set_guardian(ctx: Context<SetGuardian>, guardian: Option<Pubkey>) -> Result<()> {
+        instructions::set_guardian::handler(ctx, guardian)
+    }
+
+    pub fn pause_marketplace(ctx: Context<PauseMarketplace>, flags: u8) -> Result<()> {
+        instructions::pause_marketplace::handler(ctx, flags)
+    }
+
+    pub fn unpause_marketplace(ctx: Context<UnpauseMarketplace>, flags: u8) -> Result<()> {
+        instructions::unpause_marketplace::handler(ctx, flags)
+    }
+
+    pub fn withdraw_sol_treasury(
+        ctx: Context<WithdrawSolTreasury>,
+        amount: Option<u64>,
+    ) -> Result<()> {
+        instructions::withdraw_sol_treasury::handler(ctx, amount)
     }
-    
 }
 
-#[light_system_accounts]
-#[derive(Accounts, LightTraits)]
-pub struct GenericAccounts<'info> {
-    #[account(mut)]
-    #[fee_payer]
-    pub signer: Signer<'info>,
-    /// CHECK: checked by cpi.
-    #[authority]
-    pub cpi_signer: AccountInfo<'info>,
-    #[self_program]
-    pub self_program: Program<'info, crate::program::Chainsensor>,
-    #[account(
-        seeds = [b"marketplace", signer.key().as_ref()],
-        bump,
-        constraint = marketplace.is_active @ ErrorCode::MarketplaceInactive,
-    )]
-    pub marketplace: Account<'info, Marketplace>,
+pub fn validate_marketplace_name(name: &str) -> Result<()> {
+    require!(name.len() <= 32, ErrorCode::NameTooLong);
+    require!(
+        name.chars().all(|c| c.is_alphanumeric() || c == ' ' || c == '_'),
+        ErrorCode::InvalidNameChars
+    );
+    require!(!name.is_empty(), ErrorCode::NameEmpty);
+    Ok(())
+}
+
+pub fn validate_seller_fee(seller_fee: u16) -> Result<()> {
+    require!(seller_fee <= 10000, ErrorCode::InvalidFee);
+    Ok(())
 }
 
 #[derive(Accounts)]
-#[instruction(name: String)]
+#[instruction(name: String, seller_fee: u16, marketplace_id: u64)]
 pub struct Initialize<'info> {
     #[account(mut)]
     pub admin: Signer<'info>,
+    // Seeding by the creator as well keeps others from squatting on an id
     #[account(
         init,
         payer = admin,
-        seeds = [b"marketplace", admin.key().as_ref()],
+        seeds = [b"marketplace", admin.key().as_ref(), &marketplace_id.to_le_bytes()],
         bump,
         space = 8 + Marketplace::INIT_SPACE,
     )]
     pub marketplace: Account<'info, Marketplace>,
-    #[account(mut)]
-    /// CHECK: Manually validated as a token account.
-    pub treasury: UncheckedAccount<'info>,
-    /// CHECK: Manually validated as a mint.
-    pub usdc_mint: UncheckedAccount<'info>,
-    pub token_program: Program<'info, Token>,
+    /// Fee treasury, a token account owned by its own PDA so the program can sign withdrawals.
+    #[account(
+        init,
+        payer = admin,
+        seeds = [b"treasury", marketplace.key().as_ref(), usdc_mint.key().as_ref()],
+        bump,
+        token::mint = usdc_mint,
+        token::authority = treasury,
+        token::token_program = token_program,
+    )]
+    pub treasury: InterfaceAccount<'info, TokenAccount>,
+    /// The default payment mint is accepted from the start.
+    #[account(
+        init,
+        payer = admin,
+        seeds = [b"accepted_mint", marketplace.key().as_ref(), usdc_mint.key().as_ref()],
+        bump,
+        space = 8 + AcceptedMint::INIT_SPACE,
+    )]
+    pub accepted_mint: Account<'info, AcceptedMint>,
+    /// Lamport fee treasury for SOL-settled listings.
+    #[account(
+        init,
+        payer = admin,
+        seeds = [b"sol_treasury", marketplace.key().as_ref()],
+        bump,
+        space = 8 + SolTreasury::INIT_SPACE,
+    )]
+    pub sol_treasury: Account<'info, SolTreasury>,
+    /// Default payment mint; SPL Token or Token-2022.
+    #[account(mint::token_program = token_program)]
+    pub usdc_mint: InterfaceAccount<'info, Mint>,
+    pub token_program: Interface<'info, TokenInterface>,
     pub system_program: Program<'info, System>,
     pub rent: Sysvar<'info, Rent>,
 }
@@ -200,6 +530,174 @@ pub enum ErrorCode {
     InvalidListingPda,
 
     // Listing cancellation errors
-    #[msg("Only the seller can cancel the listing")]
+    #[msg("Only the device owner or its operator can cancel the listing")]
     CancelUnauthorized,
+
+    // Listing purchase errors
+    #[msg("Listing is not active")]
+    ListingNotActive,
+    #[msg("Cannot buy your own listing")]
+    CannotBuyOwnListing,
+    #[msg("Listing has expired")]
+    ListingExpired,
+    #[msg("Invalid number of units requested")]
+    InvalidUnitsRequested,
+    #[msg("Insufficient funds")]
+    InsufficientFunds,
+    #[msg("Insufficient units available")]
+    InsufficientUnits,
+    #[msg("Math overflow")]
+    MathOverflow,
+    #[msg("Listing does not belong to this marketplace")]
+    ListingMarketplaceMismatch,
+    #[msg("Device changed owner after the listing was created")]
+    ListingSellerNotOwner,
+
+    // Compressed device checks
+    #[msg("Signer does not own the device")]
+    DeviceOwnerMismatch,
+    #[msg("Device is registered on a different marketplace")]
+    DeviceMarketplaceMismatch,
+    #[msg("Device ID does not match the registered device")]
+    DeviceIdMismatch,
+
+    // Address errors
+    #[msg("Address Merkle tree is not the pinned device address tree")]
+    InvalidAddressTree,
+
+    // Registration policy errors
+    #[msg("Unknown registration policy")]
+    InvalidRegistrationPolicy,
+    #[msg("Seller is not on the marketplace allowlist")]
+    SellerNotAllowlisted,
+    #[msg("Marketplace admin must co-sign device registration")]
+    AdminSignatureRequired,
+    #[msg("Signer is not the marketplace admin")]
+    AdminMismatch,
+
+    // Device status errors
+    #[msg("Device is not active")]
+    DeviceInactive,
+    #[msg("Device is already active")]
+    DeviceAlreadyActive,
+    #[msg("Signer is neither the device owner nor the marketplace admin")]
+    DeviceStatusUnauthorized,
+    #[msg("Device was deactivated by the marketplace admin and only the admin can reactivate it")]
+    DeactivatedByAdmin,
+
+    // Device transfer errors
+    #[msg("New owner must differ from the current owner")]
+    InvalidNewOwner,
+
+    // Device close errors
+    #[msg("Device still has active listings")]
+    DeviceHasActiveListings,
+
+    // Device key attestation errors
+    #[msg("Missing Ed25519 signature instruction from the device key")]
+    MissingDeviceSignature,
+    #[msg("Ed25519 instruction does not match the device key or message")]
+    InvalidDeviceSignature,
+    #[msg("Device key does not match the registered key hash")]
+    DeviceKeyMismatch,
+
+    // Batch registration errors
+    #[msg("Device batch is empty or exceeds the maximum batch size")]
+    InvalidBatchSize,
+
+    // Device metadata errors
+    #[msg("Latitude or longitude out of range")]
+    InvalidLocation,
+    #[msg("Sampling interval must be greater than zero")]
+    InvalidSamplingInterval,
+
+    // Device certificate errors
+    #[msg("Device already has a certificate")]
+    CertificateAlreadyMinted,
+    #[msg("Device has no certificate")]
+    CertificateNotMinted,
+    #[msg("Certificate does not belong to this device")]
+    CertificateMismatch,
+    #[msg("Certificate URI prefix is too long")]
+    CertificateUriTooLong,
+    #[msg("Certified devices are transferred through their certificate NFT")]
+    DeviceCertified,
+    #[msg("Certified devices need a proof that the owner still holds the certificate")]
+    CertificateProofRequired,
+    #[msg("Invalid Bubblegum tree config")]
+    InvalidCertificateTree,
+
+    // Device operator errors
+    #[msg("Operator cannot be the default pubkey or the device owner")]
+    InvalidOperator,
+    #[msg("Signer is neither the device owner nor its operator")]
+    NotOwnerOrOperator,
+    #[msg("Price is below the owner-approved floor for the operator")]
+    PriceBelowOperatorFloor,
+
+    // Heartbeat errors
+    #[msg("Heartbeat must be signed by the device key or its operator")]
+    HeartbeatUnauthorized,
+
+    // Data batch errors
+    #[msg("Data batch Merkle root cannot be empty")]
+    DataBatchRootEmpty,
+    #[msg("Blob ID must be between 1 and 64 characters")]
+    InvalidBlobId,
+    #[msg("Invalid data batch time window")]
+    InvalidDataWindow,
+    #[msg("Listing data CID does not match the data batch blob ID")]
+    DataBatchCidMismatch,
+    #[msg("Data batch must contain at least one reading")]
+    InvalidReadingCount,
+
+    // Device ban errors
+    #[msg("Device is banned from this marketplace")]
+    DeviceBanned,
+
+    // Admin transfer errors
+    #[msg("Signer is not the pending marketplace admin")]
+    NotPendingAdmin,
+    #[msg("Proposed admin cannot be the default pubkey")]
+    InvalidNewAdmin,
+
+    // Treasury errors
+    #[msg("Mint does not match the marketplace payment mint")]
+    InvalidMint,
+    #[msg("Withdrawal amount must be greater than zero")]
+    InvalidWithdrawAmount,
+    #[msg("Mint has a Token-2022 extension that settlement does not support")]
+    UnsupportedMintExtension,
+
+    // Settlement errors
+    #[msg("Token settlement accounts are required for this listing")]
+    MissingTokenAccounts,
+    #[msg("Seller and SOL treasury accounts are required for this listing")]
+    MissingSolAccounts,
+    #[msg("Invalid settlement mode")]
+    InvalidSettlementMode,
+    #[msg("SOL listings must not pass an accepted mint")]
+    UnexpectedAcceptedMint,
+    #[msg("SOL payout would leave the seller below the rent-exempt minimum")]
+    SellerNotRentExempt,
+
+    // Fee schedule errors
+    #[msg("Fee schedule data type cannot be empty")]
+    DataTypeEmpty,
+    #[msg("Seller tier exceeds the maximum tier")]
+    InvalidSellerTier,
+
+    // Pause errors
+    #[msg("Device registration is paused")]
+    RegistrationPaused,
+    #[msg("Listing creation is paused")]
+    ListingPaused,
+    #[msg("Purchases are paused")]
+    PurchasesPaused,
+    #[msg("Treasury withdrawals are paused")]
+    WithdrawalsPaused,
+    #[msg("Pause flags must be non-zero and within the known flags")]
+    InvalidPauseFlags,
+    #[msg("Signer is neither the marketplace admin nor its guardian")]
+    NotAdminOrGuardian,
 }
\ No newline at end of file
@@ -1,4 +1,5 @@
 use anchor_lang::prelude::*;
+use anchor_lang::solana_program::hash::hash;
 use light_account_checks::discriminator::Discriminator;
 use light_sdk_macros::{LightDiscriminator, LightHasher};
 
@@ -15,6 +16,95 @@ pub struct Marketplace {
     #[max_len(32)]
     pub name: String,
     pub created_at: i64,
+    pub registration_policy: u8,
+    pub marketplace_id: u64,
+    pub creator: Pubkey, // seeds the PDA with marketplace_id; never changes
+    pub pending_admin: Option<Pubkey>,
+    pub pause_flags: u8,
+    pub guardian: Option<Pubkey>, // may pause, but not unpause
+}
+
+impl Marketplace {
+    pub fn is_paused(&self, flag: u8) -> bool {
+        self.pause_flags & flag != 0
+    }
+}
+
+// Marketplace.pause_flags bits
+pub const PAUSE_REGISTRATION: u8 = 1 << 0;
+pub const PAUSE_LISTING: u8 = 1 << 1;
+pub const PAUSE_PURCHASES: u8 = 1 << 2;
+pub const PAUSE_WITHDRAWALS: u8 = 1 << 3;
+pub const PAUSE_ALL: u8 = PAUSE_REGISTRATION | PAUSE_LISTING | PAUSE_PURCHASES | PAUSE_WITHDRAWALS;
+
+// Marketplace.registration_policy values
+pub const REGISTRATION_OPEN: u8 = 0;
+pub const REGISTRATION_ALLOWLIST: u8 = 1;
+pub const REGISTRATION_ADMIN_COSIGN: u8 = 2;
+
+#[account]
+#[derive(InitSpace)]
+pub struct AllowlistEntry {
+    pub marketplace: Pubkey,
+    pub seller: Pubkey,
+    pub added_at: i64,
+    pub bump: u8,
+}
+
+/// A payment mint the marketplace settles in, with its fee treasury.
+#[account]
+#[derive(InitSpace)]
+pub struct AcceptedMint {
+    pub marketplace: Pubkey,
+    pub mint: Pubkey,
+    pub treasury: Pubkey,
+    pub treasury_bump: u8,
+    pub added_at: i64,
+    pub bump: u8,
+}
+
+/// Seller fee for devices of one `data_type`, at `[b"fee_schedule", marketplace, data_type]`.
+/// Overrides `Marketplace.seller_fee` while it exists.
+#[account]
+#[derive(InitSpace)]
+pub struct FeeSchedule {
+    pub marketplace: Pubkey,
+    pub data_type: [u8; 32],
+    pub seller_fee: u16, // basis points for tier 0 sellers
+    #[max_len(MAX_SELLER_TIER)]
+    pub tier_fees: Vec<u16>, // tier_fees[n - 1] applies to tier n; missing tiers use seller_fee
+    pub updated_at: i64,
+    pub bump: u8,
+}
+
+impl FeeSchedule {
+    pub fn fee_for_tier(&self, tier: u8) -> u16 {
+        match tier {
+            0 => self.seller_fee,
+            n => self.tier_fees.get(n as usize - 1).copied().unwrap_or(self.seller_fee),
+        }
+    }
+}
+
+/// Admin-assigned seller tier, at `[b"seller_tier", marketplace, seller]`.
+/// Sellers without one are tier 0.
+#[account]
+#[derive(InitSpace)]
+pub struct SellerTier {
+    pub marketplace: Pubkey,
+    pub seller: Pubkey,
+    pub tier: u8,
+    pub bump: u8,
+}
+
+pub const MAX_SELLER_TIER: usize = 4;
+
+/// Lamport fee treasury for SOL-settled listings, at `[b"sol_treasury", marketplace]`.
+#[account]
+#[derive(InitSpace)]
+pub struct SolTreasury {
+    pub marketplace: Pubkey,
+    pub bump: u8,
 }
 
 #[derive(
@@ -35,12 +125,149 @@ pub struct CompressedDeviceRegistry {
     pub device_id: [u8; 32], // Fixed-size array
     #[hash]
     pub ek_pubkey_hash: [u8; 32],
-    pub bump: u8,
-    #[hash]
-    pub device_type: [u8; 32], // Fixed-size array
     pub created_at: i64,
+    #[nested]
+    pub metadata: DeviceMetadata,
+    pub is_active: bool,
+    pub deactivated_at: i64, // 0 while active
+    pub deactivated_by_admin: bool,
+    pub active_listings: u32,
+    pub key_rotations: u32,
     #[hash]
-    pub data_type: [u8; 32], // Fixed-size array
+    pub certificate: Pubkey, // cNFT asset ID, default until minted
+    #[nested]
+    pub operator: DeviceOperator,
+}
+
+/// Device characteristics hashed into the compressed record so buyers can
+/// verify them on-chain.
+#[derive(
+    Clone,
+    Debug,
+    Default,
+    anchor_lang::AnchorDeserialize,
+    anchor_lang::AnchorSerialize,
+    LightHasher,
+)]
+pub struct DeviceMetadata {
+    #[hash]
+    pub device_type: [u8; 32],
+    #[hash]
+    pub data_type: [u8; 32],
+    #[hash]
+    pub data_unit: [u8; 32],
+    #[hash]
+    pub firmware_model: [u8; 32],
+    pub latitude: i32,  // degrees * 1e7
+    pub longitude: i32, // degrees * 1e7
+    pub sampling_interval: u32, // seconds between readings
+}
+
+impl DeviceMetadata {
+    pub fn validate(&self) -> Result<()> {
+        require!(!self.device_type.iter().all(|&x| x == 0), crate::ErrorCode::DeviceTypeTooLong);
+        require!(!self.data_type.iter().all(|&x| x == 0), crate::ErrorCode::DataTypeTooLong);
+        require!(
+            (-900_000_000..=900_000_000).contains(&self.latitude)
+                && (-1_800_000_000..=1_800_000_000).contains(&self.longitude),
+            crate::ErrorCode::InvalidLocation
+        );
+        require!(self.sampling_interval > 0, crate::ErrorCode::InvalidSamplingInterval);
+        Ok(())
+    }
+}
+
+/// Day-to-day operator delegated by the device owner. A default `key` means
+/// no operator is set.
+#[derive(
+    Clone,
+    Debug,
+    Default,
+    anchor_lang::AnchorDeserialize,
+    anchor_lang::AnchorSerialize,
+    LightHasher,
+)]
+pub struct DeviceOperator {
+    #[hash]
+    pub key: Pubkey,
+    pub price_floor: u64, // minimum price_per_unit for operator-created listings
+}
+
+impl CompressedDeviceRegistry {
+    pub fn is_owner_or_operator(&self, signer: &Pubkey) -> bool {
+        *signer == self.owner
+            || (self.operator.key != Pubkey::default() && *signer == self.operator.key)
+    }
+}
+
+/// Companion PDA at `[b"heartbeat", device_address]` tracking device liveness.
+/// `ek_pubkey_hash` and `operator` are cached from the compressed record by
+/// `sync_device_heartbeat` so heartbeats don't need a validity proof; every
+/// instruction that changes them on the record refreshes the cache too.
+#[account]
+#[derive(InitSpace)]
+pub struct DeviceHeartbeat {
+    pub device: Pubkey,
+    pub ek_pubkey_hash: [u8; 32],
+    pub operator: Pubkey,
+    pub last_seen_slot: u64,
+    pub last_seen_at: i64,
+    pub heartbeat_count: u64,
+    pub bump: u8,
+}
+
+impl DeviceHeartbeat {
+    pub fn is_device_or_operator(&self, signer: &Pubkey) -> bool {
+        hash(signer.as_ref()).to_bytes() == self.ek_pubkey_hash
+            || (self.operator != Pubkey::default() && *signer == self.operator)
+    }
+
+    /// Overwrites the cached key and operator of the heartbeat PDA in `info`.
+    /// Does nothing when the PDA was never created.
+    pub fn refresh(info: &AccountInfo, ek_pubkey_hash: [u8; 32], operator: Pubkey) -> Result<()> {
+        if info.data_is_empty() {
+            return Ok(());
+        }
+        require_keys_eq!(
+            *info.owner,
+            crate::ID,
+            anchor_lang::error::ErrorCode::AccountOwnedByWrongProgram
+        );
+        let mut data = info.try_borrow_mut_data()?;
+        let mut heartbeat = DeviceHeartbeat::try_deserialize(&mut &data[..])?;
+        heartbeat.ek_pubkey_hash = ek_pubkey_hash;
+        heartbeat.operator = operator;
+        heartbeat.try_serialize(&mut &mut data[..])?;
+        Ok(())
+    }
+}
+
+/// Commitment to a batch of readings, at `[b"data_batch", device_address, merkle_root]`.
+#[account]
+#[derive(InitSpace)]
+pub struct DataBatch {
+    pub device: Pubkey,
+    pub merkle_root: [u8; 32],
+    #[max_len(64)]
+    pub blob_id: String, // Walrus blob id
+    pub window_start: i64,
+    pub window_end: i64,
+    pub reading_count: u64,
+    pub submitted_by: Pubkey,
+    pub created_at: i64,
+    pub bump: u8,
+}
+
+/// Admin ban on a device, at `[b"ban", marketplace, device_address]`.
+/// The device is banned for as long as this account exists.
+#[account]
+#[derive(InitSpace)]
+pub struct DeviceBan {
+    pub marketplace: Pubkey,
+    pub device: Pubkey,
+    pub reason: u8, // admin-defined reason code
+    pub banned_at: i64,
+    pub bump: u8,
 }
 
 #[account]
@@ -67,8 +294,15 @@ pub struct ListingState {
     pub buyer:           Option<Pubkey>,
     pub purchase_count:  u64,
     pub sold_at:         Option<i64>,
+    pub data_batch:      Option<Pubkey>,
+    pub settlement_mode: u8,
+    pub data_type:       [u8; 32], // device data type at listing time; selects the fee schedule
 }
 
+// ListingState.settlement_mode values
+pub const SETTLEMENT_TOKEN: u8 = 0; // priced in `token_mint` base units
+pub const SETTLEMENT_SOL: u8 = 1;   // priced in lamports; `token_mint` is unset
+
 #[account]
 #[derive(InitSpace)]
 pub struct PurchaseRecord {
@@ -82,6 +316,95 @@ pub struct PurchaseRecord {
     pub price_paid: u64,
     // Marketplace fee amount
     pub fee: u64,
+    // Fee rate applied, in basis points
+    pub fee_bps: u16,
+    // Amounts that actually arrived, net of any Token-2022 transfer fee
+    pub seller_received: u64,
+    pub treasury_received: u64,
     // Unix timestamp of the purchase
     pub timestamp: i64,
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ErrorCode;
+
+    fn metadata() -> DeviceMetadata {
+        let mut device_type = [0u8; 32];
+        device_type[..7].copy_from_slice(b"weather");
+        let mut data_type = [0u8; 32];
+        data_type[..11].copy_from_slice(b"temperature");
+        DeviceMetadata {
+            device_type,
+            data_type,
+            latitude: 525_200_000,
+            longitude: 134_050_000,
+            sampling_interval: 60,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn metadata_accepts_valid_values() {
+        assert!(metadata().validate().is_ok());
+        let edge = DeviceMetadata {
+            latitude: -900_000_000,
+            longitude: 1_800_000_000,
+            ..metadata()
+        };
+        assert!(edge.validate().is_ok());
+    }
+
+    #[test]
+    fn metadata_requires_device_and_data_type() {
+        let no_device_type = DeviceMetadata {
+            device_type: [0u8; 32],
+            ..metadata()
+        };
+        assert_eq!(no_device_type.validate().unwrap_err(), ErrorCode::DeviceTypeTooLong.into());
+        let no_data_type = DeviceMetadata {
+            data_type: [0u8; 32],
+            ..metadata()
+        };
+        assert_eq!(no_data_type.validate().unwrap_err(), ErrorCode::DataTypeTooLong.into());
+    }
+
+    #[test]
+    fn metadata_rejects_out_of_range_location() {
+        for (latitude, longitude) in [(900_000_001, 0), (-900_000_001, 0), (0, 1_800_000_001)] {
+            let metadata = DeviceMetadata {
+                latitude,
+                longitude,
+                ..metadata()
+            };
+            assert_eq!(metadata.validate().unwrap_err(), ErrorCode::InvalidLocation.into());
+        }
+    }
+
+    #[test]
+    fn metadata_rejects_zero_sampling_interval() {
+        let metadata = DeviceMetadata {
+            sampling_interval: 0,
+            ..metadata()
+        };
+        assert_eq!(metadata.validate().unwrap_err(), ErrorCode::InvalidSamplingInterval.into());
+    }
+
+    #[test]
+    fn fee_for_tier_falls_back_to_seller_fee() {
+        let schedule = FeeSchedule {
+            marketplace: Pubkey::default(),
+            data_type: [0u8; 32],
+            seller_fee: 500,
+            tier_fees: vec![400, 300],
+            updated_at: 0,
+            bump: 255,
+        };
+        assert_eq!(schedule.fee_for_tier(0), 500);
+        assert_eq!(schedule.fee_for_tier(1), 400);
+        assert_eq!(schedule.fee_for_tier(2), 300);
+        assert_eq!(schedule.fee_for_tier(3), 500);
+        assert_eq!(schedule.fee_for_tier(MAX_SELLER_TIER as u8), 500);
+    }
+}
@@ -156,379 +156,4372 @@
 // }
 #![cfg(feature = "test-sbf")]
 
-use anchor_lang::AccountDeserialize;
+use anchor_lang::{AccountDeserialize, AnchorDeserialize, InstructionData, ToAccountMetas};
+use anchor_spl::associated_token::spl_associated_token_account::instruction::create_associated_token_account_idempotent;
+use anchor_spl::associated_token::{
+    get_associated_token_address, get_associated_token_address_with_program_id,
+};
+use anchor_spl::token_2022::spl_token_2022;
+use anchor_spl::token_2022::spl_token_2022::extension::{
+    transfer_fee, transfer_hook, ExtensionType,
+};
+use anchor_spl::token_interface::TokenAccount;
+use chainsensor::certificate::CertificateProof;
+use chainsensor::compressed_account_helpers::DeviceInput;
+use chainsensor::state::{
+    AcceptedMint, CompressedDeviceRegistry, DataBatch, DeviceBan, DeviceHeartbeat, DeviceMetadata,
+    FeeSchedule, ListingState, Marketplace, PurchaseRecord, PAUSE_LISTING, PAUSE_PURCHASES,
+    PAUSE_REGISTRATION, PAUSE_WITHDRAWALS, REGISTRATION_ADMIN_COSIGN, REGISTRATION_ALLOWLIST,
+    REGISTRATION_OPEN, SETTLEMENT_SOL, SETTLEMENT_TOKEN,
+};
+use chainsensor::{
+    DeviceRegistrationParams, ErrorCode, CERTIFICATE_AUTHORITY_SEED, CERTIFICATE_NAME,
+    CERTIFICATE_SYMBOL, CPI_AUTHORITY_PDA_SEED, ID as PROGRAM_ID, SPL_ACCOUNT_COMPRESSION_ID,
+};
 use light_client::indexer::test_indexer::TestIndexer;
+use light_client::indexer::{AddressMerkleTreeAccounts, Indexer, StateMerkleTreeAccounts};
+use light_client::rpc::merkle_tree::MerkleTreeExt;
 use light_client::rpc::test_rpc::ProgramTestRpcConnection;
-use light_sdk::address::derive_address;
+use light_sdk::address::{derive_address, derive_address_seed};
+use light_sdk::merkle_context::{
+    pack_address_merkle_context, pack_merkle_context, AddressMerkleContext, RemainingAccounts,
+};
+use light_sdk::utils::get_cpi_authority_pda;
+use light_sdk::{PROGRAM_ID_ACCOUNT_COMPRESSION, PROGRAM_ID_LIGHT_SYSTEM, PROGRAM_ID_NOOP};
+use light_test_utils::spl::create_mint_helper;
 use light_test_utils::test_env::{setup_test_programs_with_accounts_v2, EnvAccounts};
-use solana_sdk::instruction::Instruction;
+use light_test_utils::{assert_rpc_error, RpcConnection, RpcError};
+use mpl_bubblegum::accounts::TreeConfig;
+use mpl_bubblegum::hash::{hash_creators, hash_metadata};
+use mpl_bubblegum::instructions::{
+    CreateTreeConfigBuilder, SetTreeDelegateBuilder, TransferBuilder,
+};
+use mpl_bubblegum::types::{LeafSchema, MetadataArgs, TokenProgramVersion, TokenStandard};
+use mpl_bubblegum::utils::get_asset_id;
+use solana_sdk::ed25519_program;
+use solana_sdk::hash::hash;
+use solana_sdk::instruction::{AccountMeta, Instruction};
+use solana_sdk::keccak::hashv;
 use solana_sdk::pubkey::Pubkey;
-use solana_sdk::signature::{Keypair, Signer};
-use solana_sdk::transaction::TransactionError;
-use chainsensor::state::{DeviceRegistry, DeviceMetadata, Marketplace};
-use chainsensor::ID as PROGRAM_ID; use anchor_lang::prelude::ErrorCode;
-use solana_sdk::instruction::InstructionError;
-use anchor_lang::{ToAccountMetas, InstructionData, prelude::ErrorCode};
-use light_test_utils::RpcConnection;
+use solana_sdk::signature::{keypair_from_seed, Keypair, Signer};
+use solana_sdk::system_instruction;
 
+async fn setup() -> (
+    ProgramTestRpcConnection,
+    TestIndexer<ProgramTestRpcConnection>,
+    EnvAccounts,
+    Keypair,
+) {
+    setup_with_programs(Vec::new()).await
+}
+
+/// Like `setup`, additionally loading the named programs from the test fixtures.
+async fn setup_with_programs(
+    programs: Vec<(String, Pubkey)>,
+) -> (
+    ProgramTestRpcConnection,
+    TestIndexer<ProgramTestRpcConnection>,
+    EnvAccounts,
+    Keypair,
+) {
+    let mut programs = programs;
+    programs.insert(0, (String::from("chainsensor"), PROGRAM_ID));
+    let (rpc, env) = setup_test_programs_with_accounts_v2(Some(programs)).await;
+    let payer = rpc.get_payer().insecure_clone();
+    let test_indexer = TestIndexer::new(
+        &[StateMerkleTreeAccounts {
+            merkle_tree: env.merkle_tree_pubkey,
+            nullifier_queue: env.nullifier_queue_pubkey,
+            cpi_context: env.cpi_context_account_pubkey,
+        }],
+        &[AddressMerkleTreeAccounts {
+            merkle_tree: env.address_merkle_tree_pubkey,
+            queue: env.address_merkle_tree_queue_pubkey,
+        }],
+        false,
+        false,
+    )
+    .await;
+    (rpc, test_indexer, env, payer)
+}
+
+async fn funded_keypair<R: RpcConnection>(rpc: &mut R) -> Keypair {
+    let keypair = Keypair::new();
+    rpc.airdrop_lamports(&keypair.pubkey(), 1_000_000_000)
+        .await
+        .unwrap();
+    keypair
+}
+
+async fn get_anchor_account<R: RpcConnection, T: AccountDeserialize>(
+    rpc: &mut R,
+    key: Pubkey,
+) -> T {
+    let account = rpc.get_account(key).await.unwrap().unwrap();
+    T::try_deserialize(&mut &account.data[..]).unwrap()
+}
+
+fn marketplace_pda(creator: &Pubkey, marketplace_id: u64) -> Pubkey {
+    Pubkey::find_program_address(
+        &[
+            b"marketplace",
+            creator.as_ref(),
+            &marketplace_id.to_le_bytes(),
+        ],
+        &PROGRAM_ID,
+    )
+    .0
+}
+
+fn treasury_pda(marketplace_key: &Pubkey, mint: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(
+        &[b"treasury", marketplace_key.as_ref(), mint.as_ref()],
+        &PROGRAM_ID,
+    )
+    .0
+}
+
+fn accepted_mint_pda(marketplace_key: &Pubkey, mint: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(
+        &[b"accepted_mint", marketplace_key.as_ref(), mint.as_ref()],
+        &PROGRAM_ID,
+    )
+    .0
+}
+
+fn fee_schedule_pda(marketplace_key: &Pubkey, data_type: &[u8; 32]) -> Pubkey {
+    Pubkey::find_program_address(
+        &[
+            b"fee_schedule",
+            marketplace_key.as_ref(),
+            data_type.as_ref(),
+        ],
+        &PROGRAM_ID,
+    )
+    .0
+}
+
+fn seller_tier_pda(marketplace_key: &Pubkey, seller: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(
+        &[b"seller_tier", marketplace_key.as_ref(), seller.as_ref()],
+        &PROGRAM_ID,
+    )
+    .0
+}
+
+fn sol_treasury_pda(marketplace_key: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[b"sol_treasury", marketplace_key.as_ref()], &PROGRAM_ID).0
+}
+
+fn listing_pda(device_address: &[u8; 32], listing_id: &str) -> Pubkey {
+    Pubkey::find_program_address(
+        &[b"listing", device_address.as_ref(), listing_id.as_bytes()],
+        &PROGRAM_ID,
+    )
+    .0
+}
+
+fn heartbeat_pda(device_address: &[u8; 32]) -> Pubkey {
+    Pubkey::find_program_address(&[b"heartbeat", device_address.as_ref()], &PROGRAM_ID).0
+}
+
+fn data_batch_pda(device_address: &[u8; 32], merkle_root: &[u8; 32]) -> Pubkey {
+    Pubkey::find_program_address(
+        &[b"data_batch", device_address.as_ref(), merkle_root.as_ref()],
+        &PROGRAM_ID,
+    )
+    .0
+}
+
+fn device_ban_pda(marketplace_key: &Pubkey, device_address: &[u8; 32]) -> Pubkey {
+    Pubkey::find_program_address(
+        &[b"ban", marketplace_key.as_ref(), device_address.as_ref()],
+        &PROGRAM_ID,
+    )
+    .0
+}
+
+fn cpi_signer() -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[CPI_AUTHORITY_PDA_SEED], &PROGRAM_ID)
+}
+
+fn registered_program_pda() -> Pubkey {
+    Pubkey::find_program_address(
+        &[PROGRAM_ID_LIGHT_SYSTEM.to_bytes().as_slice()],
+        &PROGRAM_ID_ACCOUNT_COMPRESSION,
+    )
+    .0
+}
+
+fn padded_device_id(device_id: &str) -> [u8; 32] {
+    let mut padded = [0u8; 32];
+    padded[..device_id.len()].copy_from_slice(device_id.as_bytes());
+    padded
+}
+
+/// Deterministic endorsement key standing in for the device's TPM key.
+fn device_key(device_id: &[u8; 32]) -> Keypair {
+    keypair_from_seed(device_id).unwrap()
+}
 
-// Helper function to initialize the marketplace
-async fn initialize_marketplace<R: light_client::rpc::RpcConnection>(
+fn device_metadata() -> DeviceMetadata {
+    DeviceMetadata {
+        device_type: padded_device_id("weather-station"),
+        data_type: padded_device_id("temperature"),
+        data_unit: padded_device_id("celsius"),
+        firmware_model: padded_device_id("ws-1"),
+        latitude: 525_200_000,
+        longitude: 134_050_000,
+        sampling_interval: 60,
+    }
+}
+
+// Ed25519 precompile instruction with the public key, signature and message inline
+fn ed25519_instruction(signer: &Keypair, message: &[u8]) -> Instruction {
+    let public_key_offset: u16 = 16;
+    let signature_offset = public_key_offset + 32;
+    let message_offset = signature_offset + 64;
+    let mut data = vec![1u8, 0];
+    for field in [
+        signature_offset,
+        u16::MAX,
+        public_key_offset,
+        u16::MAX,
+        message_offset,
+        message.len() as u16,
+        u16::MAX,
+    ] {
+        data.extend_from_slice(&field.to_le_bytes());
+    }
+    data.extend_from_slice(signer.pubkey().as_ref());
+    data.extend_from_slice(signer.sign_message(message).as_ref());
+    data.extend_from_slice(message);
+    Instruction {
+        program_id: ed25519_program::ID,
+        accounts: vec![],
+        data,
+    }
+}
+
+async fn initialize_marketplace<R: RpcConnection>(
+    rpc: &mut R,
+    payer: &Keypair,
+    name: &str,
+    seller_fee: u16,
+) -> Result<Pubkey, RpcError> {
+    initialize_marketplace_with_id(rpc, payer, name, seller_fee, 0).await
+}
+
+async fn initialize_marketplace_with_id<R: RpcConnection>(
     rpc: &mut R,
     payer: &Keypair,
     name: &str,
     seller_fee: u16,
-) -> Result<Pubkey, TransactionError> {
-    let (marketplace_key, bump) = Pubkey::find_program_address(&[b"marketplace", payer.pubkey().as_ref()], &PROGRAM_ID);
-    let (treasury_key, treasury_bump) = Pubkey::find_program_address(&[b"treasury", payer.pubkey().as_ref()], &PROGRAM_ID);
-    let usdc_mint = Pubkey::new_unique(); // Mock USDC mint for testing
+    marketplace_id: u64,
+) -> Result<Pubkey, RpcError> {
+    let usdc_mint = create_mint_helper(rpc, payer).await;
+    let marketplace_key = marketplace_pda(&payer.pubkey(), marketplace_id);
+    let treasury = treasury_pda(&marketplace_key, &usdc_mint);
 
     let accounts = chainsensor::accounts::Initialize {
         admin: payer.pubkey(),
         marketplace: marketplace_key,
-        treasury: treasury_key,
+        treasury,
+        accepted_mint: accepted_mint_pda(&marketplace_key, &usdc_mint),
+        sol_treasury: sol_treasury_pda(&marketplace_key),
         usdc_mint,
         token_program: anchor_spl::token::ID,
         system_program: solana_sdk::system_program::id(),
         rent: solana_sdk::sysvar::rent::id(),
     };
-
     let instruction_data = chainsensor::instruction::Initialize {
         name: name.to_string(),
         seller_fee,
-        _bumps: chainsensor::accounts::InitializeBumps { marketplace: bump, treasury: treasury_bump },
+        marketplace_id,
     };
-
     let instruction = Instruction {
         program_id: PROGRAM_ID,
         accounts: accounts.to_account_metas(Some(true)),
         data: instruction_data.data(),
     };
+    rpc.create_and_send_transaction(&[instruction], &payer.pubkey(), &[payer])
+        .await?;
+    Ok(marketplace_key)
+}
+
+async fn register_device<R: RpcConnection + MerkleTreeExt>(
+    rpc: &mut R,
+    test_indexer: &mut TestIndexer<R>,
+    env: &EnvAccounts,
+    owner: &Keypair,
+    marketplace_key: Pubkey,
+    device_id: [u8; 32],
+) -> Result<[u8; 32], RpcError> {
+    register_device_with_policy(
+        rpc,
+        test_indexer,
+        env,
+        owner,
+        marketplace_key,
+        device_id,
+        None,
+        None,
+    )
+    .await
+}
+
+/// Registers `device_id` passing the allowlist entry and admin co-signature
+/// that the marketplace registration policy may require.
+async fn register_device_with_policy<R: RpcConnection + MerkleTreeExt>(
+    rpc: &mut R,
+    test_indexer: &mut TestIndexer<R>,
+    env: &EnvAccounts,
+    owner: &Keypair,
+    marketplace_key: Pubkey,
+    device_id: [u8; 32],
+    allowlist_entry: Option<Pubkey>,
+    admin: Option<&Keypair>,
+) -> Result<[u8; 32], RpcError> {
+    send_register_device(
+        rpc,
+        test_indexer,
+        env,
+        owner,
+        marketplace_key,
+        device_id,
+        Some(&device_key(&device_id)),
+        allowlist_entry,
+        admin,
+    )
+    .await
+}
+
+/// Registers `device_id` under the key from `device_key`, with the
+/// registration attestation signed by `signing_key` or left out if `None`.
+async fn send_register_device<R: RpcConnection + MerkleTreeExt>(
+    rpc: &mut R,
+    test_indexer: &mut TestIndexer<R>,
+    env: &EnvAccounts,
+    owner: &Keypair,
+    marketplace_key: Pubkey,
+    device_id: [u8; 32],
+    signing_key: Option<&Keypair>,
+    allowlist_entry: Option<Pubkey>,
+    admin: Option<&Keypair>,
+) -> Result<[u8; 32], RpcError> {
+    let mut remaining_accounts = RemainingAccounts::default();
+    let merkle_tree_index = remaining_accounts.insert_or_get(env.merkle_tree_pubkey);
+    let address_merkle_context = address_merkle_context(env);
+    let address = device_address(env, &marketplace_key, &device_id);
+    let packed_address_merkle_context =
+        pack_address_merkle_context(address_merkle_context, &mut remaining_accounts);
+
+    let rpc_result = test_indexer
+        .create_proof_for_compressed_accounts(
+            None,
+            None,
+            Some(&[address]),
+            Some(vec![env.address_merkle_tree_pubkey]),
+            rpc,
+        )
+        .await;
+    let (cpi_signer, bump) = cpi_signer();
+    let instruction_data = chainsensor::instruction::RegisterDevice {
+        proof: rpc_result.proof,
+        address_merkle_tree_root_index: rpc_result.address_root_indices[0],
+        address_merkle_context: packed_address_merkle_context,
+        merkle_tree_index,
+        bump,
+        device_id,
+        ek_pubkey: device_key(&device_id).pubkey().to_bytes(),
+        metadata: device_metadata(),
+    };
+    let accounts = chainsensor::accounts::RegisterDevice {
+        owner: owner.pubkey(),
+        cpi_signer,
+        self_program: PROGRAM_ID,
+        light_system_program: PROGRAM_ID_LIGHT_SYSTEM,
+        account_compression_program: PROGRAM_ID_ACCOUNT_COMPRESSION,
+        account_compression_authority: get_cpi_authority_pda(&PROGRAM_ID_LIGHT_SYSTEM),
+        registered_program_pda: registered_program_pda(),
+        noop_program: PROGRAM_ID_NOOP,
+        system_program: solana_sdk::system_program::id(),
+        marketplace: marketplace_key,
+        allowlist_entry,
+        admin: admin.map(|admin| admin.pubkey()),
+        instructions_sysvar: solana_sdk::sysvar::instructions::ID,
+    };
+    let message = [
+        marketplace_key.to_bytes(),
+        owner.pubkey().to_bytes(),
+        device_id,
+    ]
+    .concat();
+    let mut instructions: Vec<Instruction> = signing_key
+        .map(|signing_key| ed25519_instruction(signing_key, &message))
+        .into_iter()
+        .collect();
+    instructions.push(Instruction {
+        program_id: PROGRAM_ID,
+        accounts: [
+            accounts.to_account_metas(Some(true)),
+            remaining_accounts.to_account_metas(),
+        ]
+        .concat(),
+        data: instruction_data.data(),
+    });
+
+    let mut signers = vec![owner];
+    signers.extend(admin);
+    let event = rpc
+        .create_and_send_transaction_with_event(&instructions, &owner.pubkey(), &signers, None)
+        .await?;
+    test_indexer.add_compressed_accounts_with_token_data(&event.unwrap().0);
+    Ok(address)
+}
 
-    match rpc.create_and_send_transaction(&[instruction], &payer.pubkey(), &[payer]).await {
-        Ok(_) => Ok(marketplace_key),
-        Err(e) => Err(e),
+fn address_merkle_context(env: &EnvAccounts) -> AddressMerkleContext {
+    AddressMerkleContext {
+        address_merkle_tree_pubkey: env.address_merkle_tree_pubkey,
+        address_queue_pubkey: env.address_merkle_tree_queue_pubkey,
     }
 }
 
-// Helper function to register a device
-async fn register_device<R: light_client::rpc::RpcConnection + light_client::rpc::merkle_tree::MerkleTreeExt>(
-//async fn register_device<R: light_client::rpc::RpcConnection>(
+fn device_address(env: &EnvAccounts, marketplace_key: &Pubkey, device_id: &[u8; 32]) -> [u8; 32] {
+    let address_merkle_context = address_merkle_context(env);
+    let address_seed = derive_address_seed(
+        &[
+            b"device".as_slice(),
+            marketplace_key.as_ref(),
+            device_id.as_slice(),
+        ],
+        &PROGRAM_ID,
+        &address_merkle_context,
+    );
+    derive_address(&address_seed, &address_merkle_context)
+}
+
+/// Registers `device_ids` in one `register_devices` call, each attested by
+/// its own key from `device_key`.
+async fn register_devices<R: RpcConnection + MerkleTreeExt>(
     rpc: &mut R,
     test_indexer: &mut TestIndexer<R>,
     env: &EnvAccounts,
-    payer: &Keypair,
+    owner: &Keypair,
     marketplace_key: Pubkey,
-    device_id: &str,
-    price_per_unit: u64,
-    total_data_units: u64,
-) -> Result<[u8; 32], TransactionError> {
-    let address_seed = device_id.as_bytes();
-    let address = derive_address(address_seed, &PROGRAM_ID);
-    let proof = test_indexer.create_proof_for_compressed_accounts(&[address], None).await;
-
-    let metadata = DeviceMetadata {
-        sensor_type: "Temperature".to_string(),
-        location: "Room1".to_string(),
-        description: "Test sensor".to_string(),
-    };
+    device_ids: &[[u8; 32]],
+) -> Result<Vec<[u8; 32]>, RpcError> {
+    let mut remaining_accounts = RemainingAccounts::default();
+    let merkle_tree_index = remaining_accounts.insert_or_get(env.merkle_tree_pubkey);
+    let addresses: Vec<[u8; 32]> = device_ids
+        .iter()
+        .map(|device_id| device_address(env, &marketplace_key, device_id))
+        .collect();
+    let packed_address_merkle_context =
+        pack_address_merkle_context(address_merkle_context(env), &mut remaining_accounts);
 
+    let rpc_result = test_indexer
+        .create_proof_for_compressed_accounts(
+            None,
+            None,
+            Some(&addresses),
+            Some(vec![env.address_merkle_tree_pubkey; addresses.len()]),
+            rpc,
+        )
+        .await;
+    let devices = device_ids
+        .iter()
+        .zip(&rpc_result.address_root_indices)
+        .map(|(device_id, root_index)| DeviceRegistrationParams {
+            device_id: *device_id,
+            ek_pubkey: device_key(device_id).pubkey().to_bytes(),
+            metadata: device_metadata(),
+            address_merkle_tree_root_index: *root_index,
+        })
+        .collect();
+    let (cpi_signer, bump) = cpi_signer();
+    let instruction_data = chainsensor::instruction::RegisterDevices {
+        proof: rpc_result.proof,
+        address_merkle_context: packed_address_merkle_context,
+        merkle_tree_index,
+        bump,
+        devices,
+    };
     let accounts = chainsensor::accounts::RegisterDevice {
-        owner: payer.pubkey(),
+        owner: owner.pubkey(),
+        cpi_signer,
+        self_program: PROGRAM_ID,
+        light_system_program: PROGRAM_ID_LIGHT_SYSTEM,
+        account_compression_program: PROGRAM_ID_ACCOUNT_COMPRESSION,
+        account_compression_authority: get_cpi_authority_pda(&PROGRAM_ID_LIGHT_SYSTEM),
+        registered_program_pda: registered_program_pda(),
+        noop_program: PROGRAM_ID_NOOP,
+        system_program: solana_sdk::system_program::id(),
         marketplace: marketplace_key,
-        cpi_authority: get_cpi_authority_pda(&PROGRAM_ID).0,
+        allowlist_entry: None,
+        admin: None,
+        instructions_sysvar: solana_sdk::sysvar::instructions::ID,
+    };
+    // Device `i` of `n` is attested by the Ed25519 instruction `n - i`
+    // positions before `register_devices`
+    let mut instructions: Vec<Instruction> = device_ids
+        .iter()
+        .map(|device_id| {
+            let message = [
+                marketplace_key.to_bytes(),
+                owner.pubkey().to_bytes(),
+                *device_id,
+            ]
+            .concat();
+            ed25519_instruction(&device_key(device_id), &message)
+        })
+        .collect();
+    instructions.push(Instruction {
+        program_id: PROGRAM_ID,
+        accounts: [
+            accounts.to_account_metas(Some(true)),
+            remaining_accounts.to_account_metas(),
+        ]
+        .concat(),
+        data: instruction_data.data(),
+    });
+
+    let event = rpc
+        .create_and_send_transaction_with_event(&instructions, &owner.pubkey(), &[owner], None)
+        .await?;
+    test_indexer.add_compressed_accounts_with_token_data(&event.unwrap().0);
+    Ok(addresses)
+}
+
+/// Current compressed record of a device as an instruction input, with the
+/// remaining accounts its packed Merkle context refers to.
+struct ProvenDevice {
+    input: DeviceInput,
+    remaining_accounts: RemainingAccounts,
+}
+
+async fn prove_device<R: RpcConnection + MerkleTreeExt>(
+    rpc: &mut R,
+    test_indexer: &mut TestIndexer<R>,
+    address: [u8; 32],
+) -> ProvenDevice {
+    let compressed_account = test_indexer
+        .get_compressed_accounts_by_owner(&PROGRAM_ID)
+        .into_iter()
+        .find(|account| account.compressed_account.address == Some(address))
+        .unwrap();
+    let device_registry = CompressedDeviceRegistry::deserialize(
+        &mut &compressed_account
+            .compressed_account
+            .data
+            .as_ref()
+            .unwrap()
+            .data[..],
+    )
+    .unwrap();
+    let hash = compressed_account.hash().unwrap();
+    let merkle_tree_pubkey = compressed_account.merkle_context.merkle_tree_pubkey;
+    let rpc_result = test_indexer
+        .create_proof_for_compressed_accounts(
+            Some(&[hash]),
+            Some(&[merkle_tree_pubkey]),
+            None,
+            None,
+            rpc,
+        )
+        .await;
+    let mut remaining_accounts = RemainingAccounts::default();
+    let merkle_context =
+        pack_merkle_context(compressed_account.merkle_context, &mut remaining_accounts);
+    ProvenDevice {
+        input: DeviceInput {
+            device_registry,
+            proof: rpc_result.proof,
+            merkle_context,
+            merkle_tree_root_index: rpc_result.root_indices[0],
+            bump: cpi_signer().1,
+        },
+        remaining_accounts,
+    }
+}
+
+/// Sends `data` to the program with the light system accounts first, then
+/// `accounts`, then `remaining_accounts`, and indexes the compressed outputs.
+async fn send_light_instruction<R: RpcConnection + MerkleTreeExt>(
+    rpc: &mut R,
+    test_indexer: &mut TestIndexer<R>,
+    signer: &Keypair,
+    accounts: impl ToAccountMetas,
+    data: impl InstructionData,
+    remaining_accounts: &RemainingAccounts,
+) -> Result<(), RpcError> {
+    let instruction = Instruction {
+        program_id: PROGRAM_ID,
+        accounts: [
+            accounts.to_account_metas(Some(true)),
+            remaining_accounts.to_account_metas(),
+        ]
+        .concat(),
+        data: data.data(),
+    };
+    let event = rpc
+        .create_and_send_transaction_with_event(&[instruction], &signer.pubkey(), &[signer], None)
+        .await?;
+    test_indexer.add_compressed_accounts_with_token_data(&event.unwrap().0);
+    Ok(())
+}
+
+async fn create_listing<R: RpcConnection + MerkleTreeExt>(
+    rpc: &mut R,
+    test_indexer: &mut TestIndexer<R>,
+    seller: &Keypair,
+    marketplace_key: Pubkey,
+    device_address: [u8; 32],
+    listing_id: &str,
+    device_id: &str,
+) -> Result<Pubkey, RpcError> {
+    create_listing_at_price(
+        rpc,
+        test_indexer,
+        seller,
+        marketplace_key,
+        device_address,
+        listing_id,
+        device_id,
+        1_000_000,
+        None,
+    )
+    .await
+}
+
+async fn create_listing_at_price<R: RpcConnection + MerkleTreeExt>(
+    rpc: &mut R,
+    test_indexer: &mut TestIndexer<R>,
+    seller: &Keypair,
+    marketplace_key: Pubkey,
+    device_address: [u8; 32],
+    listing_id: &str,
+    device_id: &str,
+    price_per_unit: u64,
+    data_batch: Option<Pubkey>,
+) -> Result<Pubkey, RpcError> {
+    let marketplace: Marketplace = get_anchor_account(rpc, marketplace_key).await;
+    create_listing_with_settlement(
+        rpc,
+        test_indexer,
+        seller,
+        marketplace_key,
+        device_address,
+        listing_id,
+        device_id,
+        price_per_unit,
+        data_batch,
+        SETTLEMENT_TOKEN,
+        Some(marketplace.token_mint),
+    )
+    .await
+}
+
+/// Lists with an explicit settlement mode; `payment_mint` selects the
+/// accepted-mint account passed, if any.
+async fn create_listing_with_settlement<R: RpcConnection + MerkleTreeExt>(
+    rpc: &mut R,
+    test_indexer: &mut TestIndexer<R>,
+    seller: &Keypair,
+    marketplace_key: Pubkey,
+    device_address: [u8; 32],
+    listing_id: &str,
+    device_id: &str,
+    price_per_unit: u64,
+    data_batch: Option<Pubkey>,
+    settlement_mode: u8,
+    payment_mint: Option<Pubkey>,
+) -> Result<Pubkey, RpcError> {
+    let device = prove_device(rpc, test_indexer, device_address).await;
+    let listing_state = listing_pda(&device_address, listing_id);
+    let accounts = chainsensor::accounts::CreateListing {
+        seller: seller.pubkey(),
+        cpi_signer: cpi_signer().0,
+        self_program: PROGRAM_ID,
+        light_system_program: PROGRAM_ID_LIGHT_SYSTEM,
+        account_compression_program: PROGRAM_ID_ACCOUNT_COMPRESSION,
+        account_compression_authority: get_cpi_authority_pda(&PROGRAM_ID_LIGHT_SYSTEM),
+        registered_program_pda: registered_program_pda(),
+        noop_program: PROGRAM_ID_NOOP,
         system_program: solana_sdk::system_program::id(),
+        marketplace: marketplace_key,
+        accepted_mint: payment_mint.map(|mint| accepted_mint_pda(&marketplace_key, &mint)),
+        listing_state,
+        data_batch,
+        device_ban: device_ban_pda(&marketplace_key, &device_address),
+        rent: solana_sdk::sysvar::rent::id(),
+        certificate_tree: None,
+        compression_program: None,
+    };
+    let instruction_data = chainsensor::instruction::CreateListing {
+        listing_id: listing_id.to_string(),
+        device_address,
+        data_cid: "bafy-test-cid".to_string(),
+        price_per_unit,
+        device_id: device_id.to_string(),
+        total_data_units: 10,
+        expires_at: None,
+        settlement_mode,
+        device: device.input,
+        certificate_proof: None,
+    };
+    send_light_instruction(
+        rpc,
+        test_indexer,
+        seller,
+        accounts,
+        instruction_data,
+        &device.remaining_accounts,
+    )
+    .await?;
+    Ok(listing_state)
+}
+
+async fn cancel_listing<R: RpcConnection + MerkleTreeExt>(
+    rpc: &mut R,
+    test_indexer: &mut TestIndexer<R>,
+    signer: &Keypair,
+    device_address: [u8; 32],
+    listing_id: &str,
+) -> Result<(), RpcError> {
+    let device = prove_device(rpc, test_indexer, device_address).await;
+    let accounts = chainsensor::accounts::CancelListing {
+        seller: signer.pubkey(),
+        cpi_signer: cpi_signer().0,
+        self_program: PROGRAM_ID,
         light_system_program: PROGRAM_ID_LIGHT_SYSTEM,
         account_compression_program: PROGRAM_ID_ACCOUNT_COMPRESSION,
+        account_compression_authority: get_cpi_authority_pda(&PROGRAM_ID_LIGHT_SYSTEM),
+        registered_program_pda: registered_program_pda(),
         noop_program: PROGRAM_ID_NOOP,
-        registered_program_pda: light_sdk::get_registered_program_pda(&PROGRAM_ID_LIGHT_SYSTEM),
-        address_merkle_tree: env.address_merkle_tree_pubkey,
-        address_queue: env.address_queue_pubkey,
-        state_merkle_tree: env.state_merkle_tree_pubkey,
-        state_queue: env.state_queue_pubkey,
+        system_program: solana_sdk::system_program::id(),
+        listing_state: listing_pda(&device_address, listing_id),
+        certificate_tree: None,
+        compression_program: None,
     };
+    let instruction_data = chainsensor::instruction::CancelListing {
+        listing_id: listing_id.to_string(),
+        device: device.input,
+        certificate_proof: None,
+    };
+    send_light_instruction(
+        rpc,
+        test_indexer,
+        signer,
+        accounts,
+        instruction_data,
+        &device.remaining_accounts,
+    )
+    .await
+}
 
-    let instruction_data = chainsensor::instruction::RegisterDevice {
-        address,
-        device_id: device_id.to_string(),
-        price_per_unit,
-        total_data_units,
-        metadata,
-        address_merkle_context: pack_address_merkle_context(env.address_merkle_tree_pubkey, env.address_queue_pubkey),
-        proof: proof.clone(),
+/// Creates `owner`'s token account for the marketplace mint if needed and mints
+/// `amount` into it, with `payer` as the mint authority.
+async fn fund_token_account<R: RpcConnection>(
+    rpc: &mut R,
+    payer: &Keypair,
+    marketplace_key: Pubkey,
+    owner: &Pubkey,
+    amount: u64,
+) -> Pubkey {
+    let marketplace: Marketplace = get_anchor_account(rpc, marketplace_key).await;
+    fund_mint_account(rpc, payer, marketplace.token_mint, owner, amount).await
+}
+
+async fn fund_mint_account<R: RpcConnection>(
+    rpc: &mut R,
+    payer: &Keypair,
+    mint: Pubkey,
+    owner: &Pubkey,
+    amount: u64,
+) -> Pubkey {
+    let token_program = token_program_of(rpc, mint).await;
+    let token_account = get_associated_token_address_with_program_id(owner, &mint, &token_program);
+    let instructions = [
+        create_associated_token_account_idempotent(&payer.pubkey(), owner, &mint, &token_program),
+        spl_token_2022::instruction::mint_to(
+            &token_program,
+            &mint,
+            &token_account,
+            &payer.pubkey(),
+            &[],
+            amount,
+        )
+        .unwrap(),
+    ];
+    rpc.create_and_send_transaction(&instructions, &payer.pubkey(), &[payer])
+        .await
+        .unwrap();
+    token_account
+}
+
+/// SPL Token or Token-2022, whichever owns the mint.
+async fn token_program_of<R: RpcConnection>(rpc: &mut R, mint: Pubkey) -> Pubkey {
+    rpc.get_account(mint).await.unwrap().unwrap().owner
+}
+
+/// Creates a 6-decimal Token-2022 mint with `payer` as mint authority, carrying
+/// `extension`: a 1% transfer fee capped at 5_000 base units, or a transfer hook.
+async fn create_token_2022_mint<R: RpcConnection>(
+    rpc: &mut R,
+    payer: &Keypair,
+    extension: ExtensionType,
+) -> Pubkey {
+    let mint = Keypair::new();
+    let program_id = spl_token_2022::ID;
+    let size =
+        ExtensionType::try_calculate_account_len::<spl_token_2022::state::Mint>(&[extension])
+            .unwrap();
+    let lamports = rpc
+        .get_minimum_balance_for_rent_exemption(size)
+        .await
+        .unwrap();
+    let init_extension = match extension {
+        ExtensionType::TransferFeeConfig => {
+            transfer_fee::instruction::initialize_transfer_fee_config(
+                &program_id,
+                &mint.pubkey(),
+                Some(&payer.pubkey()),
+                Some(&payer.pubkey()),
+                100,
+                5_000,
+            )
+        }
+        ExtensionType::TransferHook => transfer_hook::instruction::initialize(
+            &program_id,
+            &mint.pubkey(),
+            Some(payer.pubkey()),
+            Some(PROGRAM_ID),
+        ),
+        _ => unimplemented!("unsupported test extension"),
+    }
+    .unwrap();
+    let instructions = [
+        system_instruction::create_account(
+            &payer.pubkey(),
+            &mint.pubkey(),
+            lamports,
+            size as u64,
+            &program_id,
+        ),
+        init_extension,
+        spl_token_2022::instruction::initialize_mint2(
+            &program_id,
+            &mint.pubkey(),
+            &payer.pubkey(),
+            None,
+            6,
+        )
+        .unwrap(),
+    ];
+    rpc.create_and_send_transaction(&instructions, &payer.pubkey(), &[payer, &mint])
+        .await
+        .unwrap();
+    mint.pubkey()
+}
+
+async fn token_balance<R: RpcConnection>(rpc: &mut R, token_account: Pubkey) -> u64 {
+    let account: TokenAccount = get_anchor_account(rpc, token_account).await;
+    account.amount
+}
+
+/// Buys `units` of the listing, passing the accounts its settlement mode needs;
+/// for token listings the buyer and seller token accounts must exist.
+async fn purchase_listing<R: RpcConnection + MerkleTreeExt>(
+    rpc: &mut R,
+    test_indexer: &mut TestIndexer<R>,
+    buyer: &Keypair,
+    marketplace_key: Pubkey,
+    device_address: [u8; 32],
+    listing_id: &str,
+    units_requested: u64,
+) -> Result<Pubkey, RpcError> {
+    let device = prove_device(rpc, test_indexer, device_address).await;
+    let listing_state = listing_pda(&device_address, listing_id);
+    let listing: ListingState = get_anchor_account(rpc, listing_state).await;
+    let purchase_record = Pubkey::find_program_address(
+        &[
+            b"purchase",
+            listing_state.as_ref(),
+            &listing.purchase_count.to_le_bytes(),
+        ],
+        &PROGRAM_ID,
+    )
+    .0;
+    let token_settled = listing.settlement_mode == SETTLEMENT_TOKEN;
+    let mint = listing.token_mint;
+    let token_program = if token_settled {
+        token_program_of(rpc, mint).await
+    } else {
+        anchor_spl::token::ID
     };
+    let ata =
+        |owner: &Pubkey| get_associated_token_address_with_program_id(owner, &mint, &token_program);
+    let accounts = chainsensor::accounts::PurchaseListing {
+        buyer: buyer.pubkey(),
+        cpi_signer: cpi_signer().0,
+        self_program: PROGRAM_ID,
+        light_system_program: PROGRAM_ID_LIGHT_SYSTEM,
+        account_compression_program: PROGRAM_ID_ACCOUNT_COMPRESSION,
+        account_compression_authority: get_cpi_authority_pda(&PROGRAM_ID_LIGHT_SYSTEM),
+        registered_program_pda: registered_program_pda(),
+        noop_program: PROGRAM_ID_NOOP,
+        system_program: solana_sdk::system_program::id(),
+        buyer_ata: token_settled.then(|| ata(&buyer.pubkey())),
+        seller_ata: token_settled.then(|| ata(&listing.seller)),
+        treasury_ata: token_settled.then(|| treasury_pda(&marketplace_key, &mint)),
+        accepted_mint: token_settled.then(|| accepted_mint_pda(&marketplace_key, &mint)),
+        payment_mint: token_settled.then_some(mint),
+        token_program: token_settled.then_some(token_program),
+        seller: (!token_settled).then_some(listing.seller),
+        sol_treasury: (!token_settled).then(|| sol_treasury_pda(&marketplace_key)),
+        listing_state,
+        marketplace: marketplace_key,
+        device_ban: device_ban_pda(&marketplace_key, &device_address),
+        fee_schedule: fee_schedule_pda(&marketplace_key, &listing.data_type),
+        seller_tier: seller_tier_pda(&marketplace_key, &listing.seller),
+        clock: solana_sdk::sysvar::clock::id(),
+        purchase_record,
+        rent: solana_sdk::sysvar::rent::id(),
+        certificate_tree: None,
+        compression_program: None,
+    };
+    let instruction_data = chainsensor::instruction::PurchaseListing {
+        listing_id: listing_id.to_string(),
+        units_requested,
+        device: device.input,
+        certificate_proof: None,
+    };
+    send_light_instruction(
+        rpc,
+        test_indexer,
+        buyer,
+        accounts,
+        instruction_data,
+        &device.remaining_accounts,
+    )
+    .await?;
+    Ok(purchase_record)
+}
 
-    let instruction = Instruction {
-        program_id: PROGRAM_ID,
-        accounts: accounts.to_account_metas(Some(true)),
-        data: instruction_data.data(),
+async fn withdraw_treasury<R: RpcConnection>(
+    rpc: &mut R,
+    admin: &Keypair,
+    marketplace_key: Pubkey,
+    destination: Pubkey,
+    amount: Option<u64>,
+) -> Result<(), RpcError> {
+    let marketplace: Marketplace = get_anchor_account(rpc, marketplace_key).await;
+    withdraw_mint_treasury(
+        rpc,
+        admin,
+        marketplace_key,
+        marketplace.token_mint,
+        destination,
+        amount,
+    )
+    .await
+}
+
+async fn withdraw_mint_treasury<R: RpcConnection>(
+    rpc: &mut R,
+    admin: &Keypair,
+    marketplace_key: Pubkey,
+    mint: Pubkey,
+    destination: Pubkey,
+    amount: Option<u64>,
+) -> Result<(), RpcError> {
+    send_admin_instruction(
+        rpc,
+        admin,
+        chainsensor::accounts::WithdrawTreasury {
+            admin: admin.pubkey(),
+            marketplace: marketplace_key,
+            mint,
+            treasury: treasury_pda(&marketplace_key, &mint),
+            destination,
+            token_program: token_program_of(rpc, mint).await,
+        },
+        chainsensor::instruction::WithdrawTreasury { amount },
+    )
+    .await
+}
+
+async fn withdraw_sol_treasury<R: RpcConnection>(
+    rpc: &mut R,
+    admin: &Keypair,
+    marketplace_key: Pubkey,
+    destination: Pubkey,
+    amount: Option<u64>,
+) -> Result<(), RpcError> {
+    send_admin_instruction(
+        rpc,
+        admin,
+        chainsensor::accounts::WithdrawSolTreasury {
+            admin: admin.pubkey(),
+            marketplace: marketplace_key,
+            sol_treasury: sol_treasury_pda(&marketplace_key),
+            destination,
+        },
+        chainsensor::instruction::WithdrawSolTreasury { amount },
+    )
+    .await
+}
+
+/// Lists 10 units of the device at 1_000_000 each and funds a new buyer with
+/// enough tokens to buy all of them.
+async fn setup_purchase<R: RpcConnection + MerkleTreeExt>(
+    rpc: &mut R,
+    test_indexer: &mut TestIndexer<R>,
+    payer: &Keypair,
+    marketplace_key: Pubkey,
+    device_address: [u8; 32],
+) -> Keypair {
+    create_listing(
+        rpc,
+        test_indexer,
+        payer,
+        marketplace_key,
+        device_address,
+        "listing1",
+        "device1",
+    )
+    .await
+    .unwrap();
+    let buyer = funded_keypair(rpc).await;
+    fund_token_account(rpc, payer, marketplace_key, &buyer.pubkey(), 10_000_000).await;
+    fund_token_account(rpc, payer, marketplace_key, &payer.pubkey(), 0).await;
+    buyer
+}
+
+async fn close_device<R: RpcConnection + MerkleTreeExt>(
+    rpc: &mut R,
+    test_indexer: &mut TestIndexer<R>,
+    owner: &Keypair,
+    device_address: [u8; 32],
+) -> Result<(), RpcError> {
+    let device = prove_device(rpc, test_indexer, device_address).await;
+    let accounts = chainsensor::accounts::CloseDevice {
+        owner: owner.pubkey(),
+        cpi_signer: cpi_signer().0,
+        self_program: PROGRAM_ID,
+        light_system_program: PROGRAM_ID_LIGHT_SYSTEM,
+        account_compression_program: PROGRAM_ID_ACCOUNT_COMPRESSION,
+        account_compression_authority: get_cpi_authority_pda(&PROGRAM_ID_LIGHT_SYSTEM),
+        registered_program_pda: registered_program_pda(),
+        noop_program: PROGRAM_ID_NOOP,
+        system_program: solana_sdk::system_program::id(),
+        heartbeat: heartbeat_pda(&device_address),
+        certificate_tree: None,
+        compression_program: None,
+    };
+    let instruction_data = chainsensor::instruction::CloseDevice {
+        device_address,
+        device: device.input,
+        certificate_proof: None,
     };
+    send_light_instruction(
+        rpc,
+        test_indexer,
+        owner,
+        accounts,
+        instruction_data,
+        &device.remaining_accounts,
+    )
+    .await
+}
+
+async fn update_device<R: RpcConnection + MerkleTreeExt>(
+    rpc: &mut R,
+    test_indexer: &mut TestIndexer<R>,
+    owner: &Keypair,
+    device_address: [u8; 32],
+    device_type: [u8; 32],
+) -> Result<(), RpcError> {
+    let device = prove_device(rpc, test_indexer, device_address).await;
+    let accounts = chainsensor::accounts::UpdateDevice {
+        owner: owner.pubkey(),
+        cpi_signer: cpi_signer().0,
+        self_program: PROGRAM_ID,
+        light_system_program: PROGRAM_ID_LIGHT_SYSTEM,
+        account_compression_program: PROGRAM_ID_ACCOUNT_COMPRESSION,
+        account_compression_authority: get_cpi_authority_pda(&PROGRAM_ID_LIGHT_SYSTEM),
+        registered_program_pda: registered_program_pda(),
+        noop_program: PROGRAM_ID_NOOP,
+        system_program: solana_sdk::system_program::id(),
+        certificate_tree: None,
+        compression_program: None,
+    };
+    let instruction_data = chainsensor::instruction::UpdateDevice {
+        device_address,
+        device: device.input,
+        metadata: DeviceMetadata {
+            device_type,
+            data_type: padded_device_id("humidity"),
+            ..device_metadata()
+        },
+        certificate_proof: None,
+    };
+    send_light_instruction(
+        rpc,
+        test_indexer,
+        owner,
+        accounts,
+        instruction_data,
+        &device.remaining_accounts,
+    )
+    .await
+}
+
+async fn set_device_status<R: RpcConnection + MerkleTreeExt>(
+    rpc: &mut R,
+    test_indexer: &mut TestIndexer<R>,
+    authority: &Keypair,
+    marketplace_key: Pubkey,
+    device_address: [u8; 32],
+    is_active: bool,
+) -> Result<(), RpcError> {
+    let device = prove_device(rpc, test_indexer, device_address).await;
+    let accounts = chainsensor::accounts::SetDeviceStatus {
+        authority: authority.pubkey(),
+        cpi_signer: cpi_signer().0,
+        self_program: PROGRAM_ID,
+        light_system_program: PROGRAM_ID_LIGHT_SYSTEM,
+        account_compression_program: PROGRAM_ID_ACCOUNT_COMPRESSION,
+        account_compression_authority: get_cpi_authority_pda(&PROGRAM_ID_LIGHT_SYSTEM),
+        registered_program_pda: registered_program_pda(),
+        noop_program: PROGRAM_ID_NOOP,
+        system_program: solana_sdk::system_program::id(),
+        marketplace: marketplace_key,
+        certificate_tree: None,
+        compression_program: None,
+    };
+    if is_active {
+        let instruction_data = chainsensor::instruction::ReactivateDevice {
+            device_address,
+            device: device.input,
+            certificate_proof: None,
+        };
+        send_light_instruction(
+            rpc,
+            test_indexer,
+            authority,
+            accounts,
+            instruction_data,
+            &device.remaining_accounts,
+        )
+        .await
+    } else {
+        let instruction_data = chainsensor::instruction::DeactivateDevice {
+            device_address,
+            device: device.input,
+            certificate_proof: None,
+        };
+        send_light_instruction(
+            rpc,
+            test_indexer,
+            authority,
+            accounts,
+            instruction_data,
+            &device.remaining_accounts,
+        )
+        .await
+    }
+}
+
+async fn transfer_device<R: RpcConnection + MerkleTreeExt>(
+    rpc: &mut R,
+    test_indexer: &mut TestIndexer<R>,
+    owner: &Keypair,
+    device_address: [u8; 32],
+    new_owner: Pubkey,
+) -> Result<(), RpcError> {
+    let device = prove_device(rpc, test_indexer, device_address).await;
+    let accounts = chainsensor::accounts::TransferDevice {
+        owner: owner.pubkey(),
+        cpi_signer: cpi_signer().0,
+        self_program: PROGRAM_ID,
+        light_system_program: PROGRAM_ID_LIGHT_SYSTEM,
+        account_compression_program: PROGRAM_ID_ACCOUNT_COMPRESSION,
+        account_compression_authority: get_cpi_authority_pda(&PROGRAM_ID_LIGHT_SYSTEM),
+        registered_program_pda: registered_program_pda(),
+        noop_program: PROGRAM_ID_NOOP,
+        system_program: solana_sdk::system_program::id(),
+        heartbeat: heartbeat_pda(&device_address),
+    };
+    let instruction_data = chainsensor::instruction::TransferDevice {
+        device_address,
+        device: device.input,
+        new_owner,
+    };
+    send_light_instruction(
+        rpc,
+        test_indexer,
+        owner,
+        accounts,
+        instruction_data,
+        &device.remaining_accounts,
+    )
+    .await
+}
+
+/// Sets `operator` with `price_floor` on the device, or clears the operator
+/// when `operator` is `None`.
+async fn set_device_operator<R: RpcConnection + MerkleTreeExt>(
+    rpc: &mut R,
+    test_indexer: &mut TestIndexer<R>,
+    owner: &Keypair,
+    device_address: [u8; 32],
+    operator: Option<(Pubkey, u64)>,
+) -> Result<(), RpcError> {
+    let device = prove_device(rpc, test_indexer, device_address).await;
+    let accounts = chainsensor::accounts::SetDeviceOperator {
+        owner: owner.pubkey(),
+        cpi_signer: cpi_signer().0,
+        self_program: PROGRAM_ID,
+        light_system_program: PROGRAM_ID_LIGHT_SYSTEM,
+        account_compression_program: PROGRAM_ID_ACCOUNT_COMPRESSION,
+        account_compression_authority: get_cpi_authority_pda(&PROGRAM_ID_LIGHT_SYSTEM),
+        registered_program_pda: registered_program_pda(),
+        noop_program: PROGRAM_ID_NOOP,
+        system_program: solana_sdk::system_program::id(),
+        heartbeat: heartbeat_pda(&device_address),
+        certificate_tree: None,
+        compression_program: None,
+    };
+    match operator {
+        Some((operator, price_floor)) => {
+            let instruction_data = chainsensor::instruction::SetDeviceOperator {
+                device_address,
+                device: device.input,
+                operator,
+                price_floor,
+                certificate_proof: None,
+            };
+            send_light_instruction(
+                rpc,
+                test_indexer,
+                owner,
+                accounts,
+                instruction_data,
+                &device.remaining_accounts,
+            )
+            .await
+        }
+        None => {
+            let instruction_data = chainsensor::instruction::ClearDeviceOperator {
+                device_address,
+                device: device.input,
+                certificate_proof: None,
+            };
+            send_light_instruction(
+                rpc,
+                test_indexer,
+                owner,
+                accounts,
+                instruction_data,
+                &device.remaining_accounts,
+            )
+            .await
+        }
+    }
+}
+
+async fn sync_device_heartbeat<R: RpcConnection + MerkleTreeExt>(
+    rpc: &mut R,
+    test_indexer: &mut TestIndexer<R>,
+    owner: &Keypair,
+    device_address: [u8; 32],
+) -> Result<(), RpcError> {
+    let device = prove_device(rpc, test_indexer, device_address).await;
+    let accounts = chainsensor::accounts::SyncDeviceHeartbeat {
+        owner: owner.pubkey(),
+        cpi_signer: cpi_signer().0,
+        self_program: PROGRAM_ID,
+        light_system_program: PROGRAM_ID_LIGHT_SYSTEM,
+        account_compression_program: PROGRAM_ID_ACCOUNT_COMPRESSION,
+        account_compression_authority: get_cpi_authority_pda(&PROGRAM_ID_LIGHT_SYSTEM),
+        registered_program_pda: registered_program_pda(),
+        noop_program: PROGRAM_ID_NOOP,
+        system_program: solana_sdk::system_program::id(),
+        heartbeat: heartbeat_pda(&device_address),
+        certificate_tree: None,
+        compression_program: None,
+    };
+    let instruction_data = chainsensor::instruction::SyncDeviceHeartbeat {
+        device_address,
+        device: device.input,
+        certificate_proof: None,
+    };
+    send_light_instruction(
+        rpc,
+        test_indexer,
+        owner,
+        accounts,
+        instruction_data,
+        &device.remaining_accounts,
+    )
+    .await
+}
+
+/// Posts a heartbeat signed by `signer`, with `payer` covering the fee.
+async fn device_heartbeat<R: RpcConnection>(
+    rpc: &mut R,
+    payer: &Keypair,
+    signer: &Keypair,
+    device_address: [u8; 32],
+) -> Result<(), RpcError> {
+    let accounts = chainsensor::accounts::RecordHeartbeat {
+        signer: signer.pubkey(),
+        heartbeat: heartbeat_pda(&device_address),
+    };
+    let instruction = Instruction {
+        program_id: PROGRAM_ID,
+        accounts: accounts.to_account_metas(Some(true)),
+        data: chainsensor::instruction::DeviceHeartbeat {}.data(),
+    };
+    rpc.create_and_send_transaction(&[instruction], &payer.pubkey(), &[payer, signer])
+        .await?;
+    Ok(())
+}
+
+/// Commits a batch of `reading_count` readings stored under the listing test CID.
+async fn commit_data_batch<R: RpcConnection>(
+    rpc: &mut R,
+    signer: &Keypair,
+    device_address: [u8; 32],
+    merkle_root: [u8; 32],
+    reading_count: u64,
+) -> Result<Pubkey, RpcError> {
+    let data_batch = data_batch_pda(&device_address, &merkle_root);
+    let accounts = chainsensor::accounts::CommitDataBatch {
+        signer: signer.pubkey(),
+        heartbeat: heartbeat_pda(&device_address),
+        data_batch,
+        system_program: solana_sdk::system_program::id(),
+    };
+    let instruction_data = chainsensor::instruction::CommitDataBatch {
+        merkle_root,
+        blob_id: "bafy-test-cid".to_string(),
+        window_start: 0,
+        window_end: 0,
+        reading_count,
+    };
+    send_admin_instruction(rpc, signer, accounts, instruction_data).await?;
+    Ok(data_batch)
+}
+
+/// Rotates the device key to `new_ek`, with the rotation signed by `old_ek`
+/// and/or approved by the marketplace `admin`.
+async fn rotate_device_key<R: RpcConnection + MerkleTreeExt>(
+    rpc: &mut R,
+    test_indexer: &mut TestIndexer<R>,
+    owner: &Keypair,
+    marketplace_key: Pubkey,
+    device_address: [u8; 32],
+    old_ek: Option<&Keypair>,
+    admin: Option<&Keypair>,
+    new_ek: &Keypair,
+) -> Result<(), RpcError> {
+    let device = prove_device(rpc, test_indexer, device_address).await;
+    let device_registry = &device.input.device_registry;
+    let accounts = chainsensor::accounts::RotateDeviceKey {
+        owner: owner.pubkey(),
+        cpi_signer: cpi_signer().0,
+        self_program: PROGRAM_ID,
+        light_system_program: PROGRAM_ID_LIGHT_SYSTEM,
+        account_compression_program: PROGRAM_ID_ACCOUNT_COMPRESSION,
+        account_compression_authority: get_cpi_authority_pda(&PROGRAM_ID_LIGHT_SYSTEM),
+        registered_program_pda: registered_program_pda(),
+        noop_program: PROGRAM_ID_NOOP,
+        system_program: solana_sdk::system_program::id(),
+        marketplace: marketplace_key,
+        admin: admin.map(|admin| admin.pubkey()),
+        instructions_sysvar: solana_sdk::sysvar::instructions::ID,
+        heartbeat: heartbeat_pda(&device_address),
+        certificate_tree: None,
+        compression_program: None,
+    };
+    let message = [
+        marketplace_key.as_ref(),
+        owner.pubkey().as_ref(),
+        device_registry.device_id.as_slice(),
+        new_ek.pubkey().as_ref(),
+        &device_registry.key_rotations.to_le_bytes(),
+    ]
+    .concat();
+    let mut instructions: Vec<Instruction> = old_ek
+        .map(|old_ek| ed25519_instruction(old_ek, &message))
+        .into_iter()
+        .collect();
+    let instruction_data = chainsensor::instruction::RotateDeviceKey {
+        device_address,
+        device: device.input,
+        old_ek_pubkey: old_ek.map(|old_ek| old_ek.pubkey().to_bytes()),
+        new_ek_pubkey: new_ek.pubkey().to_bytes(),
+        certificate_proof: None,
+    };
+    instructions.push(Instruction {
+        program_id: PROGRAM_ID,
+        accounts: [
+            accounts.to_account_metas(Some(true)),
+            device.remaining_accounts.to_account_metas(),
+        ]
+        .concat(),
+        data: instruction_data.data(),
+    });
+    let mut signers = vec![owner];
+    signers.extend(admin);
+    let event = rpc
+        .create_and_send_transaction_with_event(&instructions, &owner.pubkey(), &signers, None)
+        .await?;
+    test_indexer.add_compressed_accounts_with_token_data(&event.unwrap().0);
+    Ok(())
+}
+
+const CERTIFICATE_TREE_DEPTH: usize = 3;
+const CERTIFICATE_TREE_BUFFER_SIZE: usize = 8;
+
+fn certificate_authority() -> Pubkey {
+    Pubkey::find_program_address(&[CERTIFICATE_AUTHORITY_SEED], &PROGRAM_ID).0
+}
+
+/// Certificate metadata exactly as `mint_device_certificate` builds it.
+fn certificate_metadata(uri_prefix: &str, device_address: [u8; 32]) -> MetadataArgs {
+    MetadataArgs {
+        name: CERTIFICATE_NAME.to_string(),
+        symbol: CERTIFICATE_SYMBOL.to_string(),
+        uri: format!("{}{}", uri_prefix, Pubkey::new_from_array(device_address)),
+        seller_fee_basis_points: 0,
+        primary_sale_happened: true,
+        is_mutable: false,
+        edition_nonce: None,
+        token_standard: Some(TokenStandard::NonFungible),
+        collection: None,
+        uses: None,
+        token_program_version: TokenProgramVersion::Original,
+        creators: Vec::new(),
+    }
+}
+
+/// A minted certificate, as needed to rebuild its Bubblegum leaf.
+struct CertificateLeaf {
+    owner: Pubkey,
+    data_hash: [u8; 32],
+    creator_hash: [u8; 32],
+}
+
+/// Local mirror of a Bubblegum certificate tree, used to build leaf proofs.
+struct CertificateTree {
+    tree: Pubkey,
+    leaves: Vec<CertificateLeaf>,
+}
+
+impl CertificateTree {
+    fn leaf_hash(&self, nonce: usize) -> [u8; 32] {
+        let leaf = &self.leaves[nonce];
+        LeafSchema::V1 {
+            id: get_asset_id(&self.tree, nonce as u64),
+            owner: leaf.owner,
+            delegate: leaf.owner,
+            nonce: nonce as u64,
+            data_hash: leaf.data_hash,
+            creator_hash: leaf.creator_hash,
+        }
+        .hash()
+    }
+
+    /// Node hashes from the leaves up to the root; empty leaves are zero.
+    fn levels(&self) -> Vec<Vec<[u8; 32]>> {
+        let mut level: Vec<[u8; 32]> = (0..self.leaves.len())
+            .map(|nonce| self.leaf_hash(nonce))
+            .collect();
+        level.resize(1 << CERTIFICATE_TREE_DEPTH, [0u8; 32]);
+        let mut levels = vec![level];
+        while levels.last().unwrap().len() > 1 {
+            let next = levels
+                .last()
+                .unwrap()
+                .chunks(2)
+                .map(|pair| hashv(&[&pair[0], &pair[1]]).to_bytes())
+                .collect();
+            levels.push(next);
+        }
+        levels
+    }
+
+    fn root(&self) -> [u8; 32] {
+        self.levels().last().unwrap()[0]
+    }
+
+    fn proof_accounts(&self, nonce: usize) -> Vec<AccountMeta> {
+        self.levels()[..CERTIFICATE_TREE_DEPTH]
+            .iter()
+            .enumerate()
+            .map(|(level, nodes)| {
+                AccountMeta::new_readonly(
+                    Pubkey::new_from_array(nodes[(nonce >> level) ^ 1]),
+                    false,
+                )
+            })
+            .collect()
+    }
+
+    /// Proof that the current owner holds certificate `nonce`, with the proof
+    /// nodes placed `proof_accounts_offset` accounts into the remaining accounts.
+    fn certificate_proof(&self, nonce: usize, proof_accounts_offset: usize) -> CertificateProof {
+        let leaf = &self.leaves[nonce];
+        CertificateProof {
+            root: self.root(),
+            nonce: nonce as u64,
+            delegate: leaf.owner,
+            data_hash: leaf.data_hash,
+            creator_hash: leaf.creator_hash,
+            proof_accounts_offset: proof_accounts_offset as u8,
+        }
+    }
+}
+
+/// Creates a Bubblegum tree delegated to the program's certificate authority.
+async fn create_certificate_tree<R: RpcConnection>(
+    rpc: &mut R,
+    payer: &Keypair,
+) -> CertificateTree {
+    let tree = Keypair::new();
+    // spl-account-compression header plus a concurrent Merkle tree without canopy
+    let path_size = 32 * CERTIFICATE_TREE_DEPTH + 40;
+    let size = 56 + 24 + (CERTIFICATE_TREE_BUFFER_SIZE + 1) * path_size;
+    let lamports = rpc
+        .get_minimum_balance_for_rent_exemption(size)
+        .await
+        .unwrap();
+    let create_account = system_instruction::create_account(
+        &payer.pubkey(),
+        &tree.pubkey(),
+        lamports,
+        size as u64,
+        &SPL_ACCOUNT_COMPRESSION_ID,
+    );
+    let tree_config = TreeConfig::find_pda(&tree.pubkey()).0;
+    let create_tree_config = CreateTreeConfigBuilder::new()
+        .tree_config(tree_config)
+        .merkle_tree(tree.pubkey())
+        .payer(payer.pubkey())
+        .tree_creator(payer.pubkey())
+        .log_wrapper(PROGRAM_ID_NOOP)
+        .compression_program(SPL_ACCOUNT_COMPRESSION_ID)
+        .max_depth(CERTIFICATE_TREE_DEPTH as u32)
+        .max_buffer_size(CERTIFICATE_TREE_BUFFER_SIZE as u32)
+        .instruction();
+    let set_tree_delegate = SetTreeDelegateBuilder::new()
+        .tree_config(tree_config)
+        .tree_creator(payer.pubkey())
+        .new_tree_delegate(certificate_authority())
+        .merkle_tree(tree.pubkey())
+        .instruction();
+    rpc.create_and_send_transaction(
+        &[create_account, create_tree_config, set_tree_delegate],
+        &payer.pubkey(),
+        &[payer, &tree],
+    )
+    .await
+    .unwrap();
+    CertificateTree {
+        tree: tree.pubkey(),
+        leaves: Vec::new(),
+    }
+}
+
+async fn mint_device_certificate<R: RpcConnection + MerkleTreeExt>(
+    rpc: &mut R,
+    test_indexer: &mut TestIndexer<R>,
+    owner: &Keypair,
+    device_address: [u8; 32],
+    certificate_tree: &mut CertificateTree,
+    uri_prefix: &str,
+) -> Result<(), RpcError> {
+    let device = prove_device(rpc, test_indexer, device_address).await;
+    let accounts = chainsensor::accounts::MintDeviceCertificate {
+        owner: owner.pubkey(),
+        cpi_signer: cpi_signer().0,
+        self_program: PROGRAM_ID,
+        light_system_program: PROGRAM_ID_LIGHT_SYSTEM,
+        account_compression_program: PROGRAM_ID_ACCOUNT_COMPRESSION,
+        account_compression_authority: get_cpi_authority_pda(&PROGRAM_ID_LIGHT_SYSTEM),
+        registered_program_pda: registered_program_pda(),
+        noop_program: PROGRAM_ID_NOOP,
+        system_program: solana_sdk::system_program::id(),
+        certificate_authority: certificate_authority(),
+        tree_config: TreeConfig::find_pda(&certificate_tree.tree).0,
+        certificate_tree: certificate_tree.tree,
+        bubblegum_program: mpl_bubblegum::ID,
+        compression_program: SPL_ACCOUNT_COMPRESSION_ID,
+    };
+    let instruction_data = chainsensor::instruction::MintDeviceCertificate {
+        device_address,
+        device: device.input,
+        uri_prefix: uri_prefix.to_string(),
+    };
+    send_light_instruction(
+        rpc,
+        test_indexer,
+        owner,
+        accounts,
+        instruction_data,
+        &device.remaining_accounts,
+    )
+    .await?;
+    let metadata = certificate_metadata(uri_prefix, device_address);
+    certificate_tree.leaves.push(CertificateLeaf {
+        owner: owner.pubkey(),
+        data_hash: hash_metadata(&metadata).unwrap(),
+        creator_hash: hash_creators(&metadata.creators),
+    });
+    Ok(())
+}
+
+/// Sells certificate `nonce` to `new_owner` through Bubblegum, outside the program.
+async fn transfer_certificate<R: RpcConnection>(
+    rpc: &mut R,
+    certificate_tree: &mut CertificateTree,
+    owner: &Keypair,
+    nonce: usize,
+    new_owner: Pubkey,
+) {
+    let leaf = &certificate_tree.leaves[nonce];
+    let instruction = TransferBuilder::new()
+        .tree_config(TreeConfig::find_pda(&certificate_tree.tree).0)
+        .leaf_owner(owner.pubkey(), true)
+        .leaf_delegate(owner.pubkey(), false)
+        .new_leaf_owner(new_owner)
+        .merkle_tree(certificate_tree.tree)
+        .log_wrapper(PROGRAM_ID_NOOP)
+        .compression_program(SPL_ACCOUNT_COMPRESSION_ID)
+        .root(certificate_tree.root())
+        .data_hash(leaf.data_hash)
+        .creator_hash(leaf.creator_hash)
+        .nonce(nonce as u64)
+        .index(nonce as u32)
+        .add_remaining_accounts(&certificate_tree.proof_accounts(nonce))
+        .instruction();
+    rpc.create_and_send_transaction(&[instruction], &owner.pubkey(), &[owner])
+        .await
+        .unwrap();
+    certificate_tree.leaves[nonce].owner = new_owner;
+}
+
+/// Claims the device for `holder`, proving it holds certificate `nonce`.
+async fn claim_device<R: RpcConnection + MerkleTreeExt>(
+    rpc: &mut R,
+    test_indexer: &mut TestIndexer<R>,
+    holder: &Keypair,
+    device_address: [u8; 32],
+    certificate_tree: &CertificateTree,
+    nonce: usize,
+) -> Result<(), RpcError> {
+    let device = prove_device(rpc, test_indexer, device_address).await;
+    let accounts = chainsensor::accounts::ClaimDevice {
+        holder: holder.pubkey(),
+        cpi_signer: cpi_signer().0,
+        self_program: PROGRAM_ID,
+        light_system_program: PROGRAM_ID_LIGHT_SYSTEM,
+        account_compression_program: PROGRAM_ID_ACCOUNT_COMPRESSION,
+        account_compression_authority: get_cpi_authority_pda(&PROGRAM_ID_LIGHT_SYSTEM),
+        registered_program_pda: registered_program_pda(),
+        noop_program: PROGRAM_ID_NOOP,
+        system_program: solana_sdk::system_program::id(),
+        certificate_tree: certificate_tree.tree,
+        compression_program: SPL_ACCOUNT_COMPRESSION_ID,
+        heartbeat: heartbeat_pda(&device_address),
+    };
+    // The certificate proof nodes follow the light accounts
+    let light_accounts = device.remaining_accounts.to_account_metas();
+    let instruction_data = chainsensor::instruction::ClaimDevice {
+        device_address,
+        device: device.input,
+        certificate_proof: certificate_tree.certificate_proof(nonce, light_accounts.len()),
+    };
+    let instruction = Instruction {
+        program_id: PROGRAM_ID,
+        accounts: [
+            accounts.to_account_metas(Some(true)),
+            light_accounts,
+            certificate_tree.proof_accounts(nonce),
+        ]
+        .concat(),
+        data: instruction_data.data(),
+    };
+    let event = rpc
+        .create_and_send_transaction_with_event(&[instruction], &holder.pubkey(), &[holder], None)
+        .await?;
+    test_indexer.add_compressed_accounts_with_token_data(&event.unwrap().0);
+    Ok(())
+}
+
+/// Deserializes the current compressed record of the device at `address`.
+fn current_device<R: RpcConnection + MerkleTreeExt>(
+    test_indexer: &TestIndexer<R>,
+    address: [u8; 32],
+) -> CompressedDeviceRegistry {
+    let compressed_account = test_indexer
+        .get_compressed_accounts_by_owner(&PROGRAM_ID)
+        .into_iter()
+        .find(|account| account.compressed_account.address == Some(address))
+        .unwrap();
+    CompressedDeviceRegistry::deserialize(
+        &mut &compressed_account
+            .compressed_account
+            .data
+            .as_ref()
+            .unwrap()
+            .data[..],
+    )
+    .unwrap()
+}
+
+async fn send_admin_instruction<R: RpcConnection>(
+    rpc: &mut R,
+    signer: &Keypair,
+    accounts: impl ToAccountMetas,
+    data: impl InstructionData,
+) -> Result<(), RpcError> {
+    let instruction = Instruction {
+        program_id: PROGRAM_ID,
+        accounts: accounts.to_account_metas(Some(true)),
+        data: data.data(),
+    };
+    rpc.create_and_send_transaction(&[instruction], &signer.pubkey(), &[signer])
+        .await?;
+    Ok(())
+}
+
+async fn add_accepted_mint<R: RpcConnection>(
+    rpc: &mut R,
+    admin: &Keypair,
+    marketplace_key: Pubkey,
+    mint: Pubkey,
+) -> Result<(), RpcError> {
+    send_admin_instruction(
+        rpc,
+        admin,
+        chainsensor::accounts::AddAcceptedMint {
+            admin: admin.pubkey(),
+            marketplace: marketplace_key,
+            mint,
+            accepted_mint: accepted_mint_pda(&marketplace_key, &mint),
+            treasury: treasury_pda(&marketplace_key, &mint),
+            token_program: token_program_of(rpc, mint).await,
+            system_program: solana_sdk::system_program::id(),
+            rent: solana_sdk::sysvar::rent::id(),
+        },
+        chainsensor::instruction::AddAcceptedMint {},
+    )
+    .await
+}
+
+async fn remove_accepted_mint<R: RpcConnection>(
+    rpc: &mut R,
+    admin: &Keypair,
+    marketplace_key: Pubkey,
+    mint: Pubkey,
+) -> Result<(), RpcError> {
+    send_admin_instruction(
+        rpc,
+        admin,
+        chainsensor::accounts::RemoveAcceptedMint {
+            admin: admin.pubkey(),
+            marketplace: marketplace_key,
+            accepted_mint: accepted_mint_pda(&marketplace_key, &mint),
+        },
+        chainsensor::instruction::RemoveAcceptedMint {},
+    )
+    .await
+}
+
+async fn set_registration_policy<R: RpcConnection>(
+    rpc: &mut R,
+    admin: &Keypair,
+    marketplace_key: Pubkey,
+    registration_policy: u8,
+) -> Result<(), RpcError> {
+    send_admin_instruction(
+        rpc,
+        admin,
+        chainsensor::accounts::SetRegistrationPolicy {
+            admin: admin.pubkey(),
+            marketplace: marketplace_key,
+        },
+        chainsensor::instruction::SetRegistrationPolicy {
+            registration_policy,
+        },
+    )
+    .await
+}
+
+/// Sets the `data_type` fee schedule to `(seller_fee, tier_fees)`, or removes it
+/// when `fees` is `None`.
+async fn set_fee_schedule<R: RpcConnection>(
+    rpc: &mut R,
+    admin: &Keypair,
+    marketplace_key: Pubkey,
+    data_type: [u8; 32],
+    fees: Option<(u16, Vec<u16>)>,
+) -> Result<(), RpcError> {
+    let fee_schedule = fee_schedule_pda(&marketplace_key, &data_type);
+    match fees {
+        Some((seller_fee, tier_fees)) => {
+            send_admin_instruction(
+                rpc,
+                admin,
+                chainsensor::accounts::SetFeeSchedule {
+                    admin: admin.pubkey(),
+                    marketplace: marketplace_key,
+                    fee_schedule,
+                    system_program: solana_sdk::system_program::id(),
+                },
+                chainsensor::instruction::SetFeeSchedule {
+                    data_type,
+                    seller_fee,
+                    tier_fees,
+                },
+            )
+            .await
+        }
+        None => {
+            send_admin_instruction(
+                rpc,
+                admin,
+                chainsensor::accounts::RemoveFeeSchedule {
+                    admin: admin.pubkey(),
+                    marketplace: marketplace_key,
+                    fee_schedule,
+                },
+                chainsensor::instruction::RemoveFeeSchedule { data_type },
+            )
+            .await
+        }
+    }
+}
+
+async fn set_seller_tier<R: RpcConnection>(
+    rpc: &mut R,
+    admin: &Keypair,
+    marketplace_key: Pubkey,
+    seller: Pubkey,
+    tier: u8,
+) -> Result<(), RpcError> {
+    send_admin_instruction(
+        rpc,
+        admin,
+        chainsensor::accounts::SetSellerTier {
+            admin: admin.pubkey(),
+            marketplace: marketplace_key,
+            seller_tier: seller_tier_pda(&marketplace_key, &seller),
+            system_program: solana_sdk::system_program::id(),
+        },
+        chainsensor::instruction::SetSellerTier { seller, tier },
+    )
+    .await
+}
+
+async fn set_guardian<R: RpcConnection>(
+    rpc: &mut R,
+    admin: &Keypair,
+    marketplace_key: Pubkey,
+    guardian: Option<Pubkey>,
+) -> Result<(), RpcError> {
+    send_admin_instruction(
+        rpc,
+        admin,
+        chainsensor::accounts::SetGuardian {
+            admin: admin.pubkey(),
+            marketplace: marketplace_key,
+        },
+        chainsensor::instruction::SetGuardian { guardian },
+    )
+    .await
+}
+
+/// Sets the `flags` pause bits when `paused`, clears them otherwise.
+async fn set_paused<R: RpcConnection>(
+    rpc: &mut R,
+    authority: &Keypair,
+    marketplace_key: Pubkey,
+    flags: u8,
+    paused: bool,
+) -> Result<(), RpcError> {
+    if paused {
+        send_admin_instruction(
+            rpc,
+            authority,
+            chainsensor::accounts::PauseMarketplace {
+                authority: authority.pubkey(),
+                marketplace: marketplace_key,
+            },
+            chainsensor::instruction::PauseMarketplace { flags },
+        )
+        .await
+    } else {
+        send_admin_instruction(
+            rpc,
+            authority,
+            chainsensor::accounts::UnpauseMarketplace {
+                admin: authority.pubkey(),
+                marketplace: marketplace_key,
+            },
+            chainsensor::instruction::UnpauseMarketplace { flags },
+        )
+        .await
+    }
+}
+
+/// Bans the device with `reason`, or lifts the ban when `reason` is `None`.
+async fn set_device_ban<R: RpcConnection>(
+    rpc: &mut R,
+    admin: &Keypair,
+    marketplace_key: Pubkey,
+    device_address: [u8; 32],
+    reason: Option<u8>,
+) -> Result<(), RpcError> {
+    let device_ban = device_ban_pda(&marketplace_key, &device_address);
+    match reason {
+        Some(reason) => {
+            send_admin_instruction(
+                rpc,
+                admin,
+                chainsensor::accounts::BanDevice {
+                    admin: admin.pubkey(),
+                    marketplace: marketplace_key,
+                    device_ban,
+                    system_program: solana_sdk::system_program::id(),
+                },
+                chainsensor::instruction::BanDevice {
+                    device_address,
+                    reason,
+                },
+            )
+            .await
+        }
+        None => {
+            send_admin_instruction(
+                rpc,
+                admin,
+                chainsensor::accounts::UnbanDevice {
+                    admin: admin.pubkey(),
+                    marketplace: marketplace_key,
+                    device_ban,
+                },
+                chainsensor::instruction::UnbanDevice { device_address },
+            )
+            .await
+        }
+    }
+}
+
+async fn update_marketplace<R: RpcConnection>(
+    rpc: &mut R,
+    admin: &Keypair,
+    marketplace_key: Pubkey,
+    name: Option<&str>,
+    seller_fee: Option<u16>,
+    is_active: Option<bool>,
+) -> Result<(), RpcError> {
+    send_admin_instruction(
+        rpc,
+        admin,
+        chainsensor::accounts::UpdateMarketplace {
+            admin: admin.pubkey(),
+            marketplace: marketplace_key,
+        },
+        chainsensor::instruction::UpdateMarketplace {
+            name: name.map(str::to_string),
+            seller_fee,
+            is_active,
+        },
+    )
+    .await
+}
+
+/// Proposes `new_admin`, or withdraws the proposal when it is `None`.
+async fn propose_admin<R: RpcConnection>(
+    rpc: &mut R,
+    admin: &Keypair,
+    marketplace_key: Pubkey,
+    new_admin: Option<Pubkey>,
+) -> Result<(), RpcError> {
+    send_admin_instruction(
+        rpc,
+        admin,
+        chainsensor::accounts::ProposeAdmin {
+            admin: admin.pubkey(),
+            marketplace: marketplace_key,
+        },
+        chainsensor::instruction::ProposeAdmin { new_admin },
+    )
+    .await
+}
+
+async fn accept_admin<R: RpcConnection>(
+    rpc: &mut R,
+    new_admin: &Keypair,
+    marketplace_key: Pubkey,
+) -> Result<(), RpcError> {
+    send_admin_instruction(
+        rpc,
+        new_admin,
+        chainsensor::accounts::AcceptAdmin {
+            new_admin: new_admin.pubkey(),
+            marketplace: marketplace_key,
+        },
+        chainsensor::instruction::AcceptAdmin {},
+    )
+    .await
+}
+
+fn allowlist_pda(marketplace_key: &Pubkey, seller: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(
+        &[b"allowlist", marketplace_key.as_ref(), seller.as_ref()],
+        &PROGRAM_ID,
+    )
+    .0
+}
+
+/// Initializes the marketplace and registers one device owned by the payer.
+async fn setup_with_device() -> (
+    ProgramTestRpcConnection,
+    TestIndexer<ProgramTestRpcConnection>,
+    EnvAccounts,
+    Keypair,
+    Pubkey,
+    [u8; 32],
+) {
+    let (mut rpc, mut test_indexer, env, payer) = setup().await;
+    let marketplace_key = initialize_marketplace(&mut rpc, &payer, "TestMarket", 500)
+        .await
+        .unwrap();
+    let address = register_device(
+        &mut rpc,
+        &mut test_indexer,
+        &env,
+        &payer,
+        marketplace_key,
+        padded_device_id("device1"),
+    )
+    .await
+    .unwrap();
+    (rpc, test_indexer, env, payer, marketplace_key, address)
+}
+
+/// Like `setup_with_device`, with a certificate minted for the device. Needs
+/// `mpl_bubblegum.so` and `spl_account_compression.so` in the test fixtures.
+async fn setup_with_certified_device() -> (
+    ProgramTestRpcConnection,
+    TestIndexer<ProgramTestRpcConnection>,
+    Keypair,
+    [u8; 32],
+    CertificateTree,
+) {
+    let (mut rpc, mut test_indexer, env, payer) = setup_with_programs(vec![
+        (String::from("mpl_bubblegum"), mpl_bubblegum::ID),
+        (
+            String::from("spl_account_compression"),
+            SPL_ACCOUNT_COMPRESSION_ID,
+        ),
+    ])
+    .await;
+    let marketplace_key = initialize_marketplace(&mut rpc, &payer, "TestMarket", 500)
+        .await
+        .unwrap();
+    let address = register_device(
+        &mut rpc,
+        &mut test_indexer,
+        &env,
+        &payer,
+        marketplace_key,
+        padded_device_id("device1"),
+    )
+    .await
+    .unwrap();
+    let mut certificate_tree = create_certificate_tree(&mut rpc, &payer).await;
+    mint_device_certificate(
+        &mut rpc,
+        &mut test_indexer,
+        &payer,
+        address,
+        &mut certificate_tree,
+        "https://chainsensors.io/devices/",
+    )
+    .await
+    .unwrap();
+    (rpc, test_indexer, payer, address, certificate_tree)
+}
+
+#[tokio::test]
+async fn test_initialize_marketplace_success() {
+    let (mut rpc, _, _, payer) = setup().await;
+
+    let marketplace_key = initialize_marketplace(&mut rpc, &payer, "TestMarket", 500)
+        .await
+        .unwrap();
+
+    let marketplace: Marketplace = get_anchor_account(&mut rpc, marketplace_key).await;
+    assert_eq!(marketplace.admin, payer.pubkey());
+    assert_eq!(
+        marketplace.treasury,
+        treasury_pda(&marketplace_key, &marketplace.token_mint)
+    );
+    let treasury: TokenAccount = get_anchor_account(&mut rpc, marketplace.treasury).await;
+    assert_eq!(treasury.mint, marketplace.token_mint);
+    assert_eq!(treasury.owner, marketplace.treasury);
+    let accepted_mint: AcceptedMint = get_anchor_account(
+        &mut rpc,
+        accepted_mint_pda(&marketplace_key, &marketplace.token_mint),
+    )
+    .await;
+    assert_eq!(accepted_mint.treasury, marketplace.treasury);
+    assert_eq!(marketplace.name, "TestMarket");
+    assert_eq!(marketplace.seller_fee, 500);
+    assert!(marketplace.is_active);
+}
+
+#[tokio::test]
+async fn test_initialize_marketplace_name_too_long() {
+    let (mut rpc, _, _, payer) = setup().await;
+
+    let name = "a".repeat(33); // Exceeds 32 characters
+    let result = initialize_marketplace(&mut rpc, &payer, &name, 500).await;
+    assert_rpc_error(result, 0, ErrorCode::NameTooLong.into()).unwrap();
+}
+
+#[tokio::test]
+async fn test_initialize_marketplace_name_empty() {
+    let (mut rpc, _, _, payer) = setup().await;
+
+    let result = initialize_marketplace(&mut rpc, &payer, "", 500).await;
+    assert_rpc_error(result, 0, ErrorCode::NameEmpty.into()).unwrap();
+}
+
+#[tokio::test]
+async fn test_initialize_marketplace_invalid_name_chars() {
+    let (mut rpc, _, _, payer) = setup().await;
+
+    let result = initialize_marketplace(&mut rpc, &payer, "Test-Market!", 500).await;
+    assert_rpc_error(result, 0, ErrorCode::InvalidNameChars.into()).unwrap();
+}
+
+#[tokio::test]
+async fn test_initialize_marketplace_invalid_fee() {
+    let (mut rpc, _, _, payer) = setup().await;
+
+    let result = initialize_marketplace(&mut rpc, &payer, "TestMarket", 10_001).await;
+    assert_rpc_error(result, 0, ErrorCode::InvalidFee.into()).unwrap();
+}
+
+#[tokio::test]
+async fn test_update_marketplace() {
+    let (mut rpc, mut test_indexer, _, payer, marketplace_key, address) = setup_with_device().await;
+
+    update_marketplace(
+        &mut rpc,
+        &payer,
+        marketplace_key,
+        Some("Renamed Market"),
+        Some(250),
+        Some(false),
+    )
+    .await
+    .unwrap();
+    let marketplace: Marketplace = get_anchor_account(&mut rpc, marketplace_key).await;
+    assert_eq!(marketplace.name, "Renamed Market");
+    assert_eq!(marketplace.seller_fee, 250);
+    assert!(!marketplace.is_active);
+
+    let result = create_listing(
+        &mut rpc,
+        &mut test_indexer,
+        &payer,
+        marketplace_key,
+        address,
+        "listing1",
+        "device1",
+    )
+    .await;
+    assert_rpc_error(result, 0, ErrorCode::MarketplaceInactive.into()).unwrap();
+
+    // Unset fields are left unchanged
+    update_marketplace(&mut rpc, &payer, marketplace_key, None, None, Some(true))
+        .await
+        .unwrap();
+    let marketplace: Marketplace = get_anchor_account(&mut rpc, marketplace_key).await;
+    assert_eq!(marketplace.name, "Renamed Market");
+    assert_eq!(marketplace.seller_fee, 250);
+    assert!(marketplace.is_active);
+}
+
+#[tokio::test]
+async fn test_update_marketplace_invalid() {
+    let (mut rpc, _, _, payer) = setup().await;
+    let marketplace_key = initialize_marketplace(&mut rpc, &payer, "TestMarket", 500)
+        .await
+        .unwrap();
+
+    let result =
+        update_marketplace(&mut rpc, &payer, marketplace_key, None, Some(10001), None).await;
+    assert_rpc_error(result, 0, ErrorCode::InvalidFee.into()).unwrap();
+    let result =
+        update_marketplace(&mut rpc, &payer, marketplace_key, Some("bad!"), None, None).await;
+    assert_rpc_error(result, 0, ErrorCode::InvalidNameChars.into()).unwrap();
+    let stranger = funded_keypair(&mut rpc).await;
+    let result =
+        update_marketplace(&mut rpc, &stranger, marketplace_key, None, Some(0), None).await;
+    assert_rpc_error(result, 0, ErrorCode::AdminMismatch.into()).unwrap();
+}
+
+#[tokio::test]
+async fn test_marketplace_ids_are_per_creator() {
+    let (mut rpc, _, _, payer) = setup().await;
+
+    let first = initialize_marketplace_with_id(&mut rpc, &payer, "First", 500, 7)
+        .await
+        .unwrap();
+    let second = initialize_marketplace_with_id(&mut rpc, &payer, "Second", 500, 8)
+        .await
+        .unwrap();
+    assert_ne!(first, second);
+    let marketplace: Marketplace = get_anchor_account(&mut rpc, first).await;
+    assert_eq!(marketplace.marketplace_id, 7);
+    assert_eq!(marketplace.creator, payer.pubkey());
+
+    // Another wallet taking the same id gets its own marketplace
+    let other = funded_keypair(&mut rpc).await;
+    let other_key = initialize_marketplace_with_id(&mut rpc, &other, "Other", 500, 7)
+        .await
+        .unwrap();
+    assert_ne!(other_key, first);
+
+    let result = initialize_marketplace_with_id(&mut rpc, &payer, "Again", 500, 7).await;
+    assert!(result.is_err());
+}
+
+#[tokio::test]
+async fn test_two_step_admin_transfer() {
+    let (mut rpc, mut test_indexer, _, payer, marketplace_key, address) = setup_with_device().await;
+
+    let new_admin = funded_keypair(&mut rpc).await;
+    propose_admin(&mut rpc, &payer, marketplace_key, Some(new_admin.pubkey()))
+        .await
+        .unwrap();
+    accept_admin(&mut rpc, &new_admin, marketplace_key)
+        .await
+        .unwrap();
+    let marketplace: Marketplace = get_anchor_account(&mut rpc, marketplace_key).await;
+    assert_eq!(marketplace.admin, new_admin.pubkey());
+    assert_eq!(marketplace.creator, payer.pubkey());
+    assert_eq!(marketplace.pending_admin, None);
+
+    // The marketplace keeps its address, so its devices keep working
+    update_marketplace(&mut rpc, &new_admin, marketplace_key, None, Some(100), None)
+        .await
+        .unwrap();
+    let result = update_marketplace(&mut rpc, &payer, marketplace_key, None, Some(0), None).await;
+    assert_rpc_error(result, 0, ErrorCode::AdminMismatch.into()).unwrap();
+    create_listing(
+        &mut rpc,
+        &mut test_indexer,
+        &payer,
+        marketplace_key,
+        address,
+        "listing1",
+        "device1",
+    )
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn test_admin_transfer_rejections() {
+    let (mut rpc, _, _, payer) = setup().await;
+    let marketplace_key = initialize_marketplace(&mut rpc, &payer, "TestMarket", 500)
+        .await
+        .unwrap();
+
+    let result = propose_admin(&mut rpc, &payer, marketplace_key, Some(Pubkey::default())).await;
+    assert_rpc_error(result, 0, ErrorCode::InvalidNewAdmin.into()).unwrap();
+
+    let new_admin = funded_keypair(&mut rpc).await;
+    let result = propose_admin(
+        &mut rpc,
+        &new_admin,
+        marketplace_key,
+        Some(new_admin.pubkey()),
+    )
+    .await;
+    assert_rpc_error(result, 0, ErrorCode::AdminMismatch.into()).unwrap();
+
+    propose_admin(&mut rpc, &payer, marketplace_key, Some(new_admin.pubkey()))
+        .await
+        .unwrap();
+    let stranger = funded_keypair(&mut rpc).await;
+    let result = accept_admin(&mut rpc, &stranger, marketplace_key).await;
+    assert_rpc_error(result, 0, ErrorCode::NotPendingAdmin.into()).unwrap();
+
+    // A withdrawn proposal can no longer be accepted
+    propose_admin(&mut rpc, &payer, marketplace_key, None)
+        .await
+        .unwrap();
+    let result = accept_admin(&mut rpc, &new_admin, marketplace_key).await;
+    assert_rpc_error(result, 0, ErrorCode::NotPendingAdmin.into()).unwrap();
+}
+
+#[tokio::test]
+async fn test_register_device_success() {
+    let (_, test_indexer, _, payer, marketplace_key, address) = setup_with_device().await;
+
+    let compressed_accounts = test_indexer.get_compressed_accounts_by_owner(&PROGRAM_ID);
+    assert_eq!(compressed_accounts.len(), 1);
+    let compressed_account = &compressed_accounts[0];
+    assert_eq!(compressed_account.compressed_account.address, Some(address));
+
+    let device_data = &compressed_account
+        .compressed_account
+        .data
+        .as_ref()
+        .unwrap()
+        .data;
+    let device = CompressedDeviceRegistry::deserialize(&mut &device_data[..]).unwrap();
+    assert_eq!(device.owner, payer.pubkey());
+    assert_eq!(device.marketplace, marketplace_key);
+    assert_eq!(device.device_id, padded_device_id("device1"));
+    assert_eq!(
+        device.ek_pubkey_hash,
+        hash(device_key(&device.device_id).pubkey().as_ref()).to_bytes()
+    );
+    assert_eq!(device.metadata.data_type, padded_device_id("temperature"));
+}
+
+#[tokio::test]
+async fn test_register_device_empty_device_id() {
+    let (mut rpc, mut test_indexer, env, payer) = setup().await;
+    let marketplace_key = initialize_marketplace(&mut rpc, &payer, "TestMarket", 500)
+        .await
+        .unwrap();
+
+    let result = register_device(
+        &mut rpc,
+        &mut test_indexer,
+        &env,
+        &payer,
+        marketplace_key,
+        [0u8; 32],
+    )
+    .await;
+    assert_rpc_error(result, 1, ErrorCode::DeviceIdEmpty.into()).unwrap();
+}
+
+#[tokio::test]
+async fn test_register_device_signed_by_other_key() {
+    let (mut rpc, mut test_indexer, env, payer) = setup().await;
+    let marketplace_key = initialize_marketplace(&mut rpc, &payer, "TestMarket", 500)
+        .await
+        .unwrap();
+
+    let result = send_register_device(
+        &mut rpc,
+        &mut test_indexer,
+        &env,
+        &payer,
+        marketplace_key,
+        padded_device_id("device1"),
+        Some(&Keypair::new()),
+        None,
+        None,
+    )
+    .await;
+    assert_rpc_error(result, 1, ErrorCode::InvalidDeviceSignature.into()).unwrap();
+}
+
+#[tokio::test]
+async fn test_register_device_without_attestation() {
+    let (mut rpc, mut test_indexer, env, payer) = setup().await;
+    let marketplace_key = initialize_marketplace(&mut rpc, &payer, "TestMarket", 500)
+        .await
+        .unwrap();
+
+    let result = send_register_device(
+        &mut rpc,
+        &mut test_indexer,
+        &env,
+        &payer,
+        marketplace_key,
+        padded_device_id("device1"),
+        None,
+        None,
+        None,
+    )
+    .await;
+    assert_rpc_error(result, 0, ErrorCode::MissingDeviceSignature.into()).unwrap();
+}
+
+#[tokio::test]
+async fn test_register_multiple_devices_per_owner() {
+    let (mut rpc, mut test_indexer, env, payer, marketplace_key, first) = setup_with_device().await;
+
+    // Addresses are derived from the marketplace and device ID, so one owner
+    // can hold several devices
+    let second = register_device(
+        &mut rpc,
+        &mut test_indexer,
+        &env,
+        &payer,
+        marketplace_key,
+        padded_device_id("device2"),
+    )
+    .await
+    .unwrap();
+    assert_ne!(first, second);
+    assert_eq!(
+        test_indexer
+            .get_compressed_accounts_by_owner(&PROGRAM_ID)
+            .len(),
+        2
+    );
+}
+
+#[tokio::test]
+async fn test_register_devices() {
+    let (mut rpc, mut test_indexer, env, payer) = setup().await;
+    let marketplace_key = initialize_marketplace(&mut rpc, &payer, "TestMarket", 500)
+        .await
+        .unwrap();
+
+    let device_ids = [padded_device_id("device1"), padded_device_id("device2")];
+    let addresses = register_devices(
+        &mut rpc,
+        &mut test_indexer,
+        &env,
+        &payer,
+        marketplace_key,
+        &device_ids,
+    )
+    .await
+    .unwrap();
+
+    for (device_id, address) in device_ids.iter().zip(&addresses) {
+        let device = current_device(&test_indexer, *address);
+        assert_eq!(device.owner, payer.pubkey());
+        assert_eq!(device.device_id, *device_id);
+        assert_eq!(
+            device.ek_pubkey_hash,
+            hash(device_key(device_id).pubkey().as_ref()).to_bytes()
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_register_devices_empty_device_id() {
+    let (mut rpc, mut test_indexer, env, payer) = setup().await;
+    let marketplace_key = initialize_marketplace(&mut rpc, &payer, "TestMarket", 500)
+        .await
+        .unwrap();
+
+    let result = register_devices(
+        &mut rpc,
+        &mut test_indexer,
+        &env,
+        &payer,
+        marketplace_key,
+        &[padded_device_id("device1"), [0u8; 32]],
+    )
+    .await;
+    assert_rpc_error(result, 2, ErrorCode::DeviceIdEmpty.into()).unwrap();
+}
+
+#[tokio::test]
+async fn test_set_registration_policy() {
+    let (mut rpc, _, _, payer) = setup().await;
+    let marketplace_key = initialize_marketplace(&mut rpc, &payer, "TestMarket", 500)
+        .await
+        .unwrap();
+
+    set_registration_policy(&mut rpc, &payer, marketplace_key, REGISTRATION_ADMIN_COSIGN)
+        .await
+        .unwrap();
+    let marketplace: Marketplace = get_anchor_account(&mut rpc, marketplace_key).await;
+    assert_eq!(marketplace.registration_policy, REGISTRATION_ADMIN_COSIGN);
+
+    let result = set_registration_policy(&mut rpc, &payer, marketplace_key, 3).await;
+    assert_rpc_error(result, 0, ErrorCode::InvalidRegistrationPolicy.into()).unwrap();
+
+    // Only the marketplace admin can change the policy
+    let stranger = funded_keypair(&mut rpc).await;
+    let result =
+        set_registration_policy(&mut rpc, &stranger, marketplace_key, REGISTRATION_OPEN).await;
+    assert_rpc_error(result, 0, ErrorCode::AdminMismatch.into()).unwrap();
+}
+
+#[tokio::test]
+async fn test_register_device_open_policy() {
+    let (mut rpc, mut test_indexer, env, payer) = setup().await;
+    let marketplace_key = initialize_marketplace(&mut rpc, &payer, "TestMarket", 500)
+        .await
+        .unwrap();
+
+    let seller = funded_keypair(&mut rpc).await;
+    register_device(
+        &mut rpc,
+        &mut test_indexer,
+        &env,
+        &seller,
+        marketplace_key,
+        padded_device_id("device1"),
+    )
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn test_register_device_allowlist_policy() {
+    let (mut rpc, mut test_indexer, env, payer) = setup().await;
+    let marketplace_key = initialize_marketplace(&mut rpc, &payer, "TestMarket", 500)
+        .await
+        .unwrap();
+    set_registration_policy(&mut rpc, &payer, marketplace_key, REGISTRATION_ALLOWLIST)
+        .await
+        .unwrap();
+    let seller = funded_keypair(&mut rpc).await;
+    let allowlist_entry = allowlist_pda(&marketplace_key, &seller.pubkey());
+
+    let result = register_device(
+        &mut rpc,
+        &mut test_indexer,
+        &env,
+        &seller,
+        marketplace_key,
+        padded_device_id("device1"),
+    )
+    .await;
+    assert_rpc_error(result, 1, ErrorCode::SellerNotAllowlisted.into()).unwrap();
+
+    let allowlist_accounts = chainsensor::accounts::AllowlistSeller {
+        admin: payer.pubkey(),
+        marketplace: marketplace_key,
+        allowlist_entry,
+        system_program: solana_sdk::system_program::id(),
+    };
+    send_admin_instruction(
+        &mut rpc,
+        &payer,
+        allowlist_accounts,
+        chainsensor::instruction::AllowlistSeller {
+            seller: seller.pubkey(),
+        },
+    )
+    .await
+    .unwrap();
+    register_device_with_policy(
+        &mut rpc,
+        &mut test_indexer,
+        &env,
+        &seller,
+        marketplace_key,
+        padded_device_id("device1"),
+        Some(allowlist_entry),
+        None,
+    )
+    .await
+    .unwrap();
+
+    let revoke_accounts = chainsensor::accounts::RevokeSeller {
+        admin: payer.pubkey(),
+        marketplace: marketplace_key,
+        allowlist_entry,
+    };
+    send_admin_instruction(
+        &mut rpc,
+        &payer,
+        revoke_accounts,
+        chainsensor::instruction::RevokeSeller {
+            seller: seller.pubkey(),
+        },
+    )
+    .await
+    .unwrap();
+    assert!(rpc.get_account(allowlist_entry).await.unwrap().is_none());
+    let result = register_device(
+        &mut rpc,
+        &mut test_indexer,
+        &env,
+        &seller,
+        marketplace_key,
+        padded_device_id("device2"),
+    )
+    .await;
+    assert_rpc_error(result, 1, ErrorCode::SellerNotAllowlisted.into()).unwrap();
+}
+
+#[tokio::test]
+async fn test_register_device_admin_cosign_policy() {
+    let (mut rpc, mut test_indexer, env, payer) = setup().await;
+    let marketplace_key = initialize_marketplace(&mut rpc, &payer, "TestMarket", 500)
+        .await
+        .unwrap();
+    set_registration_policy(&mut rpc, &payer, marketplace_key, REGISTRATION_ADMIN_COSIGN)
+        .await
+        .unwrap();
+    let seller = funded_keypair(&mut rpc).await;
+
+    let result = register_device(
+        &mut rpc,
+        &mut test_indexer,
+        &env,
+        &seller,
+        marketplace_key,
+        padded_device_id("device1"),
+    )
+    .await;
+    assert_rpc_error(result, 1, ErrorCode::AdminSignatureRequired.into()).unwrap();
+
+    let stranger = funded_keypair(&mut rpc).await;
+    let result = register_device_with_policy(
+        &mut rpc,
+        &mut test_indexer,
+        &env,
+        &seller,
+        marketplace_key,
+        padded_device_id("device1"),
+        None,
+        Some(&stranger),
+    )
+    .await;
+    assert_rpc_error(result, 1, ErrorCode::AdminMismatch.into()).unwrap();
+
+    register_device_with_policy(
+        &mut rpc,
+        &mut test_indexer,
+        &env,
+        &seller,
+        marketplace_key,
+        padded_device_id("device1"),
+        None,
+        Some(&payer),
+    )
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn test_update_device() {
+    let (mut rpc, mut test_indexer, _, payer, _, address) = setup_with_device().await;
+
+    update_device(
+        &mut rpc,
+        &mut test_indexer,
+        &payer,
+        address,
+        padded_device_id("air-quality"),
+    )
+    .await
+    .unwrap();
+    let device = current_device(&test_indexer, address);
+    assert_eq!(device.metadata.device_type, padded_device_id("air-quality"));
+    assert_eq!(device.metadata.data_type, padded_device_id("humidity"));
+    assert_eq!(device.metadata.data_unit, padded_device_id("celsius"));
+    assert_eq!(device.owner, payer.pubkey());
+
+    let result = update_device(&mut rpc, &mut test_indexer, &payer, address, [0u8; 32]).await;
+    assert_rpc_error(result, 0, ErrorCode::DeviceTypeTooLong.into()).unwrap();
+}
+
+#[tokio::test]
+async fn test_update_device_not_owner() {
+    let (mut rpc, mut test_indexer, _, _, _, address) = setup_with_device().await;
+
+    let stranger = funded_keypair(&mut rpc).await;
+    let result = update_device(
+        &mut rpc,
+        &mut test_indexer,
+        &stranger,
+        address,
+        padded_device_id("air-quality"),
+    )
+    .await;
+    assert_rpc_error(result, 0, ErrorCode::DeviceOwnerMismatch.into()).unwrap();
+}
+
+#[tokio::test]
+async fn test_deactivate_and_reactivate_device() {
+    let (mut rpc, mut test_indexer, _, payer, marketplace_key, address) = setup_with_device().await;
+
+    set_device_status(
+        &mut rpc,
+        &mut test_indexer,
+        &payer,
+        marketplace_key,
+        address,
+        false,
+    )
+    .await
+    .unwrap();
+    let device = current_device(&test_indexer, address);
+    assert!(!device.is_active);
+    assert!(device.deactivated_at > 0);
+
+    let result = set_device_status(
+        &mut rpc,
+        &mut test_indexer,
+        &payer,
+        marketplace_key,
+        address,
+        false,
+    )
+    .await;
+    assert_rpc_error(result, 0, ErrorCode::DeviceInactive.into()).unwrap();
+    let result = create_listing(
+        &mut rpc,
+        &mut test_indexer,
+        &payer,
+        marketplace_key,
+        address,
+        "listing1",
+        "device1",
+    )
+    .await;
+    assert_rpc_error(result, 0, ErrorCode::DeviceInactive.into()).unwrap();
+
+    set_device_status(
+        &mut rpc,
+        &mut test_indexer,
+        &payer,
+        marketplace_key,
+        address,
+        true,
+    )
+    .await
+    .unwrap();
+    let device = current_device(&test_indexer, address);
+    assert!(device.is_active);
+    assert_eq!(device.deactivated_at, 0);
+}
+
+#[tokio::test]
+async fn test_device_status_unauthorized() {
+    let (mut rpc, mut test_indexer, _, _, marketplace_key, address) = setup_with_device().await;
+
+    let stranger = funded_keypair(&mut rpc).await;
+    let result = set_device_status(
+        &mut rpc,
+        &mut test_indexer,
+        &stranger,
+        marketplace_key,
+        address,
+        false,
+    )
+    .await;
+    assert_rpc_error(result, 0, ErrorCode::DeviceStatusUnauthorized.into()).unwrap();
+}
+
+#[tokio::test]
+async fn test_admin_deactivation_needs_admin_to_reactivate() {
+    let (mut rpc, mut test_indexer, env, payer) = setup().await;
+    let marketplace_key = initialize_marketplace(&mut rpc, &payer, "TestMarket", 500)
+        .await
+        .unwrap();
+    let seller = funded_keypair(&mut rpc).await;
+    let address = register_device(
+        &mut rpc,
+        &mut test_indexer,
+        &env,
+        &seller,
+        marketplace_key,
+        padded_device_id("device1"),
+    )
+    .await
+    .unwrap();
+
+    set_device_status(
+        &mut rpc,
+        &mut test_indexer,
+        &payer,
+        marketplace_key,
+        address,
+        false,
+    )
+    .await
+    .unwrap();
+    assert!(current_device(&test_indexer, address).deactivated_by_admin);
+
+    let result = set_device_status(
+        &mut rpc,
+        &mut test_indexer,
+        &seller,
+        marketplace_key,
+        address,
+        true,
+    )
+    .await;
+    assert_rpc_error(result, 0, ErrorCode::DeactivatedByAdmin.into()).unwrap();
+
+    set_device_status(
+        &mut rpc,
+        &mut test_indexer,
+        &payer,
+        marketplace_key,
+        address,
+        true,
+    )
+    .await
+    .unwrap();
+    let device = current_device(&test_indexer, address);
+    assert!(device.is_active);
+    assert!(!device.deactivated_by_admin);
+}
+
+#[tokio::test]
+async fn test_transfer_device() {
+    let (mut rpc, mut test_indexer, _, payer, _, address) = setup_with_device().await;
+    let new_owner = funded_keypair(&mut rpc).await;
+
+    transfer_device(
+        &mut rpc,
+        &mut test_indexer,
+        &payer,
+        address,
+        new_owner.pubkey(),
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        current_device(&test_indexer, address).owner,
+        new_owner.pubkey()
+    );
+
+    // The previous owner lost control, the new owner has it
+    let result = update_device(
+        &mut rpc,
+        &mut test_indexer,
+        &payer,
+        address,
+        padded_device_id("air-quality"),
+    )
+    .await;
+    assert_rpc_error(result, 0, ErrorCode::DeviceOwnerMismatch.into()).unwrap();
+    update_device(
+        &mut rpc,
+        &mut test_indexer,
+        &new_owner,
+        address,
+        padded_device_id("air-quality"),
+    )
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn test_transfer_device_invalid_new_owner() {
+    let (mut rpc, mut test_indexer, _, payer, _, address) = setup_with_device().await;
+
+    let result =
+        transfer_device(&mut rpc, &mut test_indexer, &payer, address, payer.pubkey()).await;
+    assert_rpc_error(result, 0, ErrorCode::InvalidNewOwner.into()).unwrap();
+    let result = transfer_device(
+        &mut rpc,
+        &mut test_indexer,
+        &payer,
+        address,
+        Pubkey::default(),
+    )
+    .await;
+    assert_rpc_error(result, 0, ErrorCode::InvalidNewOwner.into()).unwrap();
+}
+
+#[tokio::test]
+async fn test_transfer_device_not_owner() {
+    let (mut rpc, mut test_indexer, _, _, _, address) = setup_with_device().await;
+
+    let stranger = funded_keypair(&mut rpc).await;
+    let result = transfer_device(
+        &mut rpc,
+        &mut test_indexer,
+        &stranger,
+        address,
+        stranger.pubkey(),
+    )
+    .await;
+    assert_rpc_error(result, 0, ErrorCode::DeviceOwnerMismatch.into()).unwrap();
+}
+
+#[tokio::test]
+async fn test_transfer_device_with_open_listing() {
+    let (mut rpc, mut test_indexer, _, payer, marketplace_key, address) = setup_with_device().await;
+    create_listing(
+        &mut rpc,
+        &mut test_indexer,
+        &payer,
+        marketplace_key,
+        address,
+        "listing1",
+        "device1",
+    )
+    .await
+    .unwrap();
+
+    let new_owner = funded_keypair(&mut rpc).await;
+    let result = transfer_device(
+        &mut rpc,
+        &mut test_indexer,
+        &payer,
+        address,
+        new_owner.pubkey(),
+    )
+    .await;
+    assert_rpc_error(result, 0, ErrorCode::DeviceHasActiveListings.into()).unwrap();
+}
+
+#[tokio::test]
+async fn test_close_device() {
+    let (mut rpc, mut test_indexer, _, payer, marketplace_key, address) = setup_with_device().await;
+    create_listing(
+        &mut rpc,
+        &mut test_indexer,
+        &payer,
+        marketplace_key,
+        address,
+        "listing1",
+        "device1",
+    )
+    .await
+    .unwrap();
+
+    let result = close_device(&mut rpc, &mut test_indexer, &payer, address).await;
+    assert_rpc_error(result, 0, ErrorCode::DeviceHasActiveListings.into()).unwrap();
+
+    cancel_listing(&mut rpc, &mut test_indexer, &payer, address, "listing1")
+        .await
+        .unwrap();
+    close_device(&mut rpc, &mut test_indexer, &payer, address)
+        .await
+        .unwrap();
+    assert!(test_indexer
+        .get_compressed_accounts_by_owner(&PROGRAM_ID)
+        .is_empty());
+}
+
+#[tokio::test]
+async fn test_close_device_not_owner() {
+    let (mut rpc, mut test_indexer, _, _, _, address) = setup_with_device().await;
+
+    let stranger = funded_keypair(&mut rpc).await;
+    let result = close_device(&mut rpc, &mut test_indexer, &stranger, address).await;
+    assert_rpc_error(result, 0, ErrorCode::DeviceOwnerMismatch.into()).unwrap();
+}
+
+#[tokio::test]
+async fn test_rotate_device_key() {
+    let (mut rpc, mut test_indexer, _, payer, marketplace_key, address) = setup_with_device().await;
+    let old_ek = device_key(&padded_device_id("device1"));
+    let new_ek = Keypair::new();
+
+    rotate_device_key(
+        &mut rpc,
+        &mut test_indexer,
+        &payer,
+        marketplace_key,
+        address,
+        Some(&old_ek),
+        None,
+        &new_ek,
+    )
+    .await
+    .unwrap();
+    let device = current_device(&test_indexer, address);
+    assert_eq!(
+        device.ek_pubkey_hash,
+        hash(new_ek.pubkey().as_ref()).to_bytes()
+    );
+    assert_eq!(device.key_rotations, 1);
+
+    // The retired key can no longer sign a rotation
+    let result = rotate_device_key(
+        &mut rpc,
+        &mut test_indexer,
+        &payer,
+        marketplace_key,
+        address,
+        Some(&old_ek),
+        None,
+        &Keypair::new(),
+    )
+    .await;
+    assert_rpc_error(result, 1, ErrorCode::DeviceKeyMismatch.into()).unwrap();
+}
+
+#[tokio::test]
+async fn test_rotate_device_key_signature_required() {
+    let (mut rpc, mut test_indexer, _, payer, marketplace_key, address) = setup_with_device().await;
+
+    let result = rotate_device_key(
+        &mut rpc,
+        &mut test_indexer,
+        &payer,
+        marketplace_key,
+        address,
+        None,
+        None,
+        &Keypair::new(),
+    )
+    .await;
+    assert_rpc_error(result, 0, ErrorCode::MissingDeviceSignature.into()).unwrap();
+}
+
+#[tokio::test]
+async fn test_rotate_device_key_admin_recovery() {
+    let (mut rpc, mut test_indexer, env, payer) = setup().await;
+    let marketplace_key = initialize_marketplace(&mut rpc, &payer, "TestMarket", 500)
+        .await
+        .unwrap();
+    let seller = funded_keypair(&mut rpc).await;
+    let address = register_device(
+        &mut rpc,
+        &mut test_indexer,
+        &env,
+        &seller,
+        marketplace_key,
+        padded_device_id("device1"),
+    )
+    .await
+    .unwrap();
+
+    // A lost device key is replaced with the admin's approval instead
+    let new_ek = Keypair::new();
+    rotate_device_key(
+        &mut rpc,
+        &mut test_indexer,
+        &seller,
+        marketplace_key,
+        address,
+        None,
+        Some(&payer),
+        &new_ek,
+    )
+    .await
+    .unwrap();
+    let device = current_device(&test_indexer, address);
+    assert_eq!(
+        device.ek_pubkey_hash,
+        hash(new_ek.pubkey().as_ref()).to_bytes()
+    );
+
+    let stranger = funded_keypair(&mut rpc).await;
+    let result = rotate_device_key(
+        &mut rpc,
+        &mut test_indexer,
+        &seller,
+        marketplace_key,
+        address,
+        None,
+        Some(&stranger),
+        &Keypair::new(),
+    )
+    .await;
+    assert_rpc_error(result, 0, ErrorCode::AdminMismatch.into()).unwrap();
+}
+
+#[tokio::test]
+async fn test_create_and_cancel_listing() {
+    let (mut rpc, mut test_indexer, _, payer, marketplace_key, address) = setup_with_device().await;
+
+    let listing_key = create_listing(
+        &mut rpc,
+        &mut test_indexer,
+        &payer,
+        marketplace_key,
+        address,
+        "listing1",
+        "device1",
+    )
+    .await
+    .unwrap();
+    let listing: ListingState = get_anchor_account(&mut rpc, listing_key).await;
+    assert_eq!(listing.seller, payer.pubkey());
+    assert_eq!(listing.device, Pubkey::new_from_array(address));
+    assert_eq!(listing.remaining_units, 10);
+    assert_eq!(listing.status, 0);
+    assert_eq!(current_device(&test_indexer, address).active_listings, 1);
+
+    cancel_listing(&mut rpc, &mut test_indexer, &payer, address, "listing1")
+        .await
+        .unwrap();
+    let listing: ListingState = get_anchor_account(&mut rpc, listing_key).await;
+    assert_eq!(listing.status, 2);
+    assert_eq!(current_device(&test_indexer, address).active_listings, 0);
+
+    let result = cancel_listing(&mut rpc, &mut test_indexer, &payer, address, "listing1").await;
+    assert_rpc_error(result, 0, ErrorCode::ListingNotActive.into()).unwrap();
+}
+
+#[tokio::test]
+async fn test_create_listing_device_id_mismatch() {
+    let (mut rpc, mut test_indexer, _, payer, marketplace_key, address) = setup_with_device().await;
+
+    let result = create_listing(
+        &mut rpc,
+        &mut test_indexer,
+        &payer,
+        marketplace_key,
+        address,
+        "listing1",
+        "device2",
+    )
+    .await;
+    assert_rpc_error(result, 0, ErrorCode::DeviceIdMismatch.into()).unwrap();
+}
+
+#[tokio::test]
+async fn test_create_listing_not_device_owner() {
+    let (mut rpc, mut test_indexer, _, _, marketplace_key, address) = setup_with_device().await;
+
+    let stranger = funded_keypair(&mut rpc).await;
+    let result = create_listing(
+        &mut rpc,
+        &mut test_indexer,
+        &stranger,
+        marketplace_key,
+        address,
+        "listing1",
+        "device1",
+    )
+    .await;
+    assert_rpc_error(result, 0, ErrorCode::NotOwnerOrOperator.into()).unwrap();
+}
+
+#[tokio::test]
+async fn test_cancel_listing_unauthorized() {
+    let (mut rpc, mut test_indexer, _, payer, marketplace_key, address) = setup_with_device().await;
+    create_listing(
+        &mut rpc,
+        &mut test_indexer,
+        &payer,
+        marketplace_key,
+        address,
+        "listing1",
+        "device1",
+    )
+    .await
+    .unwrap();
+
+    let stranger = funded_keypair(&mut rpc).await;
+    let result = cancel_listing(&mut rpc, &mut test_indexer, &stranger, address, "listing1").await;
+    assert_rpc_error(result, 0, ErrorCode::CancelUnauthorized.into()).unwrap();
+}
+
+#[tokio::test]
+async fn test_device_operator_lists_for_owner() {
+    let (mut rpc, mut test_indexer, _, payer, marketplace_key, address) = setup_with_device().await;
+
+    let operator = funded_keypair(&mut rpc).await;
+    set_device_operator(
+        &mut rpc,
+        &mut test_indexer,
+        &payer,
+        address,
+        Some((operator.pubkey(), 500_000)),
+    )
+    .await
+    .unwrap();
+    let device = current_device(&test_indexer, address);
+    assert_eq!(device.operator.key, operator.pubkey());
+    assert_eq!(device.operator.price_floor, 500_000);
+
+    let listing_key = create_listing_at_price(
+        &mut rpc,
+        &mut test_indexer,
+        &operator,
+        marketplace_key,
+        address,
+        "listing1",
+        "device1",
+        500_000,
+        None,
+    )
+    .await
+    .unwrap();
+    let listing: ListingState = get_anchor_account(&mut rpc, listing_key).await;
+    assert_eq!(listing.seller, payer.pubkey());
+    cancel_listing(&mut rpc, &mut test_indexer, &operator, address, "listing1")
+        .await
+        .unwrap();
+
+    set_device_operator(&mut rpc, &mut test_indexer, &payer, address, None)
+        .await
+        .unwrap();
+    assert_eq!(
+        current_device(&test_indexer, address).operator.key,
+        Pubkey::default()
+    );
+    let result = create_listing(
+        &mut rpc,
+        &mut test_indexer,
+        &operator,
+        marketplace_key,
+        address,
+        "listing2",
+        "device1",
+    )
+    .await;
+    assert_rpc_error(result, 0, ErrorCode::NotOwnerOrOperator.into()).unwrap();
+}
+
+#[tokio::test]
+async fn test_device_operator_price_below_floor() {
+    let (mut rpc, mut test_indexer, _, payer, marketplace_key, address) = setup_with_device().await;
+
+    let operator = funded_keypair(&mut rpc).await;
+    set_device_operator(
+        &mut rpc,
+        &mut test_indexer,
+        &payer,
+        address,
+        Some((operator.pubkey(), 2_000_000)),
+    )
+    .await
+    .unwrap();
+    let result = create_listing(
+        &mut rpc,
+        &mut test_indexer,
+        &operator,
+        marketplace_key,
+        address,
+        "listing1",
+        "device1",
+    )
+    .await;
+    assert_rpc_error(result, 0, ErrorCode::PriceBelowOperatorFloor.into()).unwrap();
+}
+
+#[tokio::test]
+async fn test_set_device_operator_invalid() {
+    let (mut rpc, mut test_indexer, _, payer, _, address) = setup_with_device().await;
+
+    let result = set_device_operator(
+        &mut rpc,
+        &mut test_indexer,
+        &payer,
+        address,
+        Some((payer.pubkey(), 0)),
+    )
+    .await;
+    assert_rpc_error(result, 0, ErrorCode::InvalidOperator.into()).unwrap();
+    let stranger = funded_keypair(&mut rpc).await;
+    let result = set_device_operator(
+        &mut rpc,
+        &mut test_indexer,
+        &stranger,
+        address,
+        Some((stranger.pubkey(), 0)),
+    )
+    .await;
+    assert_rpc_error(result, 0, ErrorCode::DeviceOwnerMismatch.into()).unwrap();
+}
+
+#[tokio::test]
+async fn test_device_heartbeat() {
+    let (mut rpc, mut test_indexer, _, payer, _, address) = setup_with_device().await;
+    let ek = device_key(&padded_device_id("device1"));
+
+    sync_device_heartbeat(&mut rpc, &mut test_indexer, &payer, address)
+        .await
+        .unwrap();
+    device_heartbeat(&mut rpc, &payer, &ek, address)
+        .await
+        .unwrap();
+    let heartbeat: DeviceHeartbeat = get_anchor_account(&mut rpc, heartbeat_pda(&address)).await;
+    assert_eq!(heartbeat.device, Pubkey::new_from_array(address));
+    assert_eq!(heartbeat.heartbeat_count, 1);
+    assert!(heartbeat.last_seen_slot > 0);
+
+    // The operator may post heartbeats once it is set
+    let operator = funded_keypair(&mut rpc).await;
+    set_device_operator(
+        &mut rpc,
+        &mut test_indexer,
+        &payer,
+        address,
+        Some((operator.pubkey(), 0)),
+    )
+    .await
+    .unwrap();
+    device_heartbeat(&mut rpc, &payer, &operator, address)
+        .await
+        .unwrap();
+    let heartbeat: DeviceHeartbeat = get_anchor_account(&mut rpc, heartbeat_pda(&address)).await;
+    assert_eq!(heartbeat.heartbeat_count, 2);
+}
+
+#[tokio::test]
+async fn test_device_heartbeat_unauthorized() {
+    let (mut rpc, mut test_indexer, _, payer, marketplace_key, address) = setup_with_device().await;
+    let old_ek = device_key(&padded_device_id("device1"));
+
+    sync_device_heartbeat(&mut rpc, &mut test_indexer, &payer, address)
+        .await
+        .unwrap();
+    let result = device_heartbeat(&mut rpc, &payer, &Keypair::new(), address).await;
+    assert_rpc_error(result, 0, ErrorCode::HeartbeatUnauthorized.into()).unwrap();
+
+    // A rotation retires the cached key
+    rotate_device_key(
+        &mut rpc,
+        &mut test_indexer,
+        &payer,
+        marketplace_key,
+        address,
+        Some(&old_ek),
+        None,
+        &Keypair::new(),
+    )
+    .await
+    .unwrap();
+    let result = device_heartbeat(&mut rpc, &payer, &old_ek, address).await;
+    assert_rpc_error(result, 0, ErrorCode::HeartbeatUnauthorized.into()).unwrap();
+
+    let stranger = funded_keypair(&mut rpc).await;
+    let result = sync_device_heartbeat(&mut rpc, &mut test_indexer, &stranger, address).await;
+    assert_rpc_error(result, 0, ErrorCode::DeviceOwnerMismatch.into()).unwrap();
+}
+
+#[tokio::test]
+async fn test_commit_data_batch_backs_listing() {
+    let (mut rpc, mut test_indexer, _, payer, marketplace_key, address) = setup_with_device().await;
+    let ek = device_key(&padded_device_id("device1"));
+    rpc.airdrop_lamports(&ek.pubkey(), 1_000_000_000)
+        .await
+        .unwrap();
+    sync_device_heartbeat(&mut rpc, &mut test_indexer, &payer, address)
+        .await
+        .unwrap();
+
+    let data_batch = commit_data_batch(&mut rpc, &ek, address, [7u8; 32], 100)
+        .await
+        .unwrap();
+    let batch: DataBatch = get_anchor_account(&mut rpc, data_batch).await;
+    assert_eq!(batch.device, Pubkey::new_from_array(address));
+    assert_eq!(batch.reading_count, 100);
+    assert_eq!(batch.submitted_by, ek.pubkey());
+
+    let listing_key = create_listing_at_price(
+        &mut rpc,
+        &mut test_indexer,
+        &payer,
+        marketplace_key,
+        address,
+        "listing1",
+        "device1",
+        1_000_000,
+        Some(data_batch),
+    )
+    .await
+    .unwrap();
+    let listing: ListingState = get_anchor_account(&mut rpc, listing_key).await;
+    assert_eq!(listing.data_batch, Some(data_batch));
+}
+
+#[tokio::test]
+async fn test_commit_data_batch_invalid() {
+    let (mut rpc, mut test_indexer, _, payer, _, address) = setup_with_device().await;
+    let ek = device_key(&padded_device_id("device1"));
+    rpc.airdrop_lamports(&ek.pubkey(), 1_000_000_000)
+        .await
+        .unwrap();
+    sync_device_heartbeat(&mut rpc, &mut test_indexer, &payer, address)
+        .await
+        .unwrap();
+
+    let result = commit_data_batch(&mut rpc, &ek, address, [0u8; 32], 100).await;
+    assert_rpc_error(result, 0, ErrorCode::DataBatchRootEmpty.into()).unwrap();
+    let result = commit_data_batch(&mut rpc, &ek, address, [7u8; 32], 0).await;
+    assert_rpc_error(result, 0, ErrorCode::InvalidReadingCount.into()).unwrap();
+    let stranger = funded_keypair(&mut rpc).await;
+    let result = commit_data_batch(&mut rpc, &stranger, address, [7u8; 32], 100).await;
+    assert_rpc_error(result, 0, ErrorCode::HeartbeatUnauthorized.into()).unwrap();
+}
+
+#[tokio::test]
+async fn test_banned_device_cannot_list() {
+    let (mut rpc, mut test_indexer, _, payer, marketplace_key, address) = setup_with_device().await;
+
+    set_device_ban(&mut rpc, &payer, marketplace_key, address, Some(3))
+        .await
+        .unwrap();
+    let ban: DeviceBan =
+        get_anchor_account(&mut rpc, device_ban_pda(&marketplace_key, &address)).await;
+    assert_eq!(ban.device, Pubkey::new_from_array(address));
+    assert_eq!(ban.reason, 3);
+    let result = create_listing(
+        &mut rpc,
+        &mut test_indexer,
+        &payer,
+        marketplace_key,
+        address,
+        "listing1",
+        "device1",
+    )
+    .await;
+    assert_rpc_error(result, 0, ErrorCode::DeviceBanned.into()).unwrap();
+
+    set_device_ban(&mut rpc, &payer, marketplace_key, address, None)
+        .await
+        .unwrap();
+    create_listing(
+        &mut rpc,
+        &mut test_indexer,
+        &payer,
+        marketplace_key,
+        address,
+        "listing1",
+        "device1",
+    )
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn test_ban_device_not_admin() {
+    let (mut rpc, _, _, _, marketplace_key, address) = setup_with_device().await;
+
+    let stranger = funded_keypair(&mut rpc).await;
+    let result = set_device_ban(&mut rpc, &stranger, marketplace_key, address, Some(1)).await;
+    assert_rpc_error(result, 0, ErrorCode::AdminMismatch.into()).unwrap();
+}
+
+#[tokio::test]
+async fn test_purchase_listing() {
+    let (mut rpc, mut test_indexer, _, payer, marketplace_key, address) = setup_with_device().await;
+    let buyer = setup_purchase(
+        &mut rpc,
+        &mut test_indexer,
+        &payer,
+        marketplace_key,
+        address,
+    )
+    .await;
+
+    let purchase_record = purchase_listing(
+        &mut rpc,
+        &mut test_indexer,
+        &buyer,
+        marketplace_key,
+        address,
+        "listing1",
+        2,
+    )
+    .await
+    .unwrap();
+    // 2 units at 1_000_000 with a 5% marketplace fee
+    let marketplace: Marketplace = get_anchor_account(&mut rpc, marketplace_key).await;
+    let record: PurchaseRecord = get_anchor_account(&mut rpc, purchase_record).await;
+    assert_eq!(record.price_paid, 2_000_000);
+    assert_eq!(record.fee, 100_000);
+    assert_eq!(record.seller_received, 1_900_000);
+    assert_eq!(token_balance(&mut rpc, marketplace.treasury).await, 100_000);
+    let seller_ata = get_associated_token_address(&payer.pubkey(), &marketplace.token_mint);
+    assert_eq!(token_balance(&mut rpc, seller_ata).await, 1_900_000);
+    let listing: ListingState =
+        get_anchor_account(&mut rpc, listing_pda(&address, "listing1")).await;
+    assert_eq!(listing.remaining_units, 8);
+    assert_eq!(listing.purchase_count, 1);
+
+    // Selling out frees the device's listing slot
+    purchase_listing(
+        &mut rpc,
+        &mut test_indexer,
+        &buyer,
+        marketplace_key,
+        address,
+        "listing1",
+        8,
+    )
+    .await
+    .unwrap();
+    let listing: ListingState =
+        get_anchor_account(&mut rpc, listing_pda(&address, "listing1")).await;
+    assert_eq!(listing.status, 1);
+    assert_eq!(current_device(&test_indexer, address).active_listings, 0);
+}
+
+#[tokio::test]
+async fn test_purchase_listing_rejections() {
+    let (mut rpc, mut test_indexer, _, payer, marketplace_key, address) = setup_with_device().await;
+    let buyer = setup_purchase(
+        &mut rpc,
+        &mut test_indexer,
+        &payer,
+        marketplace_key,
+        address,
+    )
+    .await;
+
+    let result = purchase_listing(
+        &mut rpc,
+        &mut test_indexer,
+        &buyer,
+        marketplace_key,
+        address,
+        "listing1",
+        0,
+    )
+    .await;
+    assert_rpc_error(result, 0, ErrorCode::InvalidUnitsRequested.into()).unwrap();
+    let result = purchase_listing(
+        &mut rpc,
+        &mut test_indexer,
+        &buyer,
+        marketplace_key,
+        address,
+        "listing1",
+        11,
+    )
+    .await;
+    assert_rpc_error(result, 0, ErrorCode::InsufficientUnits.into()).unwrap();
+    fund_token_account(
+        &mut rpc,
+        &payer,
+        marketplace_key,
+        &payer.pubkey(),
+        10_000_000,
+    )
+    .await;
+    let result = purchase_listing(
+        &mut rpc,
+        &mut test_indexer,
+        &payer,
+        marketplace_key,
+        address,
+        "listing1",
+        1,
+    )
+    .await;
+    assert_rpc_error(result, 0, ErrorCode::CannotBuyOwnListing.into()).unwrap();
+
+    // A ban placed after listing blocks purchases too
+    set_device_ban(&mut rpc, &payer, marketplace_key, address, Some(1))
+        .await
+        .unwrap();
+    let result = purchase_listing(
+        &mut rpc,
+        &mut test_indexer,
+        &buyer,
+        marketplace_key,
+        address,
+        "listing1",
+        1,
+    )
+    .await;
+    assert_rpc_error(result, 0, ErrorCode::DeviceBanned.into()).unwrap();
+}
+
+#[tokio::test]
+async fn test_withdraw_treasury() {
+    let (mut rpc, mut test_indexer, _, payer, marketplace_key, address) = setup_with_device().await;
+    let buyer = setup_purchase(
+        &mut rpc,
+        &mut test_indexer,
+        &payer,
+        marketplace_key,
+        address,
+    )
+    .await;
+    purchase_listing(
+        &mut rpc,
+        &mut test_indexer,
+        &buyer,
+        marketplace_key,
+        address,
+        "listing1",
+        10,
+    )
+    .await
+    .unwrap();
+    let marketplace: Marketplace = get_anchor_account(&mut rpc, marketplace_key).await;
+    let treasury = marketplace.treasury;
+    assert_eq!(token_balance(&mut rpc, treasury).await, 500_000);
+
+    let destination =
+        fund_token_account(&mut rpc, &payer, marketplace_key, &buyer.pubkey(), 0).await;
+    withdraw_treasury(
+        &mut rpc,
+        &payer,
+        marketplace_key,
+        destination,
+        Some(200_000),
+    )
+    .await
+    .unwrap();
+    assert_eq!(token_balance(&mut rpc, treasury).await, 300_000);
+    assert_eq!(token_balance(&mut rpc, destination).await, 200_000);
+
+    // `None` sweeps the remaining balance
+    withdraw_treasury(&mut rpc, &payer, marketplace_key, destination, None)
+        .await
+        .unwrap();
+    assert_eq!(token_balance(&mut rpc, treasury).await, 0);
+    assert_eq!(token_balance(&mut rpc, destination).await, 500_000);
+
+    let result = withdraw_treasury(&mut rpc, &payer, marketplace_key, destination, None).await;
+    assert_rpc_error(result, 0, ErrorCode::InvalidWithdrawAmount.into()).unwrap();
+}
+
+#[tokio::test]
+async fn test_withdraw_treasury_rejections() {
+    let (mut rpc, mut test_indexer, _, payer, marketplace_key, address) = setup_with_device().await;
+    let buyer = setup_purchase(
+        &mut rpc,
+        &mut test_indexer,
+        &payer,
+        marketplace_key,
+        address,
+    )
+    .await;
+    purchase_listing(
+        &mut rpc,
+        &mut test_indexer,
+        &buyer,
+        marketplace_key,
+        address,
+        "listing1",
+        1,
+    )
+    .await
+    .unwrap();
+    let destination =
+        fund_token_account(&mut rpc, &payer, marketplace_key, &payer.pubkey(), 0).await;
+
+    let result = withdraw_treasury(&mut rpc, &payer, marketplace_key, destination, Some(0)).await;
+    assert_rpc_error(result, 0, ErrorCode::InvalidWithdrawAmount.into()).unwrap();
+    let result =
+        withdraw_treasury(&mut rpc, &payer, marketplace_key, destination, Some(50_001)).await;
+    assert_rpc_error(result, 0, ErrorCode::InsufficientFunds.into()).unwrap();
+    let result = withdraw_treasury(&mut rpc, &buyer, marketplace_key, destination, None).await;
+    assert_rpc_error(result, 0, ErrorCode::AdminMismatch.into()).unwrap();
+}
+
+#[tokio::test]
+async fn test_accepted_mints() {
+    let (mut rpc, mut test_indexer, _, payer, marketplace_key, address) = setup_with_device().await;
+    let mint = create_mint_helper(&mut rpc, &payer).await;
+    add_accepted_mint(&mut rpc, &payer, marketplace_key, mint)
+        .await
+        .unwrap();
+    let accepted_mint_key = accepted_mint_pda(&marketplace_key, &mint);
+    let accepted_mint: AcceptedMint = get_anchor_account(&mut rpc, accepted_mint_key).await;
+    assert_eq!(accepted_mint.mint, mint);
+    assert_eq!(
+        accepted_mint.treasury,
+        treasury_pda(&marketplace_key, &mint)
+    );
+
+    // Listings settle in the chosen mint, with fees kept in its own treasury
+    let listing_key = create_listing_with_settlement(
+        &mut rpc,
+        &mut test_indexer,
+        &payer,
+        marketplace_key,
+        address,
+        "listing1",
+        "device1",
+        1_000_000,
+        None,
+        SETTLEMENT_TOKEN,
+        Some(mint),
+    )
+    .await
+    .unwrap();
+    let listing: ListingState = get_anchor_account(&mut rpc, listing_key).await;
+    assert_eq!(listing.token_mint, mint);
+    let buyer = funded_keypair(&mut rpc).await;
+    let destination = fund_mint_account(&mut rpc, &payer, mint, &buyer.pubkey(), 10_000_000).await;
+    fund_mint_account(&mut rpc, &payer, mint, &payer.pubkey(), 0).await;
+    purchase_listing(
+        &mut rpc,
+        &mut test_indexer,
+        &buyer,
+        marketplace_key,
+        address,
+        "listing1",
+        2,
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        token_balance(&mut rpc, accepted_mint.treasury).await,
+        100_000
+    );
+    let marketplace: Marketplace = get_anchor_account(&mut rpc, marketplace_key).await;
+    assert_eq!(token_balance(&mut rpc, marketplace.treasury).await, 0);
 
-    match rpc.create_and_send_transaction_with_event(&[instruction], &payer.pubkey(), &[payer], Some(test_indexer)).await {
-        Ok(_) => Ok(address),
-        Err(e) => Err(e),
-    }
+    // Removing the mint stops sales in it but leaves its treasury withdrawable
+    remove_accepted_mint(&mut rpc, &payer, marketplace_key, mint)
+        .await
+        .unwrap();
+    assert!(rpc.get_account(accepted_mint_key).await.unwrap().is_none());
+    let result = purchase_listing(
+        &mut rpc,
+        &mut test_indexer,
+        &buyer,
+        marketplace_key,
+        address,
+        "listing1",
+        1,
+    )
+    .await;
+    assert_rpc_error(
+        result,
+        0,
+        anchor_lang::error::ErrorCode::AccountNotInitialized.into(),
+    )
+    .unwrap();
+    withdraw_mint_treasury(&mut rpc, &payer, marketplace_key, mint, destination, None)
+        .await
+        .unwrap();
+    assert_eq!(token_balance(&mut rpc, accepted_mint.treasury).await, 0);
+    assert_eq!(token_balance(&mut rpc, destination).await, 8_100_000);
 }
 
-// Tests for Marketplace Initialization
-
 #[tokio::test]
-async fn test_initialize_marketplace_success() {
-    let (mut rpc, _) = setup_test_programs_with_accounts_v2(Some(vec![(
-        String::from("chainsensor"),
-        PROGRAM_ID,
-    )])).await;
-    let payer = rpc.get_payer().insecure_clone();
+async fn test_accepted_mint_rejections() {
+    let (mut rpc, mut test_indexer, _, payer, marketplace_key, address) = setup_with_device().await;
+    let mint = create_mint_helper(&mut rpc, &payer).await;
+    let other = funded_keypair(&mut rpc).await;
 
-    let marketplace_key = initialize_marketplace(&mut rpc, &payer, "TestMarket", 500).await.unwrap();
+    let result = add_accepted_mint(&mut rpc, &other, marketplace_key, mint).await;
+    assert_rpc_error(result, 0, ErrorCode::AdminMismatch.into()).unwrap();
 
-    let marketplace_data = rpc.get_account_data(&marketplace_key).await.unwrap();
-    let marketplace: Marketplace = AccountDeserialize::try_deserialize(&mut &marketplace_data[..]).unwrap();
+    // A mint the marketplace doesn't accept can't be listed in
+    let result = create_listing_with_settlement(
+        &mut rpc,
+        &mut test_indexer,
+        &payer,
+        marketplace_key,
+        address,
+        "listing1",
+        "device1",
+        1_000_000,
+        None,
+        SETTLEMENT_TOKEN,
+        Some(mint),
+    )
+    .await;
+    assert_rpc_error(
+        result,
+        0,
+        anchor_lang::error::ErrorCode::AccountNotInitialized.into(),
+    )
+    .unwrap();
 
-    assert_eq!(marketplace.admin, payer.pubkey());
-    assert_eq!(marketplace.name, "TestMarket");
-    assert_eq!(marketplace.seller_fee, 500);
-    assert!(marketplace.is_active);
+    add_accepted_mint(&mut rpc, &payer, marketplace_key, mint)
+        .await
+        .unwrap();
+    let result = remove_accepted_mint(&mut rpc, &other, marketplace_key, mint).await;
+    assert_rpc_error(result, 0, ErrorCode::AdminMismatch.into()).unwrap();
 }
 
 #[tokio::test]
-async fn test_initialize_marketplace_name_too_long() {
-    let (mut rpc, _) = setup_test_programs_with_accounts_v2(Some(vec![(
-        String::from("chainsensor"),
-        PROGRAM_ID,
-    )])).await;
-    let payer = rpc.get_payer().insecure_clone();
+async fn test_fee_schedule_and_seller_tiers() {
+    let (mut rpc, mut test_indexer, _, payer, marketplace_key, address) = setup_with_device().await;
+    let data_type = device_metadata().data_type;
+    set_fee_schedule(
+        &mut rpc,
+        &payer,
+        marketplace_key,
+        data_type,
+        Some((300, vec![200, 100])),
+    )
+    .await
+    .unwrap();
+    let schedule: FeeSchedule =
+        get_anchor_account(&mut rpc, fee_schedule_pda(&marketplace_key, &data_type)).await;
+    assert_eq!(schedule.tier_fees, vec![200, 100]);
+    let buyer = setup_purchase(
+        &mut rpc,
+        &mut test_indexer,
+        &payer,
+        marketplace_key,
+        address,
+    )
+    .await;
+    let listing: ListingState =
+        get_anchor_account(&mut rpc, listing_pda(&address, "listing1")).await;
+    assert_eq!(listing.data_type, data_type);
 
-    let name = "a".repeat(33); // Exceeds 32 characters
-    let result = initialize_marketplace(&mut rpc, &payer, &name, 500).await;
+    // Tier 0 sellers pay the schedule's base fee
+    let record = purchase_listing(
+        &mut rpc,
+        &mut test_indexer,
+        &buyer,
+        marketplace_key,
+        address,
+        "listing1",
+        2,
+    )
+    .await
+    .unwrap();
+    let record: PurchaseRecord = get_anchor_account(&mut rpc, record).await;
+    assert_eq!((record.fee_bps, record.fee), (300, 60_000));
 
-    assert!(result.is_err());
-    if let TransactionError::InstructionError(_, InstructionError::Custom(code)) = result.err().unwrap() {
-        assert_eq!(code, ErrorCode::NameTooLong as u32); // Error code 0
-    } else {
-        panic!("Expected NameTooLong error");
-    }
+    // Tier 2 sellers pay the second tier fee
+    set_seller_tier(&mut rpc, &payer, marketplace_key, payer.pubkey(), 2)
+        .await
+        .unwrap();
+    let record = purchase_listing(
+        &mut rpc,
+        &mut test_indexer,
+        &buyer,
+        marketplace_key,
+        address,
+        "listing1",
+        2,
+    )
+    .await
+    .unwrap();
+    let record: PurchaseRecord = get_anchor_account(&mut rpc, record).await;
+    assert_eq!((record.fee_bps, record.fee), (100, 20_000));
+
+    // Without a schedule the marketplace's flat 5% applies again
+    set_fee_schedule(&mut rpc, &payer, marketplace_key, data_type, None)
+        .await
+        .unwrap();
+    let record = purchase_listing(
+        &mut rpc,
+        &mut test_indexer,
+        &buyer,
+        marketplace_key,
+        address,
+        "listing1",
+        2,
+    )
+    .await
+    .unwrap();
+    let record: PurchaseRecord = get_anchor_account(&mut rpc, record).await;
+    assert_eq!((record.fee_bps, record.fee), (500, 100_000));
 }
 
 #[tokio::test]
-async fn test_initialize_marketplace_name_empty() {
-    let (mut rpc, _) = setup_test_programs_with_accounts_v2(Some(vec![(
-        String::from("chainsensor"),
-        PROGRAM_ID,
-    )])).await;
-    let payer = rpc.get_payer().insecure_clone();
-
-    let result = initialize_marketplace(&mut rpc, &payer, "", 500).await;
-
-    assert!(result.is_err());
-    if let TransactionError::InstructionError(_, InstructionError::Custom(code)) = result.err().unwrap() {
-        assert_eq!(code, ErrorCode::NameEmpty as u32); // Error code 2
-    } else {
-        panic!("Expected NameEmpty error");
+async fn test_fee_schedule_rejections() {
+    let (mut rpc, _, _, payer) = setup().await;
+    let marketplace_key = initialize_marketplace(&mut rpc, &payer, "TestMarket", 500)
+        .await
+        .unwrap();
+    let data_type = device_metadata().data_type;
+    let cases = [
+        ([0u8; 32], (300, vec![]), ErrorCode::DataTypeEmpty),
+        (data_type, (10_001, vec![]), ErrorCode::InvalidFee),
+        (data_type, (300, vec![200, 10_001]), ErrorCode::InvalidFee),
+        (data_type, (300, vec![100; 5]), ErrorCode::InvalidSellerTier),
+    ];
+    for (data_type, fees, error) in cases {
+        let result =
+            set_fee_schedule(&mut rpc, &payer, marketplace_key, data_type, Some(fees)).await;
+        assert_rpc_error(result, 0, error.into()).unwrap();
     }
+    let result = set_seller_tier(&mut rpc, &payer, marketplace_key, payer.pubkey(), 5).await;
+    assert_rpc_error(result, 0, ErrorCode::InvalidSellerTier.into()).unwrap();
+
+    let other = funded_keypair(&mut rpc).await;
+    let result = set_fee_schedule(
+        &mut rpc,
+        &other,
+        marketplace_key,
+        data_type,
+        Some((300, vec![])),
+    )
+    .await;
+    assert_rpc_error(result, 0, ErrorCode::AdminMismatch.into()).unwrap();
+    let result = set_seller_tier(&mut rpc, &other, marketplace_key, other.pubkey(), 1).await;
+    assert_rpc_error(result, 0, ErrorCode::AdminMismatch.into()).unwrap();
 }
 
 #[tokio::test]
-async fn test_initialize_marketplace_invalid_name_chars() {
-    let (mut rpc, _) = setup_test_programs_with_accounts_v2(Some(vec![(
-        String::from("chainsensor"),
-        PROGRAM_ID,
-    )])).await;
-    let payer = rpc.get_payer().insecure_clone();
-
-    let result = initialize_marketplace(&mut rpc, &payer, "Invalid@Name", 500).await;
+async fn test_token_2022_payment_mint() {
+    let (mut rpc, mut test_indexer, _, payer, marketplace_key, address) = setup_with_device().await;
+    let mint = create_token_2022_mint(&mut rpc, &payer, ExtensionType::TransferFeeConfig).await;
+    add_accepted_mint(&mut rpc, &payer, marketplace_key, mint)
+        .await
+        .unwrap();
+    create_listing_with_settlement(
+        &mut rpc,
+        &mut test_indexer,
+        &payer,
+        marketplace_key,
+        address,
+        "listing1",
+        "device1",
+        1_000_000,
+        None,
+        SETTLEMENT_TOKEN,
+        Some(mint),
+    )
+    .await
+    .unwrap();
+    let buyer = funded_keypair(&mut rpc).await;
+    fund_mint_account(&mut rpc, &payer, mint, &buyer.pubkey(), 10_000_000).await;
+    let seller_ata = fund_mint_account(&mut rpc, &payer, mint, &payer.pubkey(), 0).await;
 
-    assert!(result.is_err());
-    if let TransactionError::InstructionError(_, InstructionError::Custom(code)) = result.err().unwrap() {
-        assert_eq!(code, ErrorCode::InvalidNameChars as u32); // Error code 3
-    } else {
-        panic!("Expected InvalidNameChars error");
-    }
+    // The record keeps what arrived after the mint's 1% transfer fee
+    let purchase_record = purchase_listing(
+        &mut rpc,
+        &mut test_indexer,
+        &buyer,
+        marketplace_key,
+        address,
+        "listing1",
+        2,
+    )
+    .await
+    .unwrap();
+    let record: PurchaseRecord = get_anchor_account(&mut rpc, purchase_record).await;
+    assert_eq!(record.price_paid, 2_000_000);
+    assert_eq!(record.fee, 100_000);
+    assert_eq!(record.treasury_received, 99_000);
+    assert_eq!(record.seller_received, 1_895_000);
+    assert_eq!(
+        token_balance(&mut rpc, treasury_pda(&marketplace_key, &mint)).await,
+        99_000
+    );
+    assert_eq!(token_balance(&mut rpc, seller_ata).await, 1_895_000);
 }
 
 #[tokio::test]
-async fn test_initialize_marketplace_invalid_fee() {
-    let (mut rpc, _) = setup_test_programs_with_accounts_v2(Some(vec![(
-        String::from("chainsensor"),
-        PROGRAM_ID,
-    )])).await;
-    let payer = rpc.get_payer().insecure_clone();
+async fn test_unsupported_mint_extension() {
+    let (mut rpc, _, _, payer) = setup().await;
+    let marketplace_key = initialize_marketplace(&mut rpc, &payer, "TestMarket", 500)
+        .await
+        .unwrap();
+    let mint = create_token_2022_mint(&mut rpc, &payer, ExtensionType::TransferHook).await;
 
-    let result = initialize_marketplace(&mut rpc, &payer, "TestMarket", 10001).await; // Fee > 10000 basis points
-
-    assert!(result.is_err());
-    if let TransactionError::InstructionError(_, InstructionError::Custom(code)) = result.err().unwrap() {
-        assert_eq!(code, ErrorCode::InvalidFee as u32); // Error code 1
-    } else {
-        panic!("Expected InvalidFee error");
-    }
+    let result = add_accepted_mint(&mut rpc, &payer, marketplace_key, mint).await;
+    assert_rpc_error(result, 0, ErrorCode::UnsupportedMintExtension.into()).unwrap();
+    assert!(rpc
+        .get_account(accepted_mint_pda(&marketplace_key, &mint))
+        .await
+        .unwrap()
+        .is_none());
 }
 
-// Tests for Device Registration
-
 #[tokio::test]
-async fn test_register_device_success() {
-    let (mut rpc, env) = setup_test_programs_with_accounts_v2(Some(vec![(
-        String::from("chainsensor"),
-        PROGRAM_ID,
-    )])).await;
-    let payer = rpc.get_payer().insecure_clone();
-    let mut test_indexer = TestIndexer::new(
-        env.state_merkle_tree_pubkey,
-        env.state_queue_pubkey,
-        env.address_merkle_tree_pubkey,
-        env.address_queue_pubkey,
+async fn test_sol_settled_listing() {
+    let (mut rpc, mut test_indexer, _, payer, marketplace_key, address) = setup_with_device().await;
+    let listing_key = create_listing_with_settlement(
+        &mut rpc,
+        &mut test_indexer,
+        &payer,
+        marketplace_key,
+        address,
+        "listing1",
+        "device1",
+        1_000_000,
+        None,
+        SETTLEMENT_SOL,
         None,
-        rpc.clone(),
     )
-    .await;
-
-    let marketplace_key = initialize_marketplace(&mut rpc, &payer, "TestMarket", 500).await.unwrap();
+    .await
+    .unwrap();
+    let listing: ListingState = get_anchor_account(&mut rpc, listing_key).await;
+    assert_eq!(listing.settlement_mode, SETTLEMENT_SOL);
+    assert_eq!(listing.token_mint, Pubkey::default());
 
-    let device_id = "device1";
-    let address = register_device(
+    // 4 units at 1_000_000 lamports with a 5% fee
+    let buyer = funded_keypair(&mut rpc).await;
+    let sol_treasury = sol_treasury_pda(&marketplace_key);
+    let seller_balance = rpc.get_balance(&payer.pubkey()).await.unwrap();
+    let treasury_balance = rpc.get_balance(&sol_treasury).await.unwrap();
+    purchase_listing(
         &mut rpc,
         &mut test_indexer,
-        &env,
-        &payer,
+        &buyer,
         marketplace_key,
-        device_id,
-        100, // price_per_unit
-        1000, // total_data_units
-    ).await.unwrap();
+        address,
+        "listing1",
+        4,
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        rpc.get_balance(&payer.pubkey()).await.unwrap(),
+        seller_balance + 3_800_000
+    );
+    assert_eq!(
+        rpc.get_balance(&sol_treasury).await.unwrap(),
+        treasury_balance + 200_000
+    );
 
-    let compressed_accounts = test_indexer.get_compressed_accounts_by_owner(&PROGRAM_ID);
-    assert_eq!(compressed_accounts.len(), 1);
+    // Withdrawals leave the treasury rent-exempt
+    let destination = Keypair::new().pubkey();
+    withdraw_sol_treasury(&mut rpc, &payer, marketplace_key, destination, None)
+        .await
+        .unwrap();
+    assert_eq!(rpc.get_balance(&destination).await.unwrap(), 200_000);
+    assert_eq!(
+        rpc.get_balance(&sol_treasury).await.unwrap(),
+        treasury_balance
+    );
+    let result = withdraw_sol_treasury(&mut rpc, &payer, marketplace_key, destination, None).await;
+    assert_rpc_error(result, 0, ErrorCode::InvalidWithdrawAmount.into()).unwrap();
+}
 
-    let compressed_account = &compressed_accounts[0];
-    assert_eq!(compressed_account.compressed_account.address, Some(address));
+#[tokio::test]
+async fn test_sol_settlement_rejections() {
+    let (mut rpc, mut test_indexer, _, payer, marketplace_key, address) = setup_with_device().await;
+    let marketplace: Marketplace = get_anchor_account(&mut rpc, marketplace_key).await;
+    let cases = [
+        (SETTLEMENT_TOKEN, None, ErrorCode::MissingTokenAccounts),
+        (
+            SETTLEMENT_SOL,
+            Some(marketplace.token_mint),
+            ErrorCode::UnexpectedAcceptedMint,
+        ),
+        (7, None, ErrorCode::InvalidSettlementMode),
+    ];
+    for (settlement_mode, payment_mint, error) in cases {
+        let result = create_listing_with_settlement(
+            &mut rpc,
+            &mut test_indexer,
+            &payer,
+            marketplace_key,
+            address,
+            "listing1",
+            "device1",
+            1_000_000,
+            None,
+            settlement_mode,
+            payment_mint,
+        )
+        .await;
+        assert_rpc_error(result, 0, error.into()).unwrap();
+    }
 
-    let device_data = &compressed_account.compressed_account.data.as_ref().unwrap().data;
-    let device: DeviceRegistry = DeviceRegistry::deserialize(&mut &device_data[..]).unwrap();
+    // A payout too small to fund an emptied seller account is rejected
+    create_listing_with_settlement(
+        &mut rpc,
+        &mut test_indexer,
+        &payer,
+        marketplace_key,
+        address,
+        "listing1",
+        "device1",
+        1_000,
+        None,
+        SETTLEMENT_SOL,
+        None,
+    )
+    .await
+    .unwrap();
+    let buyer = funded_keypair(&mut rpc).await;
+    let balance = rpc.get_balance(&payer.pubkey()).await.unwrap();
+    let drain = system_instruction::transfer(&payer.pubkey(), &buyer.pubkey(), balance - 5_000);
+    rpc.create_and_send_transaction(&[drain], &payer.pubkey(), &[&payer])
+        .await
+        .unwrap();
+    let result = purchase_listing(
+        &mut rpc,
+        &mut test_indexer,
+        &buyer,
+        marketplace_key,
+        address,
+        "listing1",
+        1,
+    )
+    .await;
+    assert_rpc_error(result, 0, ErrorCode::SellerNotRentExempt.into()).unwrap();
 
-    assert_eq!(device.owner, payer.pubkey());
-    assert_eq!(device.marketplace, marketplace_key);
-    assert_eq!(device.device_id, device_id);
-    assert_eq!(device.price_per_unit, 100);
-    assert_eq!(device.total_data_units, 1000);
-    assert!(device.is_active);
-    assert_eq!(device.metadata.sensor_type, "Temperature");
-    assert_eq!(device.metadata.location, "Room1");
-    assert_eq!(device.metadata.description, "Test sensor");
+    let result =
+        withdraw_sol_treasury(&mut rpc, &buyer, marketplace_key, buyer.pubkey(), None).await;
+    assert_rpc_error(result, 0, ErrorCode::AdminMismatch.into()).unwrap();
 }
 
 #[tokio::test]
-async fn test_register_device_empty_device_id() {
-    let (mut rpc, env) = setup_test_programs_with_accounts_v2(Some(vec![(
-        String::from("chainsensor"),
-        PROGRAM_ID,
-    )])).await;
-    let payer = rpc.get_payer().insecure_clone();
-    let mut test_indexer = TestIndexer::new(
-        env.state_merkle_tree_pubkey,
-        env.state_queue_pubkey,
-        env.address_merkle_tree_pubkey,
-        env.address_queue_pubkey,
-        None,
-        rpc.clone(),
+async fn test_guardian_pause_and_admin_unpause() {
+    let (mut rpc, mut test_indexer, env, payer, marketplace_key, address) =
+        setup_with_device().await;
+    let guardian = funded_keypair(&mut rpc).await;
+    set_guardian(&mut rpc, &payer, marketplace_key, Some(guardian.pubkey()))
+        .await
+        .unwrap();
+
+    // The guardian can pause but only the admin can unpause
+    set_paused(
+        &mut rpc,
+        &guardian,
+        marketplace_key,
+        PAUSE_LISTING | PAUSE_PURCHASES,
+        true,
+    )
+    .await
+    .unwrap();
+    let marketplace: Marketplace = get_anchor_account(&mut rpc, marketplace_key).await;
+    assert_eq!(marketplace.pause_flags, PAUSE_LISTING | PAUSE_PURCHASES);
+    let result = create_listing(
+        &mut rpc,
+        &mut test_indexer,
+        &payer,
+        marketplace_key,
+        address,
+        "listing1",
+        "device1",
     )
     .await;
+    assert_rpc_error(result, 0, ErrorCode::ListingPaused.into()).unwrap();
+    let result = set_paused(&mut rpc, &guardian, marketplace_key, PAUSE_LISTING, false).await;
+    assert_rpc_error(result, 0, ErrorCode::AdminMismatch.into()).unwrap();
+    set_paused(&mut rpc, &payer, marketplace_key, PAUSE_LISTING, false)
+        .await
+        .unwrap();
 
-    let marketplace_key = initialize_marketplace(&mut rpc, &payer, "TestMarket", 500).await.unwrap();
+    // Listing works again while purchases stay paused
+    let buyer = setup_purchase(
+        &mut rpc,
+        &mut test_indexer,
+        &payer,
+        marketplace_key,
+        address,
+    )
+    .await;
+    let result = purchase_listing(
+        &mut rpc,
+        &mut test_indexer,
+        &buyer,
+        marketplace_key,
+        address,
+        "listing1",
+        1,
+    )
+    .await;
+    assert_rpc_error(result, 0, ErrorCode::PurchasesPaused.into()).unwrap();
 
+    set_paused(
+        &mut rpc,
+        &guardian,
+        marketplace_key,
+        PAUSE_REGISTRATION | PAUSE_WITHDRAWALS,
+        true,
+    )
+    .await
+    .unwrap();
     let result = register_device(
         &mut rpc,
         &mut test_indexer,
         &env,
         &payer,
         marketplace_key,
-        "", // Empty device ID
-        100,
-        1000,
-    ).await;
+        padded_device_id("device2"),
+    )
+    .await;
+    assert_rpc_error(result, 0, ErrorCode::RegistrationPaused.into()).unwrap();
+    let destination =
+        fund_token_account(&mut rpc, &payer, marketplace_key, &payer.pubkey(), 0).await;
+    let result = withdraw_treasury(&mut rpc, &payer, marketplace_key, destination, None).await;
+    assert_rpc_error(result, 0, ErrorCode::WithdrawalsPaused.into()).unwrap();
+}
 
-    assert!(result.is_err());
-    if let TransactionError::InstructionError(_, InstructionError::Custom(code)) = result.err().unwrap() {
-        assert_eq!(code, ErrorCode::DeviceIdEmpty as u32); // Error code 1
-    } else {
-        panic!("Expected DeviceIdEmpty error");
+#[tokio::test]
+async fn test_pause_rejections() {
+    let (mut rpc, _, _, payer) = setup().await;
+    let marketplace_key = initialize_marketplace(&mut rpc, &payer, "TestMarket", 500)
+        .await
+        .unwrap();
+    let stranger = funded_keypair(&mut rpc).await;
+
+    let result = set_paused(&mut rpc, &stranger, marketplace_key, PAUSE_LISTING, true).await;
+    assert_rpc_error(result, 0, ErrorCode::NotAdminOrGuardian.into()).unwrap();
+    for flags in [0, 1 << 4] {
+        let result = set_paused(&mut rpc, &payer, marketplace_key, flags, true).await;
+        assert_rpc_error(result, 0, ErrorCode::InvalidPauseFlags.into()).unwrap();
+        let result = set_paused(&mut rpc, &payer, marketplace_key, flags, false).await;
+        assert_rpc_error(result, 0, ErrorCode::InvalidPauseFlags.into()).unwrap();
     }
+    let result = set_guardian(
+        &mut rpc,
+        &stranger,
+        marketplace_key,
+        Some(stranger.pubkey()),
+    )
+    .await;
+    assert_rpc_error(result, 0, ErrorCode::AdminMismatch.into()).unwrap();
 }
 
 #[tokio::test]
-async fn test_register_device_invalid_price() {
-    let (mut rpc, env) = setup_test_programs_with_accounts_v2(Some(vec![(
-        String::from("chainsensor"),
-        PROGRAM_ID,
-    )])).await;
-    let payer = rpc.get_payer().insecure_clone();
-    let mut test_indexer = TestIndexer::new(
-        env.state_merkle_tree_pubkey,
-        env.state_queue_pubkey,
-        env.address_merkle_tree_pubkey,
-        env.address_queue_pubkey,
-        None,
-        rpc.clone(),
+async fn test_mint_device_certificate() {
+    let (_, test_indexer, _, address, certificate_tree) = setup_with_certified_device().await;
+
+    let device = current_device(&test_indexer, address);
+    assert_eq!(device.certificate, get_asset_id(&certificate_tree.tree, 0));
+}
+
+#[tokio::test]
+async fn test_mint_device_certificate_not_owner() {
+    let (mut rpc, mut test_indexer, _, _, _, address) = setup_with_device().await;
+
+    let stranger = funded_keypair(&mut rpc).await;
+    let mut certificate_tree = CertificateTree {
+        tree: Pubkey::new_unique(),
+        leaves: Vec::new(),
+    };
+    let result = mint_device_certificate(
+        &mut rpc,
+        &mut test_indexer,
+        &stranger,
+        address,
+        &mut certificate_tree,
+        "https://chainsensors.io/devices/",
     )
     .await;
+    assert_rpc_error(result, 0, ErrorCode::DeviceOwnerMismatch.into()).unwrap();
+}
 
-    let marketplace_key = initialize_marketplace(&mut rpc, &payer, "TestMarket", 500).await.unwrap();
+#[tokio::test]
+async fn test_claim_device() {
+    let (mut rpc, mut test_indexer, payer, address, mut certificate_tree) =
+        setup_with_certified_device().await;
 
-    let result = register_device(
+    let buyer = funded_keypair(&mut rpc).await;
+    transfer_certificate(&mut rpc, &mut certificate_tree, &payer, 0, buyer.pubkey()).await;
+    claim_device(
         &mut rpc,
         &mut test_indexer,
-        &env,
-        &payer,
-        marketplace_key,
-        "device1",
-        0, // Invalid price
-        1000,
-    ).await;
+        &buyer,
+        address,
+        &certificate_tree,
+        0,
+    )
+    .await
+    .unwrap();
+    assert_eq!(current_device(&test_indexer, address).owner, buyer.pubkey());
+}
+
+#[tokio::test]
+async fn test_claim_device_not_holder() {
+    let (mut rpc, mut test_indexer, payer, address, certificate_tree) =
+        setup_with_certified_device().await;
 
+    // The payer still holds the certificate, so the leaf proof for the stranger fails
+    let stranger = funded_keypair(&mut rpc).await;
+    let result = claim_device(
+        &mut rpc,
+        &mut test_indexer,
+        &stranger,
+        address,
+        &certificate_tree,
+        0,
+    )
+    .await;
     assert!(result.is_err());
-    if let TransactionError::InstructionError(_, InstructionError::Custom(code)) = result.err().unwrap() {
-        assert_eq!(code, ErrorCode::InvalidPrice as u32); // Error code 7
-    } else {
-        panic!("Expected InvalidPrice error");
-    }
+    assert_eq!(current_device(&test_indexer, address).owner, payer.pubkey());
 }
 
 #[tokio::test]
-async fn test_register_device_invalid_total_data_units() {
-    let (mut rpc, env) = setup_test_programs_with_accounts_v2(Some(vec![(
-        String::from("chainsensor"),
-        PROGRAM_ID,
-    )])).await;
-    let payer = rpc.get_payer().insecure_clone();
-    let mut test_indexer = TestIndexer::new(
-        env.state_merkle_tree_pubkey,
-        env.state_queue_pubkey,
-        env.address_merkle_tree_pubkey,
-        env.address_queue_pubkey,
-        None,
-        rpc.clone(),
+async fn test_claim_uncertified_device() {
+    let (mut rpc, mut test_indexer, _, _, _, address) = setup_with_device().await;
+
+    let certificate_tree = CertificateTree {
+        tree: Pubkey::new_unique(),
+        leaves: vec![CertificateLeaf {
+            owner: Pubkey::new_unique(),
+            data_hash: [0u8; 32],
+            creator_hash: [0u8; 32],
+        }],
+    };
+    let holder = funded_keypair(&mut rpc).await;
+    let result = claim_device(
+        &mut rpc,
+        &mut test_indexer,
+        &holder,
+        address,
+        &certificate_tree,
+        0,
     )
     .await;
+    assert_rpc_error(result, 0, ErrorCode::CertificateNotMinted.into()).unwrap();
+}
 
-    let marketplace_key = initialize_marketplace(&mut rpc, &payer, "TestMarket", 500).await.unwrap();
+#[tokio::test]
+async fn test_certified_device_requires_certificate_proof() {
+    let (mut rpc, mut test_indexer, payer, address, _) = setup_with_certified_device().await;
 
-    let result = register_device(
+    // Owner actions without a holder proof are rejected
+    let result = update_device(
+        &mut rpc,
+        &mut test_indexer,
+        &payer,
+        address,
+        padded_device_id("air-quality"),
+    )
+    .await;
+    assert_rpc_error(result, 0, ErrorCode::CertificateProofRequired.into()).unwrap();
+
+    // and the device only changes hands through its certificate
+    let new_owner = Pubkey::new_unique();
+    let result = transfer_device(&mut rpc, &mut test_indexer, &payer, address, new_owner).await;
+    assert_rpc_error(result, 0, ErrorCode::DeviceCertified.into()).unwrap();
+}
+
+#[tokio::test]
+async fn test_purchase_certified_device_requires_certificate_proof() {
+    let (mut rpc, mut test_indexer, env, payer) = setup_with_programs(vec![
+        (String::from("mpl_bubblegum"), mpl_bubblegum::ID),
+        (
+            String::from("spl_account_compression"),
+            SPL_ACCOUNT_COMPRESSION_ID,
+        ),
+    ])
+    .await;
+    let marketplace_key = initialize_marketplace(&mut rpc, &payer, "TestMarket", 500)
+        .await
+        .unwrap();
+    let address = register_device(
         &mut rpc,
         &mut test_indexer,
         &env,
         &payer,
         marketplace_key,
-        "device1",
-        100,
-        0, // Invalid total_data_units
-    ).await;
+        padded_device_id("device1"),
+    )
+    .await
+    .unwrap();
+    let buyer = setup_purchase(
+        &mut rpc,
+        &mut test_indexer,
+        &payer,
+        marketplace_key,
+        address,
+    )
+    .await;
 
-    assert!(result.is_err());
-    if let TransactionError::InstructionError(_, InstructionError::Custom(code)) = result.err().unwrap() {
-        assert_eq!(code, ErrorCode::InvalidTotalDataUnits as u32); // Assuming error code 8, adjust if different
-    } else {
-        panic!("Expected InvalidTotalDataUnits error");
-    }
+    // Certifying the device after listing it makes purchases prove the holder
+    let mut certificate_tree = create_certificate_tree(&mut rpc, &payer).await;
+    mint_device_certificate(
+        &mut rpc,
+        &mut test_indexer,
+        &payer,
+        address,
+        &mut certificate_tree,
+        "https://chainsensors.io/devices/",
+    )
+    .await
+    .unwrap();
+    let result = purchase_listing(
+        &mut rpc,
+        &mut test_indexer,
+        &buyer,
+        marketplace_key,
+        address,
+        "listing1",
+        1,
+    )
+    .await;
+    assert_rpc_error(result, 0, ErrorCode::CertificateProofRequired.into()).unwrap();
 }
 
 // #[tokio::test]